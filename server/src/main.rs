@@ -10,7 +10,7 @@ use Request = rwebserve::rwebserve::Request;
 use Response = rwebserve::rwebserve::Response;
 use ResponseHandler = rwebserve::rwebserve::ResponseHandler;
 
-type Options = {root: Path, admin: bool};
+type Options = {root: Path, admin: bool, admin_port: u16};
 
 // str constants aren't supported yet.
 // TODO: get this (somehow) from the link attribute in the rc file (going the other way
@@ -25,7 +25,8 @@ fn print_usage()
 	io::println(fmt!("server %s - sample rwebserve server", get_version()));
 	io::println(~"");
 	io::println(~"./server [options] --root=<dir>");
-	io::println(~"--admin      allows web clients to shut the server down");
+	io::println(~"--admin      enables the admin endpoints (health checks, diagnostics, and drain) at /admin");
+	io::println(~"--admin-port=PORT serves the admin endpoints on a separate port (implies --admin)");
 	io::println(~"-h, --help   prints this message and exits");
 	io::println(~"--root=DIR   path to the directory containing html files");
 	io::println(~"--version    prints the server version number and exits");
@@ -35,6 +36,7 @@ fn parse_command_line(args: &[~str]) -> Options
 {
 	let opts = ~[
 		optflag(~"admin"),
+		optopt(~"admin-port"),
 		reqopt(~"root"),
 		optflag(~"h"),
 		optflag(~"help"),
@@ -72,7 +74,22 @@ fn parse_command_line(args: &[~str]) -> Options
 		io::stderr().write_line("Positional arguments are not allowed.");
 		libc::exit(1_i32);
 	}
-	{root: path::from_str(opt_str(matched, ~"root")), admin: opt_present(matched, ~"admin")}
+	let admin_port = match opt_maybe_str(matched, ~"admin-port")
+	{
+		option::Some(ref text) =>
+		{
+			match uint::from_str(*text)
+			{
+				option::Some(port) if port > 0u && port < 65536u => port as u16,
+				_ => {io::stderr().write_line(fmt!("'%s' is not a valid port.", *text)); libc::exit(1_i32)}
+			}
+		}
+		option::None =>
+		{
+			0_u16
+		}
+	};
+	{root: path::from_str(opt_str(matched, ~"root")), admin: opt_present(matched, ~"admin") || admin_port > 0_u16, admin_port: admin_port}
 }
 
 fn validate_options(options: Options)
@@ -241,7 +258,15 @@ fn main()
 	// has problems with type inference woth closures and borrowed pointers.
 	let greeting_v: ResponseHandler = greeting_view;
 	let home_v: ResponseHandler = |config: &ConnConfig, request: &Request, response: &Response, copy options| {home_view(config, &options, request, response)};
-	let shutdown_v: ResponseHandler = |_config: &ConnConfig, _request: &Request, _response: &Response| {info!("received shutdown request"); libc::exit(0)};
+	
+	// The admin endpoints are mounted at /admin unless they were given their own port. Once
+	// a client has asked the server to drain we exit when the in-flight requests finish.
+	let admin = if options.admin {option::Some(server::start_admin(|| {info!("exiting after drain"); libc::exit(0)}))} else {option::None};
+	let groups = match admin
+	{
+		option::Some(chan) if options.admin_port == 0_u16 => ~[server::admin_group(chan, ~"admin", ~"/admin")],
+		_ => ~[],
+	};
 	
 	let config = server::Config
 	{
@@ -251,19 +276,31 @@ fn main()
 		resources_root: options.root,
 		routes: ~[
 			(~"GET", ~"/", ~"home"),
-			(~"GET", ~"/shutdown", ~"shutdown"),		// TODO: enable this via debug cfg (or maybe via a command line option)
 			(~"GET", ~"/hello/{name}", ~"greeting"),
 		],
 		views: ~[
 			(~"greeting", greeting_v),
 			(~"home",  home_v),
-			(~"shutdown",  shutdown_v),
 		],
+		groups: groups,
 		sse: ~[(~"/uptime", up)],
+		admin: admin,
 		settings: ~[(~"debug",  ~"true")],
 		..server::initialize_config()
 	};
 	
+	match admin
+	{
+		option::Some(chan) if options.admin_port > 0_u16 =>
+		{
+			let admin_config = server::admin_config(&config, chan, options.admin_port);
+			do task::spawn_sched(task::SingleThreaded) |move admin_config| {server::start(&admin_config)};
+		}
+		_ =>
+		{
+		}
+	}
+	
 	server::start(&config);
 	info!("exiting sample server");		// usually don't land here
 }
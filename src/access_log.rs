@@ -0,0 +1,370 @@
+//! Optional access log recording a line for each request.
+//
+// Like the response cache the log is a task shared by all connections: process_request sends
+// it a sendable LogEntry once the response has been built and the task formats and writes it.
+// When the file grows past max_bytes it is renamed to path.1 (path.1 to path.2, etc) and a
+// new file is started.
+use io::WriterUtil;
+
+/// Formats supported by the access log.
+///
+/// * CommonFormat: the NCSA Common Log Format, e.g.
+/// 10.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET /index.html HTTP/1.1" 200 2326
+/// * CombinedFormat: Common Log Format plus the quoted referer and user agent followed by the
+/// number of microseconds it took to handle the request.
+/// * JsonFormat: one JSON object per line with all of the LogEntry fields (including the request id).
+pub enum LogFormat
+{
+	CommonFormat,
+	CombinedFormat,
+	JsonFormat,
+}
+
+/// Settings for start_access_log.
+///
+/// * path: the file to append to. It will be created if it does not exist.
+/// * format: how entries are formatted.
+/// * max_bytes: the file is rotated once it grows larger than this (0 to never rotate).
+/// * max_files: the number of rotated files to keep.
+pub struct LogSettings
+{
+	pub path: Path,
+	pub format: LogFormat,
+	pub max_bytes: uint,
+	pub max_files: uint,
+}
+
+/// Returns settings which use CombinedFormat, rotate at 10 MB, and keep five rotated files.
+pub fn default_log_settings(path: &Path) -> LogSettings
+{
+	LogSettings {path: copy *path, format: CombinedFormat, max_bytes: 10u*1024u*1024u, max_files: 5u}
+}
+
+/// Information about a request and the server's response.
+pub struct LogEntry
+{
+	pub remote_addr: ~str,		// client address (see client_addr)
+	pub time: i64,				// seconds since the epoch
+	pub method: ~str,
+	pub url: ~str,				// as sent by the client (includes the query string)
+	pub version: ~str,			// e.g. "1.1"
+	pub status: uint,
+	pub bytes: uint,			// size of the response body
+	pub duration: u64,			// microseconds
+	pub referer: ~str,
+	pub user_agent: ~str,
+	pub request_id: ~str,		// see Request.id
+}
+
+/// Messages handled by the log task.
+pub enum LogMesg
+{
+	EntryMesg(LogEntry),
+	RotateMesg,					// rotate now (e.g. in response to a signal or an admin request)
+	ExitMesg(comm::Chan<()>),	// replies once pending entries have been written
+}
+
+/// Used to communicate with the log task. Set Config.access_log to enable logging.
+pub type LogChan = comm::Chan<LogMesg>;
+
+/// Starts up a new log task.
+pub fn start_access_log(settings: LogSettings) -> LogChan
+{
+	do task::spawn_listener
+	|port: comm::Port<LogMesg>, move settings|
+	{
+		run_log(&settings, port);
+	}
+}
+
+/// Closes the current file and starts a new one.
+pub fn rotate_log(log: LogChan)
+{
+	comm::send(log, RotateMesg);
+}
+
+/// Stops the log task. This returns after the entries sent before the call have been written.
+pub fn stop_log(log: LogChan)
+{
+	let port = comm::Port();
+	comm::send(log, ExitMesg(comm::Chan(&port)));
+	comm::recv(port);
+}
+
+/// Returns the address of the client. If the connection is from one of the trusted_proxies
+/// then the X-Forwarded-For header is used: entries are examined from right to left (the
+/// rightmost was added by the proxy nearest the server) and the first untrusted address is
+/// returned. Addresses added by untrusted proxies are ignored because clients can forge them.
+pub fn client_addr(trusted_proxies: &[~str], remote_addr: &str, forwarded_for: Option<~str>) -> ~str
+{
+	let mut addr = remote_addr.to_unique();
+	match forwarded_for
+	{
+		option::Some(ref header) if vec::contains(trusted_proxies, &addr) =>
+		{
+			let hops = vec::map(str::split_char(*header, ','), |h| {str::trim(*h)});
+			for vec::rev_each(hops)
+			|hop|
+			{
+				if str::is_empty(*hop)
+				{
+					break;
+				}
+				addr = copy *hop;
+				if !vec::contains(trusted_proxies, hop)
+				{
+					break;
+				}
+			}
+		}
+		_ =>
+		{
+		}
+	}
+	addr
+}
+
+/// Returns entry formatted as a line of text (without the end of line).
+pub fn format_entry(format: LogFormat, entry: &LogEntry) -> ~str
+{
+	let bytes = if entry.bytes > 0u {fmt!("%u", entry.bytes)} else {~"-"};
+	let common = fmt!("%s - - [%s] \"%s %s HTTP/%s\" %u %s", entry.remote_addr, http_date::format_log_date(entry.time),
+		quote(entry.method), quote(entry.url), quote(entry.version), entry.status, bytes);
+	match format
+	{
+		CommonFormat =>
+		{
+			common
+		}
+		CombinedFormat =>
+		{
+			fmt!("%s \"%s\" \"%s\" %u", common, quote(or_dash(entry.referer)), quote(or_dash(entry.user_agent)), entry.duration as uint)
+		}
+		JsonFormat =>
+		{
+			json::Object(~[
+				(~"remote_addr", json::String(copy entry.remote_addr)),
+				(~"time", json::String(http_date::format_http_date(entry.time))),
+				(~"method", json::String(copy entry.method)),
+				(~"url", json::String(copy entry.url)),
+				(~"version", json::String(copy entry.version)),
+				(~"status", json::Number(entry.status as float)),
+				(~"bytes", json::Number(entry.bytes as float)),
+				(~"duration_us", json::Number(entry.duration as float)),
+				(~"referer", json::String(copy entry.referer)),
+				(~"user_agent", json::String(copy entry.user_agent)),
+				(~"request_id", json::String(copy entry.request_id)),
+			]).to_str()
+		}
+	}
+}
+
+// ---- Functions used by process_request ---------------------------------------
+/// Sends an entry for the request to config.access_log (if it is set).
+pub fn log_request(config: &connection::ConnConfig, url: &str, request: &Request, response: &Response, bytes: uint, start_ns: u64)
+{
+	match config.access_log
+	{
+		option::Some(log) =>
+		{
+			let entry = LogEntry {
+				remote_addr: client_addr(config.trusted_proxies, request.remote_addr, request.headers.find(~"x-forwarded-for")),
+				time: std::time::get_time().sec,
+				method: copy request.method,
+				url: url.to_unique(),
+				version: copy request.version,
+				status: response.status.code(),
+				bytes: bytes,
+				duration: (std::time::precise_time_ns() - start_ns)/1000u64,
+				referer: request.headers.find(~"referer").get_default(~""),
+				user_agent: request.headers.find(~"user-agent").get_default(~""),
+				request_id: copy request.id,
+			};
+			comm::send(log, EntryMesg(entry));
+		}
+		option::None =>
+		{
+		}
+	}
+}
+
+// ---- Internal Items ----------------------------------------------------------
+priv fn run_log(settings: &LogSettings, port: comm::Port<LogMesg>)
+{
+	let mut writer = open_log(settings);
+	let mut size = file_size(&settings.path);
+	loop
+	{
+		match comm::recv(port)
+		{
+			EntryMesg(entry) =>
+			{
+				match writer
+				{
+					option::Some(w) =>
+					{
+						let line = format_entry(settings.format, &entry);
+						w.write_line(line);
+						w.flush();
+
+						size += str::len(line) + 1u;
+						if settings.max_bytes > 0u && size > settings.max_bytes
+						{
+							rotate_files(settings);
+							writer = open_log(settings);
+							size = 0u;
+						}
+					}
+					option::None =>
+					{
+					}
+				}
+			}
+			RotateMesg =>
+			{
+				rotate_files(settings);
+				writer = open_log(settings);
+				size = 0u;
+			}
+			ExitMesg(reply) =>
+			{
+				comm::send(reply, ());
+				break;
+			}
+		}
+	}
+}
+
+priv fn open_log(settings: &LogSettings) -> Option<io::Writer>
+{
+	match io::file_writer(&settings.path, ~[io::Append, io::Create])
+	{
+		result::Ok(writer) =>
+		{
+			option::Some(writer)
+		}
+		result::Err(ref mesg) =>
+		{
+			error!("couldn't open access log %s: %s", settings.path.to_str(), *mesg);
+			option::None
+		}
+	}
+}
+
+priv fn file_size(path: &Path) -> uint unsafe
+{
+	let buffer = vec::from_elem(sys::size_of::<libc::types::os::arch::posix01::stat>(), 0u8);
+	let info: *mut libc::types::os::arch::posix01::stat = cast::reinterpret_cast(&vec::raw::to_ptr(buffer));
+	let result = do str::as_c_str(path.to_str()) |cpath| {libc::funcs::posix88::stat_::stat(cpath, info)};
+	if result == 0 as libc::c_int {(*info).st_size as uint} else {0u}
+}
+
+// Renames path.(n-1) to path.n, ..., path to path.1 (discarding the oldest file).
+priv fn rotate_files(settings: &LogSettings)
+{
+	if settings.max_files == 0u
+	{
+		os::remove_file(&settings.path);
+		return;
+	}
+
+	let numbered = |n: uint| {path::from_str(fmt!("%s.%u", settings.path.to_str(), n))};
+	os::remove_file(&numbered(settings.max_files));
+	let mut n = settings.max_files;
+	while n > 1u
+	{
+		if os::path_exists(&numbered(n - 1u))
+		{
+			os::rename_file(&numbered(n - 1u), &numbered(n));
+		}
+		n -= 1u;
+	}
+	os::rename_file(&settings.path, &numbered(1u));
+}
+
+// Like Apache control characters and non-ASCII bytes are escaped so that clients can't forge
+// log lines (e.g. by sending a User-Agent with a new line).
+priv fn quote(text: &str) -> ~str
+{
+	let mut result = ~"";
+	for str::each(text)
+	|b|
+	{
+		if b == '\\' as u8 || b == '"' as u8
+		{
+			str::push_char(&mut result, '\\');
+			str::push_char(&mut result, b as char);
+		}
+		else if b < 0x20u8 || b >= 0x7Fu8
+		{
+			str::push_str(&mut result, fmt!("\\x%02x", b as uint));
+		}
+		else
+		{
+			str::push_char(&mut result, b as char);
+		}
+	}
+	result
+}
+
+priv fn or_dash(text: &str) -> ~str
+{
+	if str::is_empty(text) {~"-"} else {text.to_unique()}
+}
+
+#[cfg(test)]
+fn test_entry() -> LogEntry
+{
+	LogEntry {remote_addr: ~"10.0.0.1", time: 784111777i64, method: ~"GET", url: ~"/search?q=\"x\"", version: ~"1.1",
+		status: 200u, bytes: 2326u, duration: 150u64, referer: ~"", user_agent: ~"Mozilla/5.0", request_id: ~"abc-1"}
+}
+
+#[test]
+fn log_formats()
+{
+	let entry = test_entry();
+	assert utils::check_strs(format_entry(CommonFormat, &entry), ~"10.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /search?q=\\\"x\\\" HTTP/1.1\" 200 2326");
+	assert utils::check_strs(format_entry(CombinedFormat, &entry), ~"10.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /search?q=\\\"x\\\" HTTP/1.1\" 200 2326 \"-\" \"Mozilla/5.0\" 150");
+
+	let line = format_entry(JsonFormat, &entry);
+	let value = result::unwrap(json::parse_json(line));
+	assert value.find(~"remote_addr").get().as_str() == option::Some(~"10.0.0.1");
+	assert value.find(~"status").get().as_float() == option::Some(200.0);
+	assert value.find(~"url").get().as_str() == option::Some(~"/search?q=\"x\"");
+	assert value.find(~"request_id").get().as_str() == option::Some(~"abc-1");
+}
+
+#[test]
+fn quoted_fields()
+{
+	let entry = LogEntry {url: ~"/a\\b\r\n1.2.3.4 - - \u00e9", user_agent: ~"x\ty\x7f", ..test_entry()};
+	assert utils::check_strs(format_entry(CombinedFormat, &entry),
+		~"10.0.0.1 - - [06/Nov/1994:08:49:37 +0000] \"GET /a\\\\b\\x0d\\x0a1.2.3.4 - - \\xc3\\xa9 HTTP/1.1\" 200 2326 \"-\" \"x\\x09y\\x7f\" 150");
+}
+
+#[test]
+fn forwarded_addresses()
+{
+	let proxies = ~[~"10.0.0.1", ~"10.0.0.2"];
+	assert client_addr(proxies, ~"1.2.3.4", option::Some(~"5.6.7.8")) == ~"1.2.3.4";
+	assert client_addr(proxies, ~"10.0.0.1", option::None) == ~"10.0.0.1";
+	assert client_addr(proxies, ~"10.0.0.1", option::Some(~"5.6.7.8")) == ~"5.6.7.8";
+	assert client_addr(proxies, ~"10.0.0.1", option::Some(~"9.9.9.9, 5.6.7.8, 10.0.0.2")) == ~"5.6.7.8";
+	assert client_addr(proxies, ~"10.0.0.1", option::Some(~"10.0.0.2")) == ~"10.0.0.2";
+}
+
+#[test]
+fn rotated_log()
+{
+	let path = path::from_str(~"/tmp/rwebserve-access-test.log");
+	for uint::range(0u, 4u) |n| {os::remove_file(&path::from_str(fmt!("%s.%u", path.to_str(), n)))};
+	os::remove_file(&path);
+
+	let log = start_access_log(LogSettings {format: CommonFormat, max_bytes: 100u, max_files: 2u, ..default_log_settings(&path)});
+	for uint::range(0u, 5u) |_i| {comm::send(log, EntryMesg(test_entry()))};
+	stop_log(log);
+
+	assert os::path_exists(&path::from_str(fmt!("%s.1", path.to_str())));
+	assert os::path_exists(&path::from_str(fmt!("%s.2", path.to_str())));
+	assert !os::path_exists(&path::from_str(fmt!("%s.3", path.to_str())));
+}
@@ -0,0 +1,523 @@
+//! Optional admin sub-application with health checks, diagnostics, and graceful drain.
+//
+// Like the metrics the admin state is kept by a task shared by all connections. Connections
+// register with it when they are opened and report the requests they service and the sse
+// streams they open so that the admin views can list them. While the server is draining new
+// requests are refused with a 503 and on_drained is called once the in-flight requests finish.
+
+/// A route as it was configured (see Config.routes and Config.groups).
+pub struct RouteInfo
+{
+	pub method: ~str,
+	pub template: ~str,
+	pub route: ~str,
+	pub has_view: bool,
+}
+
+/// A client connection.
+///
+/// * id: assigned when the connection is opened.
+/// * remote_addr: ip address of the client (or proxy).
+/// * local_addr: ip address of the server.
+/// * opened: time the connection was opened (seconds since the epoch).
+/// * requests: number of requests received on the connection.
+/// * current: method and path of the request being serviced (empty if the connection is idle).
+/// * sse_streams: paths of the sse streams opened by the connection.
+pub struct ConnectionInfo
+{
+	pub id: uint,
+	pub remote_addr: ~str,
+	pub local_addr: ~str,
+	pub opened: i64,
+	pub requests: uint,
+	pub current: ~str,
+	pub sse_streams: ~[~str],
+}
+
+/// Returned by admin_state.
+pub struct AdminState
+{
+	pub ready: bool,
+	pub draining: bool,
+	pub console_logging: bool,
+	pub routes: ~[RouteInfo],
+	pub sse: ~[~str],
+	pub connections: ~[ConnectionInfo],		// sorted by id
+}
+
+/// Messages handled by the admin task. Normally the helper functions below are used instead
+/// of sending these directly.
+pub enum AdminMesg
+{
+	RoutesMesg(~[RouteInfo], ~[~str]),			// routes and sse paths
+	OpenMesg(~str, ~str, comm::Chan<uint>),		// remote and local addresses, replies with the connection id
+	CloseMesg(uint),
+	BeginMesg(uint, ~str, comm::Chan<bool>),	// connection id and request, replies false if the server is draining
+	EndMesg(uint),
+	StreamMesg(uint, ~str),						// connection id and path of a new sse stream
+	ReadyMesg(bool),
+	LoggingMesg(bool),
+	DrainMesg(comm::Chan<uint>),				// replies with the number of requests in flight
+	StateMesg(comm::Chan<AdminState>),
+	ExitMesg,
+}
+
+/// Used to communicate with the admin task. Set Config.admin so that connections are tracked
+/// and mount admin_group (or start a separate listener using admin_config) to expose the views.
+pub type AdminChan = comm::Chan<AdminMesg>;
+
+/// Starts up a new admin task. on_drained is called once the server is draining and there are
+/// no requests in flight (typically it exits the process).
+pub fn start_admin(on_drained: fn~ ()) -> AdminChan
+{
+	do task::spawn_listener
+	|port: comm::Port<AdminMesg>, move on_drained|
+	{
+		run_admin(port, on_drained);
+	}
+}
+
+/// Returns the current state of the server.
+pub fn admin_state(admin: AdminChan) -> AdminState
+{
+	let port = comm::Port();
+	comm::send(admin, StateMesg(comm::Chan(&port)));
+	comm::recv(port)
+}
+
+/// Sets the result of the readiness check. Servers start out ready but applications may want
+/// to report that they are not ready until e.g. a database connection has been established.
+pub fn set_ready(admin: AdminChan, ready: bool)
+{
+	comm::send(admin, ReadyMesg(ready));
+}
+
+/// Starts a graceful drain: the readiness check starts failing, new requests are refused with
+/// 503 Service Unavailable, and on_drained is called once the in-flight requests have finished.
+/// Returns the number of requests in flight.
+pub fn drain(admin: AdminChan) -> uint
+{
+	let port = comm::Port();
+	comm::send(admin, DrainMesg(comm::Chan(&port)));
+	comm::recv(port)
+}
+
+/// Stops the admin task.
+pub fn stop_admin(admin: AdminChan)
+{
+	comm::send(admin, ExitMesg);
+}
+
+/// Returns a group with the admin views which can be mounted using Config.groups. The routes
+/// use application/json so clients need to send an Accept header which includes it.
+///
+/// * GET prefix/health: liveness check, always 200.
+/// * GET prefix/ready: readiness check, 200 if the server is ready and 503 if not (or draining).
+/// * GET prefix/routes: the configured routes and sse paths.
+/// * GET prefix/connections: the open connections along with their requests and sse streams.
+/// * PUT prefix/logging?console=on|off: turns console logging on or off. Note that Rust fixes
+/// the per-module log levels (via RUST_LOG) at startup so they can't be changed here.
+/// * POST prefix/drain: starts a graceful drain (see drain).
+///
+/// Note that when the group is mounted on the main listener requests for it are refused while
+/// draining like any other request. Use admin_config to watch a drain from a separate listener.
+pub fn admin_group(admin: AdminChan, name: &str, prefix: &str) -> RouteGroup
+{
+	let health_v: ResponseHandler = |_config: &connection::ConnConfig, _request: &Request, response: &Response| {health_view(response)};
+	let ready_v: ResponseHandler = |_config: &connection::ConnConfig, _request: &Request, response: &Response, copy admin| {ready_view(admin, response)};
+	let routes_v: ResponseHandler = |_config: &connection::ConnConfig, _request: &Request, response: &Response, copy admin| {routes_view(admin, response)};
+	let connections_v: ResponseHandler = |_config: &connection::ConnConfig, _request: &Request, response: &Response, copy admin| {connections_view(admin, response)};
+	let logging_v: ResponseHandler = |_config: &connection::ConnConfig, request: &Request, response: &Response, copy admin| {logging_view(admin, request, response)};
+	let drain_v: ResponseHandler = |_config: &connection::ConnConfig, _request: &Request, response: &Response, copy admin| {drain_view(admin, response)};
+
+	RouteGroup {
+		routes: ~[
+			(~"GET", ~"/health<application/json>", ~"health"),
+			(~"GET", ~"/ready<application/json>", ~"ready"),
+			(~"GET", ~"/routes<application/json>", ~"routes"),
+			(~"GET", ~"/connections<application/json>", ~"connections"),
+			(~"PUT", ~"/logging{?console}<application/json>", ~"logging"),
+			(~"POST", ~"/drain<application/json>", ~"drain")],
+		views: ~[
+			(~"health", health_v),
+			(~"ready", ready_v),
+			(~"routes", routes_v),
+			(~"connections", connections_v),
+			(~"logging", logging_v),
+			(~"drain", drain_v)],
+		..route_group::route_group(name, prefix)}
+}
+
+/// Returns a config for a separate admin listener on port which serves admin_group at the root.
+/// The other fields are copied from config except that the admin listener's own connections are
+/// not tracked (so it stays available while draining) and responses are not cached.
+pub fn admin_config(config: &Config, admin: AdminChan, port: u16) -> Config
+{
+	Config {
+		port: port,
+		routes: ~[],
+		views: ~[],
+		groups: ~[admin_group(admin, ~"admin", ~"/")],
+		sse: ~[],
+		middleware: ~[],
+		response_cache: option::None,
+		admin: option::None,
+		..copy *config}
+}
+
+// ---- Functions used by the server and connection code ------------------------
+/// Sends the configured routes to config.admin (if it is set).
+pub fn register_routes(config: &Config)
+{
+	match config.admin
+	{
+		option::Some(admin) =>
+		{
+			let (group_routes, group_views, _, _) = route_group::flatten_groups(config.groups);
+			let views = vec::map(config.views + group_views, |v| {v.first()});
+			let routes = do vec::map(config.routes + group_routes)
+			|entry|
+			{
+				match *entry
+				{
+					(ref method, ref template, ref route) => RouteInfo {method: copy *method, template: copy *template, route: copy *route, has_view: vec::contains(views, route)},
+				}
+			};
+			comm::send(admin, RoutesMesg(routes, vec::map(config.sse, |s| {s.first()})));
+		}
+		option::None =>
+		{
+		}
+	}
+}
+
+/// Returns the id of a new connection (0 if config.admin is not set).
+pub fn open_connection(config: &connection::ConnConfig, local_addr: &str, remote_addr: &str) -> uint
+{
+	match config.admin
+	{
+		option::Some(admin) =>
+		{
+			let port = comm::Port();
+			comm::send(admin, OpenMesg(remote_addr.to_unique(), local_addr.to_unique(), comm::Chan(&port)));
+			comm::recv(port)
+		}
+		option::None =>
+		{
+			0u
+		}
+	}
+}
+
+pub fn close_connection(config: &connection::ConnConfig)
+{
+	send_mesg(config, CloseMesg(config.connection_id));
+}
+
+/// Returns false if the server is draining (in which case the request should be refused
+/// using draining_response). Otherwise end_request should be called once the response has
+/// been written.
+pub fn begin_request(config: &connection::ConnConfig, method: &str, url: &str) -> bool
+{
+	match config.admin
+	{
+		option::Some(admin) =>
+		{
+			let port = comm::Port();
+			comm::send(admin, BeginMesg(config.connection_id, fmt!("%s %s", method, url), comm::Chan(&port)));
+			comm::recv(port)
+		}
+		option::None =>
+		{
+			true
+		}
+	}
+}
+
+pub fn end_request(config: &connection::ConnConfig)
+{
+	send_mesg(config, EndMesg(config.connection_id));
+}
+
+pub fn record_stream(config: &connection::ConnConfig, path: &str)
+{
+	send_mesg(config, StreamMesg(config.connection_id, path.to_unique()));
+}
+
+/// Returns the header and body of the response used to refuse requests while draining.
+pub fn draining_response(config: &connection::ConnConfig) -> (~str, Body)
+{
+	let headers = headers::headers_from(~[
+		(~"Connection", ~"close"),
+		(~"Content-Type", ~"text/plain; charset=UTF-8"),
+		(~"Date", config.date_cache.now()),
+		(~"Server", copy config.server_info),
+	]);
+
+	let response = Response {status: status::ServiceUnavailable, headers: headers, body: StringBody(@~""), template: ~"", context: std::map::HashMap(), layout: ~""};
+	request::make_header_and_body(&response, StringBody(@~"The server is shutting down."))
+}
+
+// ---- Internal Items ----------------------------------------------------------
+priv struct Connection
+{
+	id: uint,
+	remote_addr: ~str,
+	local_addr: ~str,
+	opened: i64,
+	mut requests: uint,
+	mut current: ~str,
+	mut sse_streams: ~[~str],
+}
+
+priv fn send_mesg(config: &connection::ConnConfig, mesg: AdminMesg)
+{
+	match config.admin
+	{
+		option::Some(admin) => comm::send(admin, mesg),
+		option::None => {}
+	}
+}
+
+priv fn run_admin(port: comm::Port<AdminMesg>, on_drained: fn~ ())
+{
+	let mut routes = ~[];
+	let mut sse = ~[];
+	let mut connections: ~[@Connection] = ~[];
+	let mut next_id = 1u;
+	let mut ready = true;
+	let mut draining = false;
+	let mut drained = false;
+	let mut console_logging = true;
+
+	loop
+	{
+		match comm::recv(port)
+		{
+			RoutesMesg(move r, move s) =>
+			{
+				routes = r;
+				sse = s;
+			}
+			OpenMesg(move remote_addr, move local_addr, reply) =>
+			{
+				vec::push(&mut connections, @Connection {id: next_id, remote_addr: remote_addr, local_addr: local_addr, opened: std::time::get_time().sec, requests: 0u, current: ~"", sse_streams: ~[]});
+				comm::send(reply, next_id);
+				next_id += 1u;
+			}
+			CloseMesg(id) =>
+			{
+				connections = vec::filter(connections, |c| {c.id != id});
+			}
+			BeginMesg(id, move request, reply) =>
+			{
+				if !draining
+				{
+					for vec::each(connections) |c| {if c.id == id {c.requests += 1u; c.current = copy request;}};
+				}
+				comm::send(reply, !draining);
+			}
+			EndMesg(id) =>
+			{
+				for vec::each(connections) |c| {if c.id == id {c.current = ~"";}};
+			}
+			StreamMesg(id, move path) =>
+			{
+				for vec::each(connections) |c| {if c.id == id {vec::push(&mut c.sse_streams, copy path);}};
+			}
+			ReadyMesg(value) =>
+			{
+				ready = value;
+			}
+			LoggingMesg(enabled) =>
+			{
+				if enabled {logging::console_on()} else {logging::console_off()}
+				console_logging = enabled;
+			}
+			DrainMesg(reply) =>
+			{
+				info!("draining with %u requests in flight", in_flight(connections));
+				draining = true;
+				comm::send(reply, in_flight(connections));
+			}
+			StateMesg(reply) =>
+			{
+				let infos = do vec::map(connections)
+				|c|
+				{
+					ConnectionInfo {id: c.id, remote_addr: copy c.remote_addr, local_addr: copy c.local_addr, opened: c.opened,
+						requests: c.requests, current: copy c.current, sse_streams: copy c.sse_streams}
+				};
+				comm::send(reply, AdminState {ready: ready, draining: draining, console_logging: console_logging,
+					routes: copy routes, sse: copy sse, connections: infos});
+			}
+			ExitMesg =>
+			{
+				break;
+			}
+		}
+
+		if draining && !drained && in_flight(connections) == 0u
+		{
+			info!("drained");
+			drained = true;
+			on_drained();
+		}
+	}
+}
+
+priv fn in_flight(connections: &[@Connection]) -> uint
+{
+	let mut count = 0u;
+	for vec::each(connections) |c| {if str::is_not_empty(c.current) {count += 1u}};
+	count
+}
+
+priv fn health_view(response: &Response) -> Response
+{
+	json::json_response(response, status::OK, &json::Object(~[(~"status", json::String(~"ok"))]))
+}
+
+priv fn ready_view(admin: AdminChan, response: &Response) -> Response
+{
+	let state = admin_state(admin);
+	if state.draining
+	{
+		json::json_response(response, status::ServiceUnavailable, &json::Object(~[(~"status", json::String(~"draining"))]))
+	}
+	else if !state.ready
+	{
+		json::json_response(response, status::ServiceUnavailable, &json::Object(~[(~"status", json::String(~"not ready"))]))
+	}
+	else
+	{
+		json::json_response(response, status::OK, &json::Object(~[(~"status", json::String(~"ready"))]))
+	}
+}
+
+priv fn routes_view(admin: AdminChan, response: &Response) -> Response
+{
+	let state = admin_state(admin);
+	let routes = do vec::map(state.routes)
+	|r|
+	{
+		json::Object(~[
+			(~"method", json::String(copy r.method)),
+			(~"template", json::String(copy r.template)),
+			(~"route", json::String(copy r.route)),
+			(~"has_view", json::Boolean(r.has_view)),
+		])
+	};
+	let sse = vec::map(state.sse, |path| {json::String(copy *path)});
+	json::json_response(response, status::OK, &json::Object(~[(~"routes", json::List(routes)), (~"sse", json::List(sse))]))
+}
+
+priv fn connections_view(admin: AdminChan, response: &Response) -> Response
+{
+	let state = admin_state(admin);
+	let connections = do vec::map(state.connections)
+	|c|
+	{
+		json::Object(~[
+			(~"id", json::Number(c.id as float)),
+			(~"remote_addr", json::String(copy c.remote_addr)),
+			(~"local_addr", json::String(copy c.local_addr)),
+			(~"opened", json::String(http_date::format_http_date(c.opened))),
+			(~"requests", json::Number(c.requests as float)),
+			(~"current", if str::is_empty(c.current) {json::Null} else {json::String(copy c.current)}),
+			(~"sse_streams", json::List(vec::map(c.sse_streams, |path| {json::String(copy *path)}))),
+		])
+	};
+	json::json_response(response, status::OK, &json::Object(~[(~"draining", json::Boolean(state.draining)), (~"connections", json::List(connections))]))
+}
+
+priv fn logging_view(admin: AdminChan, request: &Request, response: &Response) -> Response
+{
+	match request.matches.find(@~"console")
+	{
+		option::Some(value) if *value == ~"on" || *value == ~"off" =>
+		{
+			comm::send(admin, LoggingMesg(*value == ~"on"));
+			json::json_response(response, status::OK, &json::Object(~[(~"console", json::String(copy *value))]))
+		}
+		_ =>
+		{
+			json::json_response(response, status::BadRequest, &json::Object(~[(~"error", json::String(~"expected console=on or console=off"))]))
+		}
+	}
+}
+
+priv fn drain_view(admin: AdminChan, response: &Response) -> Response
+{
+	let count = drain(admin);
+	json::json_response(response, status::Accepted, &json::Object(~[(~"status", json::String(~"draining")), (~"in_flight", json::Number(count as float))]))
+}
+
+#[test]
+fn admin_views()
+{
+	let done_port = comm::Port();
+	let done_chan = comm::Chan(&done_port);
+	let admin = start_admin(|| {comm::send(done_chan, ())});
+
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/", ~"home")],
+		views: ~[(~"home",  missing_view)],
+		groups: ~[admin_group(admin, ~"admin", ~"/admin")],
+		admin: option::Some(admin)
+		, .. initialize_config()};
+	register_routes(&config);
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	iconfig.connection_id = open_connection(&iconfig, ~"1.2.3.4", ~"10.11.12.13");
+
+	let get = |url: &str, method: &str| -> ~str
+	{
+		assert begin_request(&iconfig, method, url);
+		let request = http_parser::HttpRequest {method: method.to_unique(), ..request::make_request(url.to_unique(), ~"application/json")};
+		let (_header, body) = request::process_request(&iconfig, &request, ~"1.2.3.4", ~"10.11.12.13");
+		end_request(&iconfig);
+		body.to_str()
+	};
+
+	assert get(~"/admin/health", ~"GET") == ~"{\"status\":\"ok\"}";
+	assert get(~"/admin/ready", ~"GET") == ~"{\"status\":\"ready\"}";
+
+	let routes = result::unwrap(json::parse_json(get(~"/admin/routes", ~"GET")));
+	match routes.find(~"routes")
+	{
+		option::Some(json::List(ref list)) =>
+		{
+			assert list[0].find(~"route").get().as_str() == option::Some(~"home");
+			assert list[1].find(~"route").get().as_str() == option::Some(~"admin.health");
+			assert list[1].find(~"template").get().as_str() == option::Some(~"/admin/health<application/json>");
+		}
+		_ =>
+		{
+			fail ~"expected a routes list";
+		}
+	}
+
+	let connections = get(~"/admin/connections", ~"GET");
+	assert connections.contains("\"requests\":4");
+	assert connections.contains("\"current\":\"GET /admin/connections\"");
+
+	assert get(~"/admin/logging?console=loud", ~"PUT").contains("error");
+
+	assert get(~"/admin/drain", ~"POST") == ~"{\"status\":\"draining\",\"in_flight\":1}";
+	comm::recv(done_port);
+	assert !begin_request(&iconfig, ~"GET", ~"/");
+
+	let state = admin_state(admin);
+	assert state.draining;
+	assert vec::len(state.connections) == 1u;
+
+	close_connection(&iconfig);
+	assert vec::is_empty(admin_state(admin).connections);
+	stop_admin(admin);
+}
@@ -0,0 +1,168 @@
+//! Declarative Cache-Control policies (see RFC 7234).
+
+/// Selects the responses that a CachePolicy applies to.
+///
+/// * PathPrefix matches request paths starting with the string, e.g. "/assets/".
+/// * MimeType matches the response Content-Type, e.g. "image/png" or "image/*".
+/// * RouteName matches responses from the view for the named route.
+/// * Fingerprinted matches paths whose file name includes a content hash, e.g. "/js/app.3f9a2c1b.js".
+pub enum CacheTarget
+{
+	PathPrefix(~str),
+	MimeType(~str),
+	RouteName(~str),
+	Fingerprinted,
+}
+
+/// Caching headers to add to matching responses.
+///
+/// * target: the responses the policy applies to.
+/// * cache_control: Cache-Control directives, e.g. ~[~"public", ~"max-age=3600"]. If there is a max-age directive
+/// an Expires header is also added for the benefit of HTTP/1.0 caches.
+/// * vary: header names to add to the Vary header, e.g. ~[~"Accept-Encoding"].
+pub struct CachePolicy
+{
+	pub target: CacheTarget,
+	pub cache_control: ~[~str],
+	pub vary: ~[~str],
+}
+
+/// Returns a policy which allows fingerprinted files to be cached (effectively) forever.
+///
+/// Because the name of a fingerprinted file changes whenever its contents change clients
+/// never need to revalidate them.
+pub fn immutable_policy() -> CachePolicy
+{
+	CachePolicy {target: Fingerprinted, cache_control: ~[~"public", ~"max-age=31536000", ~"immutable"], vary: ~[]}
+}
+
+/// Returns a policy which prevents any caching of responses matching target.
+pub fn no_store_policy(target: CacheTarget) -> CachePolicy
+{
+	CachePolicy {target: target, cache_control: ~[~"no-store"], vary: ~[]}
+}
+
+/// Adds caching headers to response using the first policy which matches.
+///
+/// Nothing is done if the response already has a Cache-Control header (e.g. because the view
+/// set one or because debug is enabled) or if the status isn't normally cacheable.
+pub fn apply_cache_policy(policies: &[CachePolicy], request: &Request, response: &Response)
+{
+	let cacheable = ~[status::OK, status::NonAuthoritativeInformation, status::PartialContent, status::NotModified, status::MovedPermanently];
+	if response.headers.contains_key(~"Cache-Control") || !vec::contains(cacheable, &response.status)
+	{
+		return;
+	}
+
+	let mime_type = response.headers.content_type().get_default(~"");
+	match vec::find(policies, |p| {policy_matches(p, request, mime_type)})
+	{
+		option::Some(ref policy) =>
+		{
+			response.headers.set_cache_control(policy.cache_control);
+			match max_age(policy.cache_control)
+			{
+				option::Some(secs) =>
+				{
+					let now = response.headers.date().get_default(std::time::get_time().sec);
+					response.headers.set_expires(now + secs);
+				}
+				option::None if vec::contains(policy.cache_control, &~"no-store") || vec::contains(policy.cache_control, &~"no-cache") =>
+				{
+					response.headers.set_expires(0i64);
+				}
+				option::None =>
+				{
+				}
+			}
+			for vec::each(policy.vary) |name| {response.headers.add_to_list(~"Vary", *name)};
+		}
+		option::None =>
+		{
+		}
+	}
+}
+
+/// Returns true if the last component of path looks like "name.HASH.ext" or "name-HASH.ext"
+/// where HASH is at least 8 hex digits.
+pub fn is_fingerprinted(path: &str) -> bool
+{
+	let name = match str::rfind_char(path, '/') {option::Some(i) => path.slice(i+1, path.len()), option::None => path.to_unique()};
+	let parts = str::split_char(name, '.');
+	if vec::len(parts) < 2u
+	{
+		return false;
+	}
+
+	// Skip the extension and check everything else for a hash.
+	let mut words = ~[];
+	for vec::each(vec::slice(parts, 0u, vec::len(parts) - 1u)) |part| {words += str::split_char(*part, '-')};
+	vec::len(words) > 1u && do vec::any(vec::slice(words, 1u, vec::len(words)))
+	|word|
+	{
+		str::len(*word) >= 8u && str::all(*word, |c| {char::is_digit(c) || (c >= 'a' && c <= 'f') || (c >= 'A' && c <= 'F')})
+	}
+}
+
+priv fn policy_matches(policy: &CachePolicy, request: &Request, mime_type: &str) -> bool
+{
+	match policy.target
+	{
+		PathPrefix(ref prefix) =>
+		{
+			str::starts_with(request.path, *prefix)
+		}
+		MimeType(ref pattern) =>
+		{
+			let pattern = str::to_lower(*pattern);
+			if str::ends_with(pattern, "/*")
+			{
+				str::starts_with(mime_type, pattern.slice(0, pattern.len() - 1))
+			}
+			else
+			{
+				str::eq_slice(pattern, mime_type)
+			}
+		}
+		RouteName(ref name) =>
+		{
+			request.route == *name
+		}
+		Fingerprinted =>
+		{
+			is_fingerprinted(request.path)
+		}
+	}
+}
+
+priv fn max_age(directives: &[~str]) -> Option<i64>
+{
+	for vec::each(directives)
+	|directive|
+	{
+		let directive = str::to_lower(str::trim(*directive));
+		if str::starts_with(directive, "max-age=")
+		{
+			return int::from_str(directive.slice(8, directive.len())).map(|n| {*n as i64});
+		}
+	}
+	option::None
+}
+
+#[test]
+fn fingerprints()
+{
+	assert is_fingerprinted(~"/js/app.3f9a2c1b.js");
+	assert is_fingerprinted(~"/css/site-0123456789abcdef.css");
+	assert !is_fingerprinted(~"/js/app.js");
+	assert !is_fingerprinted(~"/js/deadbeef12.js");
+	assert !is_fingerprinted(~"/js/jquery.min.js");
+	assert !is_fingerprinted(~"/images/background-photograph.png");
+}
+
+#[test]
+fn max_ages()
+{
+	assert max_age(~[~"public", ~"Max-Age=60"]) == option::Some(60i64);
+	assert max_age(~[~"no-cache"]) == option::None;
+}
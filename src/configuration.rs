@@ -9,16 +9,45 @@ use std::base64::*;
 /// * server_info is included in the HTTP response and should include the server name and version.
 /// * resources_root should be a path to where the files associated with URLs are loaded from.
 /// * routes: maps HTTP methods ("GET") and URI templates ("hello/{name}") to route names ("greeting"). 
-///    To support non-text/html types append the template with "<some/type>".
+///    To support non-text/html types append the template with "<some/type>". Templates follow RFC 6570
+///    and variables may be constrained, e.g. "files/{name}.{ext}", "users/{id:int}", or "search{?q,page:uint}".
 /// * views: maps route names to view handler functions.
+/// * groups: route groups mounted beneath a path prefix, e.g. "/api/v1" (see RouteGroup).
 /// * static_handler: used to handle URIs that don't match routes, but are found beneath resources_root.
-/// * is_template: returns true if the path is to a mustache template.
+/// * is_template: returns the template_engines extension for the engine used to render path (None if it isn't a template).
+/// * template_engines: maps extensions (including the period) to functions which create template engines.
+/// * template_store: task which shares loaded templates between connections. server::start sets this
+/// (and loads the templates into it) if it is None.
 /// * sse: maps EventSource path to a function that creates a task to push server-sent events.
 /// * missing: used to handle URIs that don't match routes, and are not found beneath resources_root.
+/// * error_pages: maps status codes to the page used when a response has that status but no body or template
+/// (e.g. from error_pages::error_response). Codes without an entry use a simple built-in page.
+/// * middleware: layers wrapped around the handler for every request (the first layer is the outermost).
 /// * static_types: maps file extensions (including the period) to mime types.
-/// * read_error: html used when a file fails to load. Must include {{request-path}} template.
+/// * read_error: html used when a file fails to load. Must include {{request-path}} template (which will be html escaped).
 /// * load_rsrc: maps a path rooted at resources_root to a resource body.
 /// * valid_rsrc: returns true if a path rooted at resources_root points to a file.
+/// * rsrc_modified: returns the time (in seconds since the epoch) at which a path rooted at resources_root was last modified.
+/// * cache_policies: used to add Cache-Control, Expires, and Vary headers to responses which don't already have
+/// a Cache-Control header. The first matching policy is used.
+/// * response_cache: if set GET responses with explicit freshness information (e.g. Cache-Control: max-age=60) are
+/// cached and used for subsequent matching requests without calling the view. Use response_cache::start_cache to create one.
+/// * access_log: if set a line is logged for each request. Use access_log::start_access_log to create one.
+/// * admin: if set connections, requests, and sse streams are tracked so that they can be listed by the admin
+/// views and the server can be drained. Use admin::start_admin to create one and admin::admin_group to mount the views.
+/// * metrics: if set request counts, latencies, etc are recorded. Use metrics::start_metrics to create one.
+/// * metrics_path: if metrics is set GET requests for this path return the metrics in the Prometheus text format
+/// (empty to disable the endpoint).
+/// * trusted_proxies: addresses of reverse proxies whose X-Forwarded-For headers are used to find the client
+/// address for the access log.
+/// * cookie_secret: key used for cookies::signed_cookie and cookies::encrypted_cookie (empty if those aren't used).
+/// This should be at least 32 random characters and kept private: anyone who knows it can forge cookies.
+/// * languages: language tags (e.g. "en" or "fr-ca") that templates are available in. The first entry is the
+/// default language. If this is not empty templates are localized using the Accept-Language header: e.g. with
+/// a French client "home.html" will be rendered from "home.fr.html" if that file exists.
+/// * layout: path relative to resources_root of the default layout template (empty for no layout). Html
+/// templates are rendered into the layout's {{content}} tag so that site chrome can be defined once.
+/// * global_context: returns entries added to every template context (entries added by the view take precedence).
 /// * settings: arbitrary key/value pairs passed into view handlers. If debug is "true" rwebserve debugging 
 /// code will be enabled (among other things this will default the Cache-Control header to "no-cache").
 /// 
@@ -31,14 +60,31 @@ pub struct Config
 	pub resources_root: Path,
 	pub routes: ~[(~str, ~str, ~str)],					// better to use hashmap, but hashmaps cannot be sent
 	pub views: ~[(~str, ResponseHandler)],
+	pub groups: ~[route_group::RouteGroup],
 	pub static_handler: ResponseHandler,
 	pub is_template: IsTemplateFile,
+	pub template_engines: ~[(~str, template_engine::EngineFactory)],
+	pub template_store: Option<template_cache::TemplateStore>,
 	pub sse: ~[(~str, OpenSse)],
 	pub missing: ResponseHandler,
+	pub error_pages: ~[(uint, error_pages::ErrorPage)],
+	pub middleware: ~[middleware::Middleware],
 	pub static_types: ~[(~str, ~str)],
 	pub read_error: ~str,
 	pub load_rsrc: RsrcLoader,
 	pub valid_rsrc: RsrcExists,
+	pub rsrc_modified: RsrcModified,
+	pub cache_policies: ~[cache_policy::CachePolicy],
+	pub response_cache: Option<response_cache::CacheChan>,
+	pub access_log: Option<access_log::LogChan>,
+	pub admin: Option<admin::AdminChan>,
+	pub metrics: Option<metrics::MetricsChan>,
+	pub metrics_path: ~str,
+	pub trusted_proxies: ~[~str],
+	pub cookie_secret: ~str,
+	pub languages: ~[~str],
+	pub layout: ~str,
+	pub global_context: ContextHook,
 	pub settings: ~[(~str, ~str)],
 }
 
@@ -49,10 +95,16 @@ pub struct Config
 /// * local_addr: ip address of the server.
 /// * remote_addr: ip address of the client (or proxy).
 /// * path: path component of the URL. Note that this does not include the query string.
-/// * matches: contains entries from request_path matching a routes URI template.
+/// * matches: contains entries from request_path (and query expressions) matching a routes URI template.
+/// * route: the name of the route which matched the request (empty if no route matched, e.g. for static files).
 /// * params: contains entries from the query portion of the URL. Note that the keys may be duplicated.
-/// * headers: headers from the http request. Note that the names are lower cased.
+/// * query: the query portion of the URL as sent by the client, i.e. still percent encoded (empty if there was none).
+/// * headers: headers from the http request. Lookups are case-insensitive but note that the names are lower cased.
 /// * body: body of the http request.
+/// * cookies: name/value pairs from the Cookie headers (see cookies::parse_cookies). Note that the names may be duplicated.
+/// * id: identifies the request in log lines and is returned in the X-Request-ID response header. If the
+/// client (or a proxy) sent a valid X-Request-ID header that value is used.
+/// * trace: timing spans recorded while servicing the request. Views may add their own (see tracing::Trace).
 pub struct Request
 {
 	pub version: ~str,
@@ -61,30 +113,43 @@ pub struct Request
 	pub remote_addr: ~str,
 	pub path: ~str,
 	pub matches: HashMap<@~str, @~str>,
+	pub route: ~str,
 	pub params: IMap<@~str, @~str>,
-	pub headers: HashMap<@~str, @~str>,
+	pub query: ~str,
+	pub headers: headers::Headers,
 	pub body: ~str,
+	pub cookies: IMap<@~str, @~str>,
+	pub id: ~str,
+	pub trace: @tracing::Trace,
 	
 	drop {}			// TODO: enable this (was getting a compiler assert earlier)
 }
 
 /// Returned by view functions and used to generate http response messages.
 /// 
-/// * status: the status code and message for the response, defaults to "200 OK".
-/// * headers: the HTTP headers to be included in the response.
+/// * status: the status code for the response, defaults to status::OK.
+/// * headers: the HTTP headers to be included in the response, written in the order they were added.
+/// Use headers.set_cookie to add Set-Cookie headers.
 /// * body: contents the section after headers.
 /// * template: path relative to resources_root containing a template file.
 /// * context: hashmap used when rendering the template file.
+/// * layout: path relative to resources_root of a template which wraps html templates, defaults to config.layout.
+/// Set this to empty to render the template standalone.
+/// 
+/// Templates may include partials using {{> path}} where path is relative to resources_root.
+/// If the Content-Type is html the strings in context are html escaped before rendering: use
+/// html::raw_html for values which contain markup.
 /// 
 /// If template is not empty then body should be empty. If body is not empty then
 /// headers["Content-Type"] should usually be explicitly set.
 pub struct Response
 {
-	pub status: ~str,
-	pub headers: HashMap<@~str, @~str>,
+	pub status: status::Status,
+	pub headers: headers::Headers,
 	pub body: Body,
 	pub template: ~str,				// an URL path is very similar to a path::PosixPath, but that is conditionally compiled in
 	pub context: HashMap<@~str, mustache::Data>,
+	pub layout: ~str,
 	
 	drop {}			// TODO: enable this (was getting a compiler assert earlier)
 }
@@ -126,7 +191,7 @@ pub impl Body : ToStr
 
 /// Function used to generate an HTTP response.
 /// 
-/// On entry reponse.status will typically be set to \"200 OK\". response.headers will include something like the following:
+/// On entry reponse.status will typically be set to status::OK. response.headers will include something like the following:
 /// * Server: whizbang server 1.0
 /// * Content-Length: 0 (if non-zero rwebserve will not compute the body length)
 /// * Content-Type:  text/html; charset=UTF-8
@@ -135,19 +200,23 @@ pub impl Body : ToStr
 /// * status-code: the code that will be included in the response message (e.g. '200' or '404').
 /// * status-mesg: the code that will be included in the response message (e.g. 'OK' or 'Not Found').
 /// * request-version: HTTP version of the request message (e.g. '1.1').
+/// * locale: the language negotiated from config.languages and the Accept-Language header (e.g. 'fr'), if languages is not empty.
 /// 
 /// On exit the response will have:
-/// * status: is normally left unchanged.
+/// * status: is normally left unchanged. In debug mode rwebserve will warn if the status is inconsistent with the
+/// rest of the response (e.g. a 201 without a Location header or a 204 with a body).
 /// * headers: existing headers may be modified and new ones added (e.g. to control caching).
 /// * matches: should not be changed.
 /// * template: should be set to a path relative to resources_root.
 /// * context: new entries will often be added. If template is not actually a template file empty the context.
 /// 
-/// After the function returns a base-path entry is added to the response.context with the url to the directory containing the template file.
+/// After the function returns a base-path entry is added to the response.context with the url to the directory containing the template file
+/// and a urls entry is added mapping route names to URLs (see urls::route_urls). Views can use urls::url_for to generate URLs themselves.
 pub type ResponseHandler = fn~ (config: &connection::ConnConfig, request: &Request, response: &Response) -> Response;
 
-/// Returns true if the file at path should be treated as a mustache template.
-pub type IsTemplateFile = fn~ (config: &connection::ConnConfig, path: &str) -> bool;
+/// Returns the extension of the engine in template_engines which should be used to render the file at path
+/// or None if the file should not be treated as a template.
+pub type IsTemplateFile = fn~ (config: &connection::ConnConfig, path: &str) -> Option<~str>;
 
 /// Maps a path rooted at resources_root to a resource body.
 pub type RsrcLoader = fn~ (path: &Path) -> result::Result<~[u8], ~str>;
@@ -155,6 +224,12 @@ pub type RsrcLoader = fn~ (path: &Path) -> result::Result<~[u8], ~str>;
 /// Returns true if a path rooted at resources_root points to a file.
 pub type RsrcExists = fn~ (path: &Path) -> bool;
 
+/// Returns the time a path rooted at resources_root was last modified (as seconds since the epoch) or None if the time is not known.
+pub type RsrcModified = fn~ (path: &Path) -> Option<i64>;
+
+/// Returns entries to add to the context of every template rendered for request.
+pub type ContextHook = fn~ (config: &connection::ConnConfig, request: &Request) -> ~[(~str, mustache::Data)];
+
 pub struct Route
 {
 	pub method: ~str,
@@ -167,12 +242,29 @@ pub struct Route
 /// 
 /// * port is initialized to 80.
 /// * static_handler is initialized to a reasonable view handler.
-/// * is_template: is initialized to a function that returns true if the file has an extension of text/plain mime type.
-/// * missing is initialized to a view that assume a \"not-found.html\" is at the root.
+/// * is_template: is initialized to a function that uses the engine registered for the file's extension or
+/// mustache if the file's extension is a text mime type.
+/// * template_engines: is initialized to a mustache engine registered as ".mustache".
+/// * template_store: is initialized to None.
+/// * missing is initialized to a view that returns a 404 error_response.
+/// * error_pages: is initialized to use forbidden.html for 403, not-found.html for 404, and not-supported.html
+/// for 505 (the built-in pages are used if these don't exist).
 /// * static_types is given entries for audio, image, video, and text extensions.
 /// * read_error is initialized to a reasonable English language html error message.
 /// * load_rsrc: is initialized to io::read_whole_file_str.
 /// * valid_rsrc: is initialized to os::path_exists && !os::path_is_dir.
+/// * rsrc_modified: is initialized to a function which uses stat.
+/// * cache_policies: is initialized to empty.
+/// * response_cache: is initialized to None.
+/// * access_log: is initialized to None.
+/// * admin: is initialized to None.
+/// * metrics: is initialized to None.
+/// * metrics_path: is initialized to "/metrics".
+/// * trusted_proxies: is initialized to empty.
+/// * cookie_secret: is initialized to empty.
+/// * languages: is initialized to empty (i.e. templates are not localized).
+/// * layout: is initialized to empty (i.e. templates are rendered standalone).
+/// * global_context: is initialized to a function which adds the settings.
 pub fn initialize_config() -> Config
 {
 	Config 
@@ -183,10 +275,18 @@ pub fn initialize_config() -> Config
 		resources_root: path::from_str(~""),
 		routes: ~[],
 		views: ~[],
+		groups: ~[],
 		static_handler: static_view,
 		is_template: is_text_file,
+		template_engines: ~[(~".mustache", template_engine::mustache_engine)],
+		template_store: option::None,
 		sse: ~[],
 		missing: missing_view,
+		error_pages: ~[
+			(403u, error_pages::ErrorTemplate(~"forbidden.html")),
+			(404u, error_pages::ErrorTemplate(~"not-found.html")),
+			(505u, error_pages::ErrorTemplate(~"not-supported.html"))],
+		middleware: ~[],
 		static_types: ~[
 			(~".m4a", ~"audio/mp4"),
 			(~".m4b", ~"audio/mp4"),
@@ -222,6 +322,18 @@ pub fn initialize_config() -> Config
 	<p>Could not read URL {{request-path}}.</p>",
 		load_rsrc: io::read_whole_file,
 		valid_rsrc: is_valid_rsrc,
+		rsrc_modified: file_modified_time,
+		cache_policies: ~[],
+		response_cache: option::None,
+		access_log: option::None,
+		admin: option::None,
+		metrics: option::None,
+		metrics_path: ~"/metrics",
+		trusted_proxies: ~[],
+		cookie_secret: ~"",
+		languages: ~[],
+		layout: ~"",
+		global_context: settings_context,
 		settings: ~[],
 	}
 }
@@ -231,11 +343,33 @@ pub fn is_valid_rsrc(path: &Path) -> bool
 	os::path_exists(path) && !os::path_is_dir(path)
 }
 
-// Default config.missing handler. Assumes that there is a "not-found.html"
-// file at the resource root.
+pub fn file_modified_time(path: &Path) -> Option<i64> unsafe
+{
+	let buffer = vec::from_elem(sys::size_of::<libc::types::os::arch::posix01::stat>(), 0u8);
+	let info: *mut libc::types::os::arch::posix01::stat = cast::reinterpret_cast(&vec::raw::to_ptr(buffer));
+	let result = do str::as_c_str(path.to_str()) |cpath| {libc::funcs::posix88::stat_::stat(cpath, info)};
+	if result == 0 as libc::c_int
+	{
+		option::Some((*info).st_mtime as i64)
+	}
+	else
+	{
+		option::None
+	}
+}
+
+// Default config.global_context hook. Makes the settings available to all templates.
+pub fn settings_context(config: &connection::ConnConfig, _request: &Request) -> ~[(~str, mustache::Data)]
+{
+	let mut entries = ~[];
+	for config.settings.each |key, value| {vec::push(&mut entries, (copy *key, mustache::Str(value)))};
+	entries
+}
+
+// Default config.missing handler. The page comes from config.error_pages.
 pub fn missing_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
 {
-	Response {template: ~"not-found.html", ..*response}
+	error_pages::error_response(response, status::NotFound)
 }
 
 // Default config.static view handler.
@@ -245,17 +379,39 @@ pub fn missing_view(_config: &connection::ConnConfig, _request: &Request, respon
 // 1) It's expected that expanding a non-template file is not going to be a performance problem.
 // 2) Using files like *.html.mustache screws up syntax highlighting in editors.
 // 3) Users can install a new is_template closure to do something different.
-pub fn static_view(config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+pub fn static_view(config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
 {
-	let path = mustache::compile_str("{{request-path}}").render_data(mustache::Map(response.context));
-	//let path = mustache::render_str("{{request-path}}", response.context);
-	if config.is_template(config, path)
+	let path = match response.context.find(@~"request-path") {option::Some(mustache::Str(path)) => copy *path, _ => ~""};
+	let (root, file) = route_group::static_path(config, path);
+	if root.to_str() == config.resources_root.to_str() && config.is_template(config, path).is_some()
 	{
 		Response {body: StringBody(@~""), template: path, context: std::map::HashMap(), ..*response}
 	}
 	else
 	{
-		let path = utils::url_to_path(&config.resources_root, path);
+		let path = file;
+		match config.rsrc_modified(&path)
+		{
+			option::Some(modified) =>
+			{
+				// If the client's copy is up to date we can skip loading the file.
+				response.headers.set_last_modified(modified);
+				match request.headers.find(~"if-modified-since")
+				{
+					option::Some(ref since) if http_date::is_unmodified_since(*since, modified) =>
+					{
+						return Response {status: status::NotModified, body: StringBody(@~""), template: ~"", context: std::map::HashMap(), ..*response};
+					}
+					_ =>
+					{
+					}
+				}
+			}
+			option::None =>
+			{
+			}
+		}
+		
 		let contents = config.load_rsrc(&path);
 		if contents.is_ok()
 		{
@@ -264,27 +420,32 @@ pub fn static_view(config: &connection::ConnConfig, _request: &Request, response
 		else
 		{
 			error!("failed to open %s: %s", path.to_str(), contents.get_err());
-			Response {template: ~"not-found.html", ..*response}
+			error_pages::error_response(response, status::NotFound)
 		}
 	}
 }
 
-pub fn is_text_file(config: &connection::ConnConfig, path: &str) -> bool
+pub fn is_text_file(config: &connection::ConnConfig, path: &str) -> Option<~str>
 {
 	match str::rfind_char(path, '.')
 	{
 		option::Some(index) =>
 		{
 			let ext = path.slice(index, path.len());
+			if config.template_engines.contains_key(@copy ext)
+			{
+				return option::Some(ext);
+			}
+			
 			match config.static_type_table.find(@ext)
 			{
-				option::Some(mine_type) => mine_type.starts_with(~"text/"),
-				option::None => false,
+				option::Some(mine_type) if mine_type.starts_with(~"text/") && config.template_engines.contains_key(@~".mustache") => option::Some(~".mustache"),
+				_ => option::None,
 			}
 		}
 		option::None =>
 		{
-			false
+			option::None
 		}
 	} 
 }
@@ -1,6 +1,6 @@
 //! The module responsible for communication using a persistent connection to a client.
 //use socket::*;
-use request::{process_request, make_header_and_body};
+use request::{process_traced_request, make_header_and_body};
 
 // Like config except that it is connection specific, uses hashmaps, and adds some fields for sse.
 pub struct ConnConfig
@@ -10,46 +10,105 @@ pub struct ConnConfig
 	pub server_info: ~str,
 	pub resources_root: Path,
 	pub route_list: ~[Route],
+	pub router: router::Router,		// indexes into route_list
+	pub template_errors: ~[~str],		// routes whose templates could not be parsed
 	pub views_table: HashMap<@~str, ResponseHandler>,
+	pub static_mounts: ~[(~str, Path)],			// group prefixes and their static_roots
 	pub static_handler: ResponseHandler,
 	pub is_template: IsTemplateFile,
+	pub template_engines: HashMap<@~str, @template_engine::TemplateEngine>,
+	pub template_store: Option<template_cache::TemplateStore>,
 	pub sse_openers: HashMap<@~str, OpenSse>,		// key is a GET path
 	pub sse_tasks: HashMap<@~str, ControlChan>,	// key is a GET path
+	pub mut worker_status: Option<comm::Chan<WorkerStatus>>,	// set for workers (see stream_opened)
 	pub sse_push: comm::Chan<~str>,
 	pub missing: ResponseHandler,
+	pub error_pages: ~[(uint, error_pages::ErrorPage)],
+	pub middleware: ~[middleware::Middleware],
+	pub group_middleware: ~[(~str, ~[middleware::Middleware])],	// group prefixes and their middleware
 	pub static_type_table: HashMap<@~str, @~str>,
 	pub read_error: ~str,
 	pub load_rsrc: RsrcLoader,
 	pub valid_rsrc: RsrcExists,
+	pub rsrc_modified: RsrcModified,
+	pub cache_policies: ~[cache_policy::CachePolicy],
+	pub response_cache: Option<response_cache::CacheChan>,
+	pub access_log: Option<access_log::LogChan>,
+	pub admin: Option<admin::AdminChan>,
+	pub mut connection_id: uint,						// assigned by admin::open_connection
+	pub id_prefix: ~str,								// used for request ids (see tracing::request_id)
+	pub mut request_count: uint,
+	pub metrics: Option<metrics::MetricsChan>,
+	pub metrics_path: ~str,
+	pub trusted_proxies: ~[~str],
+	pub cookie_secret: ~str,
+	pub languages: ~[~str],
+	pub layout: ~str,
+	pub global_context: ContextHook,
 	pub settings: HashMap<@~str, @~str>,
+	pub date_cache: @http_date::DateCache,
+	pub template_cache: template_cache::TemplateCache,
 	
 	drop {}
 }
 
 pub fn config_to_conn(config: &Config, push: comm::Chan<~str>) -> ConnConfig
 {
+	let (group_routes, group_views, static_mounts, group_middleware) = route_group::flatten_groups(config.groups);
+	let routes = config.routes + group_routes;
+	let route_list = vec::map(routes, to_route);
+	let router = router::Router(route_list);
 	ConnConfig {
 		hosts: config.hosts,
 		port: config.port,
 		server_info: config.server_info,
 		resources_root: config.resources_root,
-		route_list: vec::map(config.routes, to_route),
-		views_table: utils::boxed_hash_from_strs(config.views),
+		route_list: route_list,
+		router: router,
+		template_errors: route_template_errors(routes),
+		views_table: utils::boxed_hash_from_strs(config.views + group_views),
+		static_mounts: static_mounts,
 		static_handler: copy config.static_handler,
 		is_template: copy config.is_template,
+		template_engines: template_engine::engines_table(config.template_engines),
+		template_store: config.template_store,
 		sse_openers: utils::boxed_hash_from_strs(config.sse),
 		sse_tasks: std::map::HashMap(),
+		worker_status: option::None,
 		sse_push: push,
 		missing: copy config.missing,
+		error_pages: copy config.error_pages,
+		middleware: copy config.middleware,
+		group_middleware: group_middleware,
 		static_type_table: utils::to_boxed_str_hash(config.static_types),
 		read_error: config.read_error,
 		load_rsrc: copy config.load_rsrc,
 		valid_rsrc: copy config.valid_rsrc,
+		rsrc_modified: copy config.rsrc_modified,
+		cache_policies: copy config.cache_policies,
+		response_cache: config.response_cache,
+		access_log: config.access_log,
+		admin: config.admin,
+		connection_id: 0u,
+		id_prefix: tracing::id_prefix(),
+		request_count: 0u,
+		metrics: config.metrics,
+		metrics_path: copy config.metrics_path,
+		trusted_proxies: copy config.trusted_proxies,
+		cookie_secret: copy config.cookie_secret,
+		languages: copy config.languages,
+		layout: copy config.layout,
+		global_context: copy config.global_context,
 		settings: utils::to_boxed_str_hash(config.settings),
+		date_cache: http_date::DateCache(),
+		template_cache: std::map::HashMap(),
 	}
 }
 
-// TODO: probably want to use task::unsupervise
+// Requests are serviced by an unlinked worker task so that if a view (or anything else involved
+// in servicing a request) fails the client gets a 500 and the connection stays up: this task
+// notices the failure, writes the response, and starts a new worker for the next request. This
+// task also owns the table of open sse streams so that they survive the failure of a worker.
 pub fn handle_connection(config: &Config, fd: libc::c_int, local_addr: &str, remote_addr: &str)
 {
 	let request_port = comm::Port();
@@ -70,6 +129,30 @@ pub fn handle_connection(config: &Config, fd: libc::c_int, local_addr: &str, rem
 	let ra = remote_addr.to_unique();
 	do task::spawn_sched(task::SingleThreaded) {read_requests(ra, fd, request_chan);}
 	
+	let writers: WriterFactory = ||
+	{
+		// The socket handle closes its descriptor when it is destroyed so workers use their own.
+		let sock = @socket::socket::socket_handle(libc::funcs::posix88::unistd::dup(fd));
+		|header: ~str, body: Body| {write_response(sock, header, body)}
+	};
+	service_connection(config, &iconfig, request_port, sse_port, local_addr, remote_addr, |header, body| {write_response(sock, header, body)}, writers);
+}
+
+// Used by workers to create the function they write responses with.
+priv type WriterFactory = fn~ () -> fn@ (header: ~str, body: Body);
+
+// Services requests until request_port is sent None. Write is used for the responses written by
+// this task and writers for the responses written by the workers.
+priv fn service_connection(config: &Config, iconfig: &ConnConfig, request_port: comm::Port<Option<http_parser::HttpRequest>>, sse_port: comm::Port<~str>,
+	local_addr: &str, remote_addr: &str, write: fn (~str, Body), writers: WriterFactory)
+{
+	let status_port = comm::Port();
+	let status_chan = comm::Chan(&status_port);
+	
+	metrics::record_event(iconfig, metrics::ConnectionsMesg(1));
+	iconfig.connection_id = admin::open_connection(iconfig, local_addr, remote_addr);
+	
+	let mut worker = start_worker(config, local_addr, remote_addr, iconfig.connection_id, iconfig.sse_push, ~[], status_chan, copy writers);
 	loop
 	{
 		debug!("-----------------------------------------------------------");
@@ -77,24 +160,149 @@ pub fn handle_connection(config: &Config, fd: libc::c_int, local_addr: &str, rem
 		{
 			either::Left(option::Some(ref request)) =>
 			{
-				let (header, body) = process_request(&iconfig, request, local_addr, remote_addr);
-				write_response(sock, header, body);
+				if admin::begin_request(iconfig, request.method, request.url)
+				{
+					// Ids are assigned here so that requests which fail can still be logged with them.
+					let start_ns = std::time::precise_time_ns();
+					let request = http_parser::HttpRequest {id: tracing::request_id(iconfig, headers::headers_from(request.headers)), ..copy *request};
+					comm::send(worker, option::Some(copy request));
+					loop
+					{
+						match comm::recv(status_port)
+						{
+							StreamMesg(move path, move control) =>
+							{
+								iconfig.sse_tasks.insert(@path, control);
+							}
+							DoneMesg =>
+							{
+								break;
+							}
+							FailedMesg =>
+							{
+								let (header, body) = request::process_failed_request(iconfig, &request, local_addr, remote_addr, start_ns);
+								write(header, body);
+								worker = start_worker(config, local_addr, remote_addr, iconfig.connection_id, iconfig.sse_push, sse_streams(iconfig), status_chan, copy writers);
+								break;
+							}
+						}
+					}
+					admin::end_request(iconfig);
+				}
+				else
+				{
+					// The server is draining so refuse the request and ask the client to close the connection.
+					let (header, body) = admin::draining_response(iconfig);
+					write(header, body);
+				}
 			}
 			either::Left(option::None) =>
 			{
-				close_sses(&iconfig);
+				comm::send(worker, option::None);
+				close_sses(iconfig);
+				metrics::record_event(iconfig, metrics::ConnectionsMesg(-1));
+				admin::close_connection(iconfig);
 				break;
 			}
 			either::Right(move body) =>
 			{
-				let response = make_response(&iconfig);
+				metrics::record_event(iconfig, metrics::SseDataMesg(str::len(body)));
+				let response = make_response(iconfig);
 				let (_, body) = make_header_and_body(&response, StringBody(@body));
-				write_response(sock, ~"", body);
+				write(~"", body);
 			}
 		}
 	}
 }
 
+// Sent by workers to the connection task.
+pub enum WorkerStatus
+{
+	StreamMesg(~str, ControlChan),	// the worker opened an sse stream for the path
+	DoneMesg,						// the response was written
+	FailedMesg,						// the worker failed while servicing the request
+}
+
+/// Called by sse::OpenSse so that the connection task can send the stream a RefreshEvent
+/// or CloseEvent even if the worker which opened it fails.
+pub fn stream_opened(config: &ConnConfig, path: &str, control: ControlChan)
+{
+	match config.worker_status
+	{
+		option::Some(status) => comm::send(status, StreamMesg(path.to_unique(), control)),
+		option::None => {}
+	}
+}
+
+// Lets handle_connection know if the worker fails (destructors are run as a failed task unwinds).
+priv struct FailureGuard
+{
+	status: comm::Chan<WorkerStatus>,
+	mut done: bool,
+	
+	drop
+	{
+		if !self.done
+		{
+			comm::send(self.status, FailedMesg);
+		}
+	}
+}
+
+// Streams are the sse streams opened by previous workers on the connection.
+priv fn start_worker(config: &Config, local_addr: &str, remote_addr: &str, connection_id: uint, sse_chan: comm::Chan<~str>, streams: ~[(~str, ControlChan)], status: comm::Chan<WorkerStatus>, writers: WriterFactory) -> comm::Chan<Option<http_parser::HttpRequest>>
+{
+	let config = copy *config;
+	let local_addr = local_addr.to_unique();
+	let remote_addr = remote_addr.to_unique();
+	do task::task().unlinked().spawn_listener
+	|requests: comm::Port<Option<http_parser::HttpRequest>>, move config, move local_addr, move remote_addr, move streams, move writers|
+	{
+		let guard = FailureGuard {status: status, done: false};
+		let iconfig = config_to_conn(&config, sse_chan);
+		iconfig.connection_id = connection_id;
+		iconfig.worker_status = option::Some(status);
+		for vec::each(streams)
+		|stream|
+		{
+			iconfig.sse_tasks.insert(@stream.first(), stream.second());
+		};
+		
+		let write = writers();
+		loop
+		{
+			match comm::recv(requests)
+			{
+				option::Some(ref request) =>
+				{
+					let (header, body, trace) = process_traced_request(&iconfig, request, local_addr, remote_addr);
+					let write_ns = std::time::precise_time_ns();
+					write(header, body);
+					trace.add(~"write", (std::time::precise_time_ns() - write_ns)/1000u64);
+					tracing::finish_trace(&iconfig, trace);
+					comm::send(status, DoneMesg);
+				}
+				option::None =>
+				{
+					guard.done = true;		// the connection task closes the sse streams
+					break;
+				}
+			}
+		}
+	}
+}
+
+priv fn sse_streams(config: &ConnConfig) -> ~[(~str, ControlChan)]
+{
+	let mut streams = ~[];
+	for config.sse_tasks.each
+	|path, control|
+	{
+		vec::push(&mut streams, (copy *path, control));
+	};
+	streams
+}
+
 priv fn read_requests(remote_addr: &str, fd: libc::c_int, poke: comm::Chan<option::Option<http_parser::HttpRequest>>)
 {
 	let sock = @socket::socket::socket_handle(fd);		// socket::socket_handle(fd);
@@ -105,22 +313,35 @@ priv fn read_requests(remote_addr: &str, fd: libc::c_int, poke: comm::Chan<optio
 		let headers = read_headers(remote_addr, sock);
 		if str::is_not_empty(headers)
 		{
+			let start_ns = std::time::precise_time_ns();
 			match parse(headers)
 			{
 				result::Ok(ref request) =>
 				{
+					let parse_time = (std::time::precise_time_ns() - start_ns)/1000u64;
 					if request.headers.contains_key(~"content-length")
 					{
-						let body = read_body(sock, request.headers.get(~"content-length"));
-						if str::is_not_empty(body)
+						// This task isn't isolated like the workers so a bad Content-Length closes the connection instead of failing.
+						match uint::from_str(request.headers.get(~"content-length"))
 						{
-							comm::send(poke, option::Some(http_parser::HttpRequest {body: body, ..*request}));
-							ok = true;
+							option::Some(content_length) =>
+							{
+								let body = read_body(sock, content_length);
+								if str::is_not_empty(body)
+								{
+									comm::send(poke, option::Some(http_parser::HttpRequest {body: body, parse_time: parse_time, ..*request}));
+									ok = true;
+								}
+							}
+							option::None =>
+							{
+								error!("Bad Content-Length: '%s' from %s", request.headers.get(~"content-length"), remote_addr);
+							}
 						}
 					}
 					else
 					{
-						comm::send(poke, option::Some(copy *request));
+						comm::send(poke, option::Some(http_parser::HttpRequest {parse_time: parse_time, ..*request}));
 						ok = true;
 					}
 				}
@@ -204,10 +425,8 @@ priv fn found_headers(buffer: &[u8]) -> bool
 	}
 }
 
-priv fn read_body(sock: @socket::socket::socket_handle, content_length: ~str) -> ~str unsafe
+priv fn read_body(sock: @socket::socket::socket_handle, total_len: uint) -> ~str unsafe
 {
-	let total_len = option::get(&uint::from_str(content_length));
-	
 	let mut buffer = ~[];
 	vec::reserve(&mut buffer, total_len);
 	
@@ -318,19 +537,27 @@ priv fn validate_config(config: &ConnConfig) -> ~str
 		vec::push(&mut errors, ~"resources_root is not a directory.");
 	}
 	
-	let mut names = ~[];
-	for vec::each(~[~"forbidden.html", ~"home.html", ~"not-found.html", ~"not-supported.html"])
-	|name|
+	for vec::each(config.static_mounts)
+	|mount|
 	{
-		let path = config.resources_root.push(*name);
-		if !os::path_exists(&path)
+		if !os::path_is_dir(&mount.second())
 		{
-			vec::push(&mut names, copy *name);
+			vec::push(&mut errors, fmt!("static_root for %s is not a directory.", mount.first()));
 		}
 	};
-	if vec::is_not_empty(names)
+	
+	for vec::each(config.error_pages)
+	|entry|
+	{
+		if entry.first() < 400u || entry.first() > 599u
+		{
+			vec::push(&mut errors, fmt!("error_pages has an entry for %u which is not an error status.", entry.first()));
+		}
+	};
+	
+	if str::is_not_empty(config.cookie_secret) && str::len(config.cookie_secret) < 32u
 	{
-		vec::push(&mut errors, ~"Missing required files: " + str::connect(names, ~", "));
+		vec::push(&mut errors, ~"cookie_secret should be at least 32 characters.");
 	}
 	
 	if str::is_empty(config.read_error)
@@ -357,6 +584,9 @@ priv fn validate_config(config: &ConnConfig) -> ~str
 		vec::push(&mut errors, fmt!("No views for the following routes: %s", str::connect(missing_routes, ~", ")));
 	}
 	
+	errors += copy config.template_errors;
+	errors += copy config.router.conflicts;
+	
 	let mut missing_views = ~[];
 	for config.views_table.each_key()
 	|route|
@@ -400,6 +630,28 @@ pub fn to_route(input: &(~str, ~str, ~str)) -> Route
 	}
 }
 
+// Templates which can't be parsed are compiled into literals (so the route can never match) and
+// are reported by validate_config.
+priv fn route_template_errors(routes: &[(~str, ~str, ~str)]) -> ~[~str]
+{
+	do vec::filter_map(routes)
+	|entry|
+	{
+		match *entry
+		{
+			(_, ref template, ref route) =>
+			{
+				let path = match str::find_char(*template, '<') {option::Some(i) => template.slice(0, i), option::None => copy *template};
+				match uri_template::parse_template(path)
+				{
+					result::Ok(_) => option::None,
+					result::Err(ref mesg) => option::Some(fmt!("Route %s has a bad template '%s': %s.", *route, *template, *mesg)),
+				}
+			}
+		}
+	}
+}
+
 #[test]
 fn routes_must_have_views()
 {
@@ -437,7 +689,44 @@ fn views_must_have_routes()
 }
 
 #[test]
-fn root_must_have_required_files()
+fn routes_must_not_conflict()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/", ~"home"), (~"GET", ~"/users/{id}", ~"user"), (~"GET", ~"/users/{name}", ~"named-user")],
+		views: ~[(~"home",  missing_view), (~"user",  missing_view), (~"named-user",  missing_view)],
+		..initialize_config()};
+		
+	let sse_port = comm::Port();
+	let sse_chan = comm::Chan(&sse_port);
+	let iconfig = config_to_conn(&config, sse_chan);
+	
+	assert validate_config(&iconfig) == ~"Route named-user conflicts with user.";
+}
+
+#[test]
+fn routes_must_parse()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/", ~"home"), (~"GET", ~"/users/{id<application/json>", ~"user")],
+		views: ~[(~"home",  missing_view), (~"user",  missing_view)],
+		..initialize_config()};
+		
+	let sse_port = comm::Port();
+	let sse_chan = comm::Chan(&sse_port);
+	let iconfig = config_to_conn(&config, sse_chan);
+	
+	let err = validate_config(&iconfig);
+	assert str::starts_with(err, ~"Route user has a bad template '/users/{id<application/json>': ");
+}
+
+#[test]
+fn root_may_omit_error_pages()
 {
 	let config = Config {
 		hosts: ~[~"localhost"],
@@ -451,6 +740,149 @@ fn root_must_have_required_files()
 	let sse_chan = comm::Chan(&sse_port);
 	let iconfig = config_to_conn(&config, sse_chan);
 	
-	assert validate_config(&iconfig) == ~"Missing required files: forbidden.html, home.html, not-found.html, not-supported.html";
+	assert validate_config(&iconfig) == ~"";
 }
 
+
+#[test]
+fn failing_worker()
+{
+	let port = comm::Port();
+	let chan = comm::Chan(&port);
+	do task::task().unlinked().spawn
+	{
+		let _guard = FailureGuard {status: chan, done: false};
+		fail ~"view failed";
+	}
+	match comm::recv(port)
+	{
+		FailedMesg => {}
+		DoneMesg => fail ~"expected FailedMesg",
+	}
+	
+	do task::spawn
+	{
+		let guard = FailureGuard {status: chan, done: false};
+		guard.done = true;
+		comm::send(chan, DoneMesg);
+	}
+	match comm::recv(port)
+	{
+		DoneMesg => {}
+		FailedMesg => fail ~"expected DoneMesg",
+	}
+	assert !port.peek();
+}
+
+#[test]
+fn failing_view()
+{
+	fn bad_view(_config: &ConnConfig, _request: &Request, _response: &Response) -> Response
+	{
+		fail ~"bad_view failed";
+	}
+	
+	fn good_view(_config: &ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		Response {body: StringBody(@~"good"), ..*response}
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/bad", ~"bad"), (~"GET", ~"/good", ~"good")],
+		views: ~[(~"bad", bad_view), (~"good", good_view)]
+		, .. initialize_config()};
+	
+	let request_port = comm::Port();
+	let request_chan = comm::Chan(&request_port);
+	let sse_port = comm::Port();
+	let sse_chan = comm::Chan(&sse_port);
+	let iconfig = config_to_conn(&config, sse_chan);
+	
+	let output_port = comm::Port();
+	let output = comm::Chan(&output_port);
+	let writers: WriterFactory = || {|header: ~str, body: Body| {comm::send(output, header + body.to_str())}};
+	
+	comm::send(request_chan, option::Some(request::make_request(~"/bad", ~"text/html")));
+	comm::send(request_chan, option::Some(request::make_request(~"/good", ~"text/html")));
+	comm::send(request_chan, option::None);
+	service_connection(&config, &iconfig, request_port, sse_port, ~"10.11.12.13", ~"1.2.3.4", |header, body| {comm::send(output, header + body.to_str())}, writers);
+	
+	let response = comm::recv(output_port);
+	assert response.contains("500 Internal Server Error");
+	assert response.contains("The server encountered an internal error.");
+	
+	// The worker was restarted so the next request on the connection still works.
+	let response = comm::recv(output_port);
+	assert response.contains("200 OK");
+	assert str::ends_with(response, "good");
+	assert !output_port.peek();
+}
+
+#[test]
+fn failing_worker_keeps_streams()
+{
+	fn bad_view(_config: &ConnConfig, _request: &Request, _response: &Response) -> Response
+	{
+		fail ~"bad_view failed";
+	}
+	
+	let events_port = comm::Port();
+	let events = comm::Chan(&events_port);
+	let opener: OpenSse = |_config: &ConnConfig, _request: &Request, _push: PushChan, copy events|
+	{
+		comm::send(events, ~"open");
+		do task::spawn_listener
+		|control: ControlPort, copy events|
+		{
+			loop
+			{
+				match comm::recv(control)
+				{
+					RefreshEvent => comm::send(events, ~"refresh"),
+					CloseEvent => {comm::send(events, ~"close"); break;}
+				}
+			}
+		}
+	};
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/bad", ~"bad")],
+		views: ~[(~"bad", bad_view)],
+		sse: ~[(~"/events", opener)]
+		, .. initialize_config()};
+	
+	let request_port = comm::Port();
+	let request_chan = comm::Chan(&request_port);
+	let sse_port = comm::Port();
+	let sse_chan = comm::Chan(&sse_port);
+	let iconfig = config_to_conn(&config, sse_chan);
+	
+	let output_port = comm::Port();
+	let output = comm::Chan(&output_port);
+	let writers: WriterFactory = || {|header: ~str, body: Body| {comm::send(output, header + body.to_str())}};
+	
+	comm::send(request_chan, option::Some(request::make_request(~"/events", ~"text/event-stream")));
+	comm::send(request_chan, option::Some(request::make_request(~"/bad", ~"text/html")));
+	comm::send(request_chan, option::Some(request::make_request(~"/events", ~"text/event-stream")));
+	comm::send(request_chan, option::None);
+	service_connection(&config, &iconfig, request_port, sse_port, ~"10.11.12.13", ~"1.2.3.4", |header, body| {comm::send(output, header + body.to_str())}, writers);
+	
+	assert comm::recv(output_port).contains("200 OK");
+	let response = comm::recv(output_port);
+	assert response.contains("500 Internal Server Error");
+	assert response.contains("X-Request-ID: ");
+	assert comm::recv(output_port).contains("200 OK");
+	
+	// The stream outlived the failed worker: it was refreshed instead of re-opened and
+	// was closed along with the connection.
+	assert comm::recv(events_port) == ~"open";
+	assert comm::recv(events_port) == ~"refresh";
+	assert comm::recv(events_port) == ~"close";
+	assert !events_port.peek();
+}
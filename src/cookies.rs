@@ -0,0 +1,628 @@
+//! Parsing of Cookie headers and building of Set-Cookie headers (see RFC 6265).
+//
+// Values are percent encoded when a Set-Cookie header is built and decoded when the Cookie header
+// is parsed so views can use arbitrary strings. Signed and encrypted cookies are keyed by
+// Config.cookie_secret: clients can read but not alter signed cookies and can neither read nor
+// alter encrypted cookies. Both use HMAC-SHA256 (std only has SHA-1 so SHA-256 is implemented
+// below). Encryption xors the value with an HMAC-SHA256 keystream (counter mode with a random
+// nonce) and then MACs the name, nonce, and ciphertext.
+
+/// Value of the SameSite attribute. Note that browsers ignore SameSite=None unless Secure is also set.
+pub enum SameSite
+{
+	SameSiteUnset,			// the attribute is omitted
+	SameSiteStrict,
+	SameSiteLax,
+	SameSiteNone,
+}
+
+/// Used to build a Set-Cookie header (see HeaderList.set_cookie).
+///
+/// * name: must be a token, e.g. "session-id".
+/// * value: arbitrary text (it's percent encoded in the header).
+/// * expires: seconds since the epoch at which the client should discard the cookie.
+/// * max_age: seconds until the client should discard the cookie (takes precedence over expires).
+/// * domain: hosts the cookie is sent to (empty to use only the host which set it).
+/// * path: the cookie is sent only for URLs beneath this path (empty to use the request's directory).
+/// * secure: if set the cookie is only sent over https.
+/// * http_only: if set the cookie is not available to scripts.
+/// * same_site: whether the cookie is sent with cross-site requests.
+pub struct Cookie
+{
+	pub name: ~str,
+	pub value: ~str,
+	pub expires: Option<i64>,
+	pub max_age: Option<i64>,
+	pub domain: ~str,
+	pub path: ~str,
+	pub secure: bool,
+	pub http_only: bool,
+	pub same_site: SameSite,
+}
+
+/// Returns a session cookie for the whole site which is hidden from scripts and only sent with
+/// same-site requests and top level navigations, e.g.
+/// response.headers.set_cookie(&Cookie {max_age: option::Some(3600i64), ..Cookie(~"theme", ~"dark")});
+pub fn Cookie(name: &str, value: &str) -> Cookie
+{
+	Cookie {
+		name: name.to_unique(),
+		value: value.to_unique(),
+		expires: option::None,
+		max_age: option::None,
+		domain: ~"",
+		path: ~"/",
+		secure: false,
+		http_only: true,
+		same_site: SameSiteLax,
+	}
+}
+
+/// Returns a cookie which tells the client to discard the cookie named name. Note that the domain
+/// and path must match the ones used when the cookie was set.
+pub fn expired_cookie(name: &str) -> Cookie
+{
+	Cookie {expires: option::Some(0i64), max_age: option::Some(0i64), ..Cookie(name, ~"")}
+}
+
+pub impl Cookie
+{
+	/// Returns the value of the Set-Cookie header for the cookie. Fails if the name is not a token
+	/// or if domain or path contain characters which would corrupt the header.
+	fn to_header() -> ~str
+	{
+		if !is_token(self.name)
+		{
+			fail fmt!("Cookie name '%s' is not a token", self.name);
+		}
+
+		let mut header = fmt!("%s=%s", self.name, uri_template::percent_encode(self.value, false));
+		match self.expires
+		{
+			option::Some(secs) =>
+			{
+				header += ~"; Expires=" + http_date::format_http_date(secs);
+			}
+			option::None =>
+			{
+			}
+		}
+		match self.max_age
+		{
+			option::Some(secs) =>
+			{
+				header += fmt!("; Max-Age=%d", secs as int);
+			}
+			option::None =>
+			{
+			}
+		}
+		if str::is_not_empty(self.domain)
+		{
+			header += ~"; Domain=" + attribute_value(~"domain", self.domain);
+		}
+		if str::is_not_empty(self.path)
+		{
+			header += ~"; Path=" + attribute_value(~"path", self.path);
+		}
+		if self.secure
+		{
+			header += ~"; Secure";
+		}
+		if self.http_only
+		{
+			header += ~"; HttpOnly";
+		}
+		match self.same_site
+		{
+			SameSiteUnset => {}
+			SameSiteStrict => header += ~"; SameSite=Strict",
+			SameSiteLax => header += ~"; SameSite=Lax",
+			SameSiteNone => header += ~"; SameSite=None",
+		}
+		header
+	}
+}
+
+/// Returns the name/value pairs from the Cookie headers. Pairs without a name are skipped and
+/// values are percent decoded. Note that the names may be duplicated (e.g. when cookies with the
+/// same name were set for different paths).
+pub fn parse_cookies(headers: headers::Headers) -> IMap<@~str, @~str>
+{
+	let mut cookies = ~[];
+	for vec::each(headers.get_all(~"cookie"))
+	|header|
+	{
+		for vec::each(str::split_char(*header, ';'))
+		|pair|
+		{
+			match str::find_char(*pair, '=')
+			{
+				option::Some(i) if str::is_not_empty(str::trim(pair.slice(0, i))) =>
+				{
+					let value = str::trim(pair.slice(i+1, pair.len()));
+					let value = if value.len() >= 2u && value[0] == '"' as u8 && value[value.len()-1u] == '"' as u8 {value.slice(1, value.len()-1u)} else {value};
+					vec::push(&mut cookies, (@str::trim(pair.slice(0, i)), @decode_value(value)));
+				}
+				_ =>
+				{
+				}
+			}
+		}
+	}
+	cookies
+}
+
+// ---- Signed and encrypted cookies --------------------------------------------
+/// Returns cookie with a MAC appended to its value. The value can be read by the client but
+/// find_signed_cookie will only return it if it hasn't been altered. Fails if Config.cookie_secret
+/// is empty.
+pub fn signed_cookie(config: &connection::ConnConfig, cookie: &Cookie) -> Cookie
+{
+	let mac = signature(config, cookie.name, cookie.value);
+	Cookie {value: fmt!("%s.%s", cookie.value, to_hex(mac)), ..*cookie}
+}
+
+/// Returns the value of the first cookie named name which was created with signed_cookie and
+/// hasn't been altered.
+pub fn find_signed_cookie(config: &connection::ConnConfig, request: &Request, name: &str) -> Option<~str>
+{
+	for vec::each(request.cookies.get_all(@name.to_unique()))
+	|value|
+	{
+		match str::rfind_char(**value, '.')
+		{
+			option::Some(i) =>
+			{
+				let text = value.slice(0, i);
+				match from_hex(value.slice(i+1u, value.len()))
+				{
+					option::Some(mac) if same_bytes(mac, signature(config, name, text)) =>
+					{
+						return option::Some(text);
+					}
+					_ =>
+					{
+					}
+				}
+			}
+			option::None =>
+			{
+			}
+		}
+	}
+	option::None
+}
+
+/// Returns cookie with its value encrypted so that the client can neither read nor alter it.
+/// Fails if Config.cookie_secret is empty.
+pub fn encrypted_cookie(config: &connection::ConnConfig, cookie: &Cookie) -> Cookie
+{
+	let nonce = rand::Rng().gen_bytes(16u);
+	let cipher = apply_keystream(cookie_key(config, ~"rwebserve encrypted cookie"), nonce, str::to_bytes(cookie.value));
+	let mac = encryption_mac(config, cookie.name, nonce, cipher);
+
+	let mut data = nonce;
+	vec::push_all(&mut data, cipher);
+	vec::push_all(&mut data, mac);
+	Cookie {value: to_hex(data), ..*cookie}
+}
+
+/// Returns the decrypted value of the first cookie named name which was created with
+/// encrypted_cookie and hasn't been altered.
+pub fn find_encrypted_cookie(config: &connection::ConnConfig, request: &Request, name: &str) -> Option<~str>
+{
+	for vec::each(request.cookies.get_all(@name.to_unique()))
+	|value|
+	{
+		match from_hex(**value)
+		{
+			option::Some(data) if data.len() >= 48u =>
+			{
+				let nonce = vec::slice(data, 0u, 16u);
+				let cipher = vec::slice(data, 16u, data.len() - 32u);
+				let mac = vec::slice(data, data.len() - 32u, data.len());
+				if same_bytes(mac, encryption_mac(config, name, nonce, cipher))
+				{
+					let plain = apply_keystream(cookie_key(config, ~"rwebserve encrypted cookie"), nonce, cipher);
+					if str::is_utf8(plain)
+					{
+						return option::Some(str::from_bytes(plain));
+					}
+				}
+			}
+			_ =>
+			{
+			}
+		}
+	}
+	option::None
+}
+
+// ---- Functions used by HeaderList --------------------------------------------
+/// Returns true if the Set-Cookie header value is for the same name, domain, and path as cookie
+/// (i.e. one would replace the other on the client). Attribute names are case-insensitive.
+pub fn same_cookie(header: &str, cookie: &Cookie) -> bool
+{
+	let parts = str::split_char(header, ';');
+	let name = match str::find_char(parts[0], '=') {option::Some(i) => str::trim(parts[0].slice(0, i)), option::None => str::trim(parts[0])};
+	let mut domain = ~"";
+	let mut path = ~"";
+	for vec::eachi(parts)
+	|i, part|
+	{
+		if i > 0u
+		{
+			match str::find_char(*part, '=')
+			{
+				option::Some(j) =>
+				{
+					let attribute = str::to_lower(str::trim(part.slice(0, j)));
+					if attribute == ~"domain"
+					{
+						domain = str::to_lower(str::trim(part.slice(j+1u, part.len())));
+					}
+					else if attribute == ~"path"
+					{
+						path = str::trim(part.slice(j+1u, part.len()));
+					}
+				}
+				option::None =>
+				{
+				}
+			}
+		}
+	}
+	name == cookie.name && domain == str::to_lower(cookie.domain) && path == cookie.path
+}
+
+// ---- Internal Items ----------------------------------------------------------
+// Token characters from section 3.2.6 of RFC 7230.
+priv fn is_token(text: &str) -> bool
+{
+	str::is_not_empty(text) && str::all(text, |ch| {ch > ' ' && ch <= '~' && !str::contains_char("()<>@,;:\\\"/[]?={}", ch)})
+}
+
+priv fn attribute_value(name: &str, value: &str) -> ~str
+{
+	if !str::all(value, |ch| {ch >= ' ' && ch <= '~' && ch != ';'})
+	{
+		fail fmt!("Cookie %s '%s' contains an invalid character", name, value);
+	}
+	value.to_unique()
+}
+
+// Like http_parser's decode except that invalid UTF-8 leaves the value as is.
+priv fn decode_value(text: &str) -> ~str
+{
+	let mut bytes = ~[];
+	let mut i = 0u;
+	while i < text.len()
+	{
+		match if text[i] == '%' as u8 && i + 2u < text.len() {(hex_digit(text[i+1u]), hex_digit(text[i+2u]))} else {(option::None, option::None)}
+		{
+			(option::Some(high), option::Some(low)) =>
+			{
+				vec::push(&mut bytes, ((high << 4) | low) as u8);
+				i += 3u;
+			}
+			_ =>
+			{
+				vec::push(&mut bytes, text[i]);
+				i += 1u;
+			}
+		}
+	}
+	if str::is_utf8(bytes) {str::from_bytes(bytes)} else {text.to_unique()}
+}
+
+priv pure fn hex_digit(b: u8) -> Option<uint>
+{
+	let ch = b as char;
+	if ch >= '0' && ch <= '9'
+	{
+		option::Some((ch - '0') as uint)
+	}
+	else if ch >= 'a' && ch <= 'f'
+	{
+		option::Some((ch - 'a') as uint + 10u)
+	}
+	else if ch >= 'A' && ch <= 'F'
+	{
+		option::Some((ch - 'A') as uint + 10u)
+	}
+	else
+	{
+		option::None
+	}
+}
+
+priv fn to_hex(bytes: &[u8]) -> ~str
+{
+	str::concat(vec::map(bytes, |b| {fmt!("%02x", *b as uint)}))
+}
+
+priv fn from_hex(text: &str) -> Option<~[u8]>
+{
+	if text.len() % 2u != 0u
+	{
+		return option::None;
+	}
+
+	let mut bytes = ~[];
+	let mut i = 0u;
+	while i < text.len()
+	{
+		match (hex_digit(text[i]), hex_digit(text[i+1u]))
+		{
+			(option::Some(high), option::Some(low)) =>
+			{
+				vec::push(&mut bytes, ((high << 4) | low) as u8);
+			}
+			_ =>
+			{
+				return option::None;
+			}
+		}
+		i += 2u;
+	}
+	option::Some(bytes)
+}
+
+// Compares the bytes in time independent of where they differ so that attackers can't use
+// timing to guess MACs.
+priv fn same_bytes(a: &[u8], b: &[u8]) -> bool
+{
+	if a.len() != b.len()
+	{
+		return false;
+	}
+
+	let mut diff = 0u8;
+	for vec::eachi(a)
+	|i, x|
+	{
+		diff |= *x ^ b[i];
+	}
+	diff == 0u8
+}
+
+// Keys for each purpose are derived from the secret so that a MAC for one can't be used for another.
+priv fn cookie_key(config: &connection::ConnConfig, purpose: &str) -> ~[u8]
+{
+	if str::is_empty(config.cookie_secret)
+	{
+		fail ~"Signed and encrypted cookies require Config.cookie_secret";
+	}
+	hmac_sha256(str::to_bytes(config.cookie_secret), str::to_bytes(purpose))
+}
+
+priv fn signature(config: &connection::ConnConfig, name: &str, value: &str) -> ~[u8]
+{
+	hmac_sha256(cookie_key(config, ~"rwebserve signed cookie"), str::to_bytes(fmt!("%s=%s", name, value)))
+}
+
+priv fn encryption_mac(config: &connection::ConnConfig, name: &str, nonce: &[u8], cipher: &[u8]) -> ~[u8]
+{
+	let mut data = str::to_bytes(name);
+	vec::push(&mut data, 0u8);
+	vec::push_all(&mut data, nonce);
+	vec::push_all(&mut data, cipher);
+	hmac_sha256(cookie_key(config, ~"rwebserve encrypted cookie mac"), data)
+}
+
+// Xors data with HMAC-SHA256(key, nonce + counter) for counter = 0, 1, ... (the same call encrypts
+// and decrypts).
+priv fn apply_keystream(key: &[u8], nonce: &[u8], data: &[u8]) -> ~[u8]
+{
+	let mut result = ~[];
+	let mut block = ~[];
+	for vec::eachi(data)
+	|i, b|
+	{
+		if i % 32u == 0u
+		{
+			let counter = (i / 32u) as u32;
+			let mut input = vec::from_slice(nonce);
+			vec::push_all(&mut input, ~[(counter >> 24) as u8, (counter >> 16) as u8, (counter >> 8) as u8, counter as u8]);
+			block = hmac_sha256(key, input);
+		}
+		vec::push(&mut result, *b ^ block[i % 32u]);
+	}
+	result
+}
+
+// See RFC 2104.
+priv fn hmac_sha256(key: &[u8], data: &[u8]) -> ~[u8]
+{
+	let key = if key.len() > 64u {sha256(key)} else {vec::from_slice(key)};
+	let mut inner = ~[];
+	let mut outer = ~[];
+	for uint::range(0u, 64u)
+	|i|
+	{
+		let k = if i < key.len() {key[i]} else {0u8};
+		vec::push(&mut inner, k ^ 0x36u8);
+		vec::push(&mut outer, k ^ 0x5cu8);
+	}
+	vec::push_all(&mut inner, data);
+	vec::push_all(&mut outer, sha256(inner));
+	sha256(outer)
+}
+
+priv const round_constants: [u32 * 64] = [
+	0x428a2f98u32, 0x71374491u32, 0xb5c0fbcfu32, 0xe9b5dba5u32, 0x3956c25bu32, 0x59f111f1u32, 0x923f82a4u32, 0xab1c5ed5u32,
+	0xd807aa98u32, 0x12835b01u32, 0x243185beu32, 0x550c7dc3u32, 0x72be5d74u32, 0x80deb1feu32, 0x9bdc06a7u32, 0xc19bf174u32,
+	0xe49b69c1u32, 0xefbe4786u32, 0x0fc19dc6u32, 0x240ca1ccu32, 0x2de92c6fu32, 0x4a7484aau32, 0x5cb0a9dcu32, 0x76f988dau32,
+	0x983e5152u32, 0xa831c66du32, 0xb00327c8u32, 0xbf597fc7u32, 0xc6e00bf3u32, 0xd5a79147u32, 0x06ca6351u32, 0x14292967u32,
+	0x27b70a85u32, 0x2e1b2138u32, 0x4d2c6dfcu32, 0x53380d13u32, 0x650a7354u32, 0x766a0abbu32, 0x81c2c92eu32, 0x92722c85u32,
+	0xa2bfe8a1u32, 0xa81a664bu32, 0xc24b8b70u32, 0xc76c51a3u32, 0xd192e819u32, 0xd6990624u32, 0xf40e3585u32, 0x106aa070u32,
+	0x19a4c116u32, 0x1e376c08u32, 0x2748774cu32, 0x34b0bcb5u32, 0x391c0cb3u32, 0x4ed8aa4au32, 0x5b9cca4fu32, 0x682e6ff3u32,
+	0x748f82eeu32, 0x78a5636fu32, 0x84c87814u32, 0x8cc70208u32, 0x90befffau32, 0xa4506cebu32, 0xbef9a3f7u32, 0xc67178f2u32];
+
+priv pure fn rotr(x: u32, n: uint) -> u32
+{
+	(x >> n) | (x << (32u - n))
+}
+
+// See FIPS 180-4.
+priv fn sha256(data: &[u8]) -> ~[u8]
+{
+	let mut message = vec::from_slice(data);
+	let bit_len = (data.len() as u64) * 8u64;
+	vec::push(&mut message, 0x80u8);
+	while message.len() % 64u != 56u
+	{
+		vec::push(&mut message, 0u8);
+	}
+	for uint::range(0u, 8u)
+	|i|
+	{
+		vec::push(&mut message, (bit_len >> (56u - 8u*i)) as u8);
+	}
+
+	let (mut h0, mut h1, mut h2, mut h3) = (0x6a09e667u32, 0xbb67ae85u32, 0x3c6ef372u32, 0xa54ff53au32);
+	let (mut h4, mut h5, mut h6, mut h7) = (0x510e527fu32, 0x9b05688cu32, 0x1f83d9abu32, 0x5be0cd19u32);
+	let w = vec::to_mut(vec::from_elem(64u, 0u32));
+	let mut chunk = 0u;
+	while chunk < message.len()
+	{
+		for uint::range(0u, 16u)
+		|t|
+		{
+			let j = chunk + 4u*t;
+			w[t] = (message[j] as u32 << 24) | (message[j+1u] as u32 << 16) | (message[j+2u] as u32 << 8) | (message[j+3u] as u32);
+		}
+		for uint::range(16u, 64u)
+		|t|
+		{
+			let s0 = rotr(w[t-15u], 7u) ^ rotr(w[t-15u], 18u) ^ (w[t-15u] >> 3);
+			let s1 = rotr(w[t-2u], 17u) ^ rotr(w[t-2u], 19u) ^ (w[t-2u] >> 10);
+			w[t] = w[t-16u] + s0 + w[t-7u] + s1;
+		}
+
+		let (mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h) = (h0, h1, h2, h3, h4, h5, h6, h7);
+		for uint::range(0u, 64u)
+		|t|
+		{
+			let s1 = rotr(e, 6u) ^ rotr(e, 11u) ^ rotr(e, 25u);
+			let ch = (e & f) ^ (!e & g);
+			let temp1 = h + s1 + ch + round_constants[t] + w[t];
+			let s0 = rotr(a, 2u) ^ rotr(a, 13u) ^ rotr(a, 22u);
+			let maj = (a & b) ^ (a & c) ^ (b & c);
+			let temp2 = s0 + maj;
+
+			h = g; g = f; f = e; e = d + temp1;
+			d = c; c = b; b = a; a = temp1 + temp2;
+		}
+		h0 += a; h1 += b; h2 += c; h3 += d;
+		h4 += e; h5 += f; h6 += g; h7 += h;
+		chunk += 64u;
+	}
+
+	let mut digest = ~[];
+	for vec::each(~[h0, h1, h2, h3, h4, h5, h6, h7])
+	|word|
+	{
+		for uint::range(0u, 4u)
+		|i|
+		{
+			vec::push(&mut digest, (*word >> (24u - 8u*i)) as u8);
+		}
+	}
+	digest
+}
+
+#[test]
+fn digests()
+{
+	assert to_hex(sha256(str::to_bytes(~""))) == ~"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+	assert to_hex(sha256(str::to_bytes(~"abc"))) == ~"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+	assert to_hex(sha256(str::to_bytes(~"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"))) == ~"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1";
+
+	// Test case 2 from RFC 4231.
+	assert to_hex(hmac_sha256(str::to_bytes(~"Jefe"), str::to_bytes(~"what do ya want for nothing?"))) == ~"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+	assert from_hex(~"00ff7A") == option::Some(~[0u8, 255u8, 122u8]);
+	assert from_hex(~"0g") == option::None;
+	assert same_bytes(~[1u8, 2u8], ~[1u8, 2u8]);
+	assert !same_bytes(~[1u8, 2u8], ~[1u8, 3u8]);
+}
+
+#[test]
+fn cookie_headers()
+{
+	let cookie = Cookie {expires: option::Some(784111777i64), max_age: option::Some(60i64), domain: ~"example.com", secure: true, same_site: SameSiteStrict, ..Cookie(~"theme", ~"dark blue")};
+	assert utils::check_strs(cookie.to_header(), ~"theme=dark%20blue; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=60; Domain=example.com; Path=/; Secure; HttpOnly; SameSite=Strict");
+	assert utils::check_strs(Cookie {path: ~"", http_only: false, same_site: SameSiteUnset, ..Cookie(~"a", ~"1")}.to_header(), ~"a=1");
+	assert utils::check_strs(expired_cookie(~"a").to_header(), ~"a=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Path=/; HttpOnly; SameSite=Lax");
+
+	let headers = headers::Headers();
+	headers.set_cookie(&Cookie(~"a", ~"1"));
+	headers.set_cookie(&Cookie(~"b", ~"2"));
+	headers.set_cookie(&Cookie(~"a", ~"3"));
+	assert utils::check_vectors(headers.get_all(~"Set-Cookie"), ~[~"b=2; Path=/; HttpOnly; SameSite=Lax", ~"a=3; Path=/; HttpOnly; SameSite=Lax"]);
+
+	// Cookies with the same name but a different domain or path are separate cookies.
+	let headers = headers::Headers();
+	headers.set_cookie(&Cookie {path: ~"/old", ..expired_cookie(~"session")});
+	headers.set_cookie(&Cookie(~"session", ~"1"));
+	headers.set_cookie(&Cookie {domain: ~"example.com", ..Cookie(~"session", ~"2")});
+	headers.set_cookie(&Cookie(~"session", ~"3"));
+	assert utils::check_vectors(headers.get_all(~"Set-Cookie"), ~[
+		~"session=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0; Path=/old; HttpOnly; SameSite=Lax",
+		~"session=2; Domain=example.com; Path=/; HttpOnly; SameSite=Lax",
+		~"session=3; Path=/; HttpOnly; SameSite=Lax"]);
+
+	let headers = headers::headers_from(~[(~"Cookie", ~"theme=dark%20blue; novalue; quoted=\"x\""), (~"cookie", ~"theme=light;=empty")]);
+	let cookies = parse_cookies(headers);
+	assert utils::check_vectors(vec::map(cookies, |c| {fmt!("%s=%s", *c.first(), *c.second())}), ~[~"theme=dark blue", ~"quoted=x", ~"theme=light"]);
+	assert cookies.get(@~"theme") == @~"dark blue";
+}
+
+#[test]
+fn secure_cookies()
+{
+	fn secret_view(config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+	{
+		let user = find_signed_cookie(config, request, ~"user").get_default(~"none");
+		let token = find_encrypted_cookie(config, request, ~"token").get_default(~"none");
+		Response {body: StringBody(@fmt!("user=%s token=%s", user, token)), ..*response}
+	}
+
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/secret", ~"secret")],
+		views: ~[(~"secret", secret_view)],
+		cookie_secret: ~"0123456789abcdef0123456789abcdef"
+		, .. initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	let user = signed_cookie(&iconfig, &Cookie(~"user", ~"bob")).value;
+	let token = encrypted_cookie(&iconfig, &Cookie(~"token", ~"s3cret; value")).value;
+	assert str::starts_with(user, ~"bob.");
+	assert !token.contains("s3cret");
+
+	let get = |cookies: ~str| {
+		let base = request::make_request(~"/secret", ~"text/html");
+		let request = http_parser::HttpRequest {headers: base.headers + ~[(~"cookie", cookies)], ..base};
+		let (_header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+		body.to_str()
+	};
+	assert utils::check_strs(get(fmt!("user=%s; token=%s", user, token)), ~"user=bob token=s3cret; value");
+
+	// Altered values, values moved to another name, and truncated values are all rejected.
+	let eve = str::replace(user, ~"bob.", ~"eve.");
+	let flipped = str::from_char(if token[0] == '0' as u8 {'1'} else {'0'}) + token.slice(1, token.len());
+	assert utils::check_strs(get(fmt!("user=%s; token=%s", eve, flipped)), ~"user=none token=none");
+	assert utils::check_strs(get(fmt!("user=%s; token=%s", token, user)), ~"user=none token=none");
+	assert utils::check_strs(get(fmt!("user=bob; token=%s", token.slice(0, 40))), ~"user=none token=none");
+
+	// A later cookie with the same name is used if the first one is invalid.
+	assert utils::check_strs(get(fmt!("user=%s; user=%s", eve, user)), ~"user=bob token=none");
+}
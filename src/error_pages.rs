@@ -0,0 +1,203 @@
+//! Pages used for responses with error status codes.
+//
+// Views (and rwebserve itself) report errors by returning error_response which leaves the body
+// and template empty. get_body then uses the Config.error_pages entry for the status code to
+// fill in the page or, if there is no entry or its template is missing, a built-in page.
+
+/// How the page for an error status is generated (see Config.error_pages).
+///
+/// * ErrorTemplate: path relative to resources_root of a template. If the file doesn't exist the
+/// built-in page is used.
+/// * ErrorView: a view handler. On entry the response's status will be the error status.
+pub enum ErrorPage
+{
+	ErrorTemplate(~str),
+	ErrorView(ResponseHandler),
+}
+
+/// Returns a response for the code error which will be filled in using Config.error_pages,
+/// e.g. a view which can't find a resource would return error_response(response, status::NotFound).
+pub fn error_response(response: &Response, code: status::Status) -> Response
+{
+	Response {status: code, body: StringBody(@~""), template: ~"", ..*response}
+}
+
+/// View handler which returns error_response for the status the response already has.
+pub fn error_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+{
+	error_response(response, response.status)
+}
+
+// ---- Functions used by get_body ----------------------------------------------
+/// Returns true if response is an error which still needs a page.
+pub fn needs_error_page(response: &Response) -> bool
+{
+	response.status.code() >= 400u && str::is_empty(response.template) && is_empty_body(&response.body)
+}
+
+/// Returns response with the page for its status code.
+pub fn apply_error_page(config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+{
+	let code = response.status.code();
+	match vec::find(config.error_pages, |entry| {entry.first() == code})
+	{
+		option::Some((_, ErrorView(ref handler))) =>
+		{
+			(*handler)(config, request, response)
+		}
+		option::Some((_, ErrorTemplate(ref template))) if config.valid_rsrc(&utils::url_to_path(&config.resources_root, *template)) =>
+		{
+			response.headers.set_content_type(~"text/html; charset=UTF-8");
+			Response {template: copy *template, ..*response}
+		}
+		_ =>
+		{
+			builtin_page(request, response)
+		}
+	}
+}
+
+// ---- Functions used by the connection code -----------------------------------
+/// Returns the header and body of the 500 response used when the task servicing request fails.
+/// In debug mode the page describes the request (the failure message itself is logged by the
+/// runtime) with the values of credential headers like Cookie redacted so that scripts can't use
+/// the page to read HttpOnly cookies. Note that this doesn't use Config.error_pages because that
+/// would involve the code which just failed.
+pub fn failure_response(config: &connection::ConnConfig, request: &http_parser::HttpRequest) -> (~str, Body)
+{
+	let details = if config.settings.contains_key(@~"debug") && config.settings.get(@~"debug") == @~"true"
+		{
+			let headers = str::concat(vec::map(request.headers, |h| {fmt!("%s: %s\n", h.first(), if is_credential(h.first()) {~"[redacted]"} else {h.second()})}));
+			fmt!("\n<p>The task servicing %s %s failed (see the server log for the failure message).</p>\n<pre>%s</pre>",
+				html::escape_html(request.method), html::escape_html(request.url), html::escape_html(headers))
+		}
+		else
+		{
+			~""
+		};
+	let body = fmt!("<!DOCTYPE html>\n<meta charset=utf-8>\n\n<title>Error %s!</title>\n<p>The server encountered an internal error.</p>%s",
+		status::InternalServerError.to_str(), details);
+
+	let headers = headers::headers_from(~[
+		(~"Content-Type", ~"text/html; charset=UTF-8"),
+		(~"Date", config.date_cache.now()),
+		(~"Server", copy config.server_info),
+	]);
+	if str::is_not_empty(request.id)
+	{
+		headers.set(~"X-Request-ID", copy request.id);
+	}
+	let response = Response {status: status::InternalServerError, headers: headers, body: StringBody(@~""), template: ~"", context: std::map::HashMap(), layout: ~""};
+	request::make_header_and_body(&response, StringBody(@if request.method == ~"HEAD" {~""} else {body}))
+}
+
+// ---- Internal Items ----------------------------------------------------------
+priv fn builtin_page(request: &Request, response: &Response) -> Response
+{
+	let path = html::escape_html(request.path);
+	let mesg = match response.status.code()
+		{
+			400u => ~"The request could not be understood.",
+			403u => fmt!("You don't have permission to access %s.", path),
+			404u => fmt!("%s was not found.", path),
+			405u | 501u => fmt!("The %s method is not supported for this URL.", html::escape_html(request.method)),
+			500u => ~"The server encountered an internal error.",
+			503u => ~"The server is temporarily unavailable.",
+			505u => fmt!("HTTP version %s is not supported.", html::escape_html(request.version)),
+			_ => fmt!("The request for %s failed.", path),
+		};
+	let body = fmt!("<!DOCTYPE html>\n<meta charset=utf-8>\n\n<title>Error %s!</title>\n<p>%s</p>", html::escape_html(response.status.to_str()), mesg);
+
+	response.headers.set_content_type(~"text/html; charset=UTF-8");
+	Response {body: StringBody(@body), template: ~"", context: std::map::HashMap(), ..*response}
+}
+
+priv fn is_credential(header: &str) -> bool
+{
+	vec::contains(~[~"authorization", ~"cookie", ~"proxy-authorization"], &str::to_lower(header))
+}
+
+priv fn is_empty_body(body: &Body) -> bool
+{
+	match *body
+	{
+		StringBody(text) => str::is_empty(*text),
+		BinaryBody(binary) => vec::is_empty(*binary),
+		CompoundBody(parts) => parts.all(|part| {is_empty_body(*part)}),
+	}
+}
+
+#[test]
+fn error_pages()
+{
+	fn teapot_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		Response {body: StringBody(@fmt!("custom %u", response.status.code())), ..*response}
+	}
+
+	fn failing_view(_config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+	{
+		if request.path == ~"/items/1" {error_response(response, status::Forbidden)} else {error_response(response, status::ServiceUnavailable)}
+	}
+
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"/tmp"),
+		routes: ~[(~"GET", ~"/items/{id}", ~"item")],
+		views: ~[(~"item", failing_view)],
+		error_pages: ~[(403u, ErrorTemplate(~"forbidden.html")), (503u, ErrorView(teapot_view))],
+		load_rsrc: |path: &Path| {result::Ok(str::to_bytes(path.to_str() + ~" contents"))},
+		valid_rsrc: |path: &Path| {str::ends_with(path.to_str(), "forbidden.html")}
+		, .. initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	let request = request::make_request(~"/items/1", ~"text/html");
+	let (header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("403 Forbidden");
+	assert utils::check_strs(body.to_str(), ~"/tmp/forbidden.html contents");
+
+	let request = request::make_request(~"/items/2", ~"text/html");
+	let (header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("503 Service Unavailable");
+	assert utils::check_strs(body.to_str(), ~"custom 503");
+
+	let request = request::make_request(~"/missing<b>", ~"text/html");
+	let (header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("404 Not Found");
+	assert header.contains("Content-Type: text/html");
+	assert body.to_str().contains("<p>/missing&lt;b&gt; was not found.</p>");
+}
+
+#[test]
+fn failure_pages()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html")
+		, .. initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	let request = request::make_request(~"/uptime?units=<s>", ~"text/html");
+	let (header, body) = failure_response(&iconfig, &request);
+	assert header.contains("500 Internal Server Error");
+	assert body.to_str().contains("The server encountered an internal error.");
+	assert !body.to_str().contains("/uptime");
+
+	let config = Config {settings: ~[(~"debug", ~"true")], .. config};
+	let iconfig = connection::config_to_conn(&config, ech);
+	let request = http_parser::HttpRequest {headers: request.headers + ~[(~"cookie", ~"session=s3cret"), (~"authorization", ~"Basic Ym9iOnB3")], ..request};
+	let (_header, body) = failure_response(&iconfig, &request);
+	assert body.to_str().contains("The task servicing GET /uptime?units=&lt;s&gt; failed");
+	assert body.to_str().contains("user-agent: Mozilla/5.0\n");
+	assert body.to_str().contains("cookie: [redacted]\n");
+	assert !body.to_str().contains("s3cret");
+	assert !body.to_str().contains("Ym9iOnB3");
+}
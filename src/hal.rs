@@ -0,0 +1,160 @@
+//! Builder for HAL resources (see http://stateless.co/hal_specification.html).
+//
+// HAL is a simple JSON format for hypermedia: resources have a _links object mapping
+// relation names to URLs and an optional _embedded object containing nested resources.
+
+/// Mime type for HAL JSON documents.
+pub const hal_mime_type: &static/str = "application/hal+json";
+
+/// A link to a resource. If templated is true then href is a URI template.
+pub struct Link
+{
+	pub href: ~str,
+	pub templated: bool,
+}
+
+/// Incrementally builds a HAL resource. Like Headers this is a boxed type with mutable state
+/// so it can be passed around and added to via borrowed pointers.
+pub struct HalBuilder
+{
+	priv mut properties: ~[(~str, Json)],
+	priv mut links: ~[(~str, Link)],
+	priv mut embedded: ~[(~str, Json)],
+}
+
+/// Returns a builder for a resource with no links or properties.
+pub fn HalBuilder() -> @HalBuilder
+{
+	@HalBuilder {properties: ~[], links: ~[], embedded: ~[]}
+}
+
+pub impl HalBuilder
+{
+	/// Adds a property to the resource, e.g. "total" or "status".
+	fn add_property(name: &str, value: Json)
+	{
+		self.properties += ~[(name.to_unique(), value)];
+	}
+
+	/// Adds a link to the resource. Relations may be used more than once (in which case
+	/// the relation will map to an array of links).
+	fn add_link(rel: &str, href: &str)
+	{
+		self.links += ~[(rel.to_unique(), Link {href: href.to_unique(), templated: false})];
+	}
+
+	/// Adds a link whose URL is generated from the named route's URI template. Template variables
+	/// missing from vars are left as is and the link is marked as templated so clients can fill them in.
+	fn add_route_link(config: &connection::ConnConfig, rel: &str, route_name: &str, vars: &[(~str, ~str)]) -> result::Result<(), ~str>
+	{
+		match vec::find(config.route_list, |r| {str::eq_slice(r.route, route_name)})
+		{
+			option::Some(ref route) =>
+			{
+				let (href, missing) = uri_template::expand(route.template, vars);
+				self.links += ~[(rel.to_unique(), Link {href: href, templated: vec::is_not_empty(missing)})];
+				result::Ok(())
+			}
+			option::None =>
+			{
+				result::Err(fmt!("there is no route named '%s'", route_name))
+			}
+		}
+	}
+
+	/// Adds a nested resource. Relations may be used more than once (in which case the
+	/// relation will map to an array of resources).
+	fn embed(rel: &str, resource: @HalBuilder)
+	{
+		self.embedded += ~[(rel.to_unique(), resource.to_json())];
+	}
+
+	/// Returns the resource as a JSON object with _links first, then the properties, and then _embedded.
+	fn to_json() -> Json
+	{
+		let mut members = ~[];
+		if vec::is_not_empty(self.links)
+		{
+			let links = do group(self.links) |link|
+			{
+				if link.templated
+				{
+					Object(~[(~"href", String(copy link.href)), (~"templated", Boolean(true))])
+				}
+				else
+				{
+					Object(~[(~"href", String(copy link.href))])
+				}
+			};
+			vec::push(&mut members, (~"_links", links));
+		}
+
+		members += copy self.properties;
+
+		if vec::is_not_empty(self.embedded)
+		{
+			vec::push(&mut members, (~"_embedded", group(self.embedded, |value| {copy *value})));
+		}
+		Object(members)
+	}
+
+	/// Returns a response whose body is the resource.
+	fn to_response(response: &Response, code: status::Status) -> Response
+	{
+		let response = json::json_response(response, code, &self.to_json());
+		response.headers.set_content_type(fmt!("%s; charset=UTF-8", hal_mime_type));
+		response
+	}
+}
+
+// Returns an object mapping each name to its value (or an array of values if the name is used more than once).
+priv fn group<T>(items: &[(~str, T)], to_json: fn (&T) -> Json) -> Json
+{
+	let mut names = ~[];
+	for vec::each(items) |item| {if !vec::contains(names, &item.first()) {vec::push(&mut names, item.first())}};
+
+	Object(do vec::map(names)
+	|name|
+	{
+		let values = do vec::filter_map(items) |item|
+		{
+			match *item
+			{
+				(ref n, ref value) if n == name => option::Some(to_json(value)),
+				_ => option::None,
+			}
+		};
+		if vec::len(values) == 1u {(copy *name, copy values[0])} else {(copy *name, List(values))}
+	})
+}
+
+#[test]
+fn hal_resource()
+{
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/orders/{id}<application/hal+json>", ~"order"), (~"GET", ~"/customers/{id}", ~"customer")],
+		..initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	let item = HalBuilder();
+	item.add_route_link(&iconfig, ~"self", ~"order", ~[(~"id", ~"10")]);
+	item.add_property(~"total", Number(30.0));
+
+	let resource = HalBuilder();
+	resource.add_link(~"self", ~"/orders");
+	resource.add_route_link(&iconfig, ~"find", ~"order", ~[]);
+	assert resource.add_route_link(&iconfig, ~"find", ~"missing", ~[]).is_err();
+	resource.add_property(~"count", Number(2.0));
+	resource.embed(~"orders", item);
+	resource.embed(~"orders", item);
+
+	let expected = ~"{\"_links\":{\"self\":{\"href\":\"/orders\"},\"find\":{\"href\":\"/orders/{id}\",\"templated\":true}}," +
+		~"\"count\":2," +
+		~"\"_embedded\":{\"orders\":[{\"_links\":{\"self\":{\"href\":\"/orders/10\"}},\"total\":30},{\"_links\":{\"self\":{\"href\":\"/orders/10\"}},\"total\":30}]}}";
+	assert utils::check_strs(resource.to_json().to_str(), expected);
+}
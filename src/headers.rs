@@ -0,0 +1,401 @@
+//! Collection type for HTTP headers.
+
+/// Ordered multimap of header names to values.
+///
+/// Names are compared case-insensitively (see section 4.2) but retain the case they were added with.
+/// Entries are kept in the order they were added which is also the order in which they are written
+/// to the client. Headers that may appear more than once (e.g. Set-Cookie) are stored as separate
+/// entries.
+///
+/// Like std::map::HashMap this is a boxed type so views can add headers to a response via a
+/// borrowed pointer.
+pub struct HeaderList
+{
+	priv mut entries: ~[(~str, ~str)],
+}
+
+pub type Headers = @HeaderList;
+
+/// Returns a new empty header collection.
+pub fn Headers() -> Headers
+{
+	@HeaderList {entries: ~[]}
+}
+
+/// Returns a header collection containing items (which may have duplicate names).
+pub fn headers_from(items: &[(~str, ~str)]) -> Headers
+{
+	let headers = Headers();
+	for vec::each(items)
+	|item|
+	{
+		match *item
+		{
+			(ref name, ref value) =>
+			{
+				headers.add(*name, *value);
+			}
+		}
+	}
+	headers
+}
+
+pub impl HeaderList
+{
+	/// Returns the number of entries (duplicate names are counted separately).
+	pure fn len() -> uint
+	{
+		vec::len(self.entries)
+	}
+
+	pure fn contains_key(name: &str) -> bool
+	{
+		vec::any(self.entries, |e| {same_name(e.first(), name)})
+	}
+
+	/// Returns the value for the first entry named name.
+	pure fn find(name: &str) -> Option<~str>
+	{
+		match vec::find(self.entries, |e| {same_name(e.first(), name)})
+		{
+			option::Some((_, ref value)) =>
+			{
+				option::Some(copy *value)
+			}
+			option::None =>
+			{
+				option::None
+			}
+		}
+	}
+
+	/// Returns the value for the first entry named name or fails if there is no such entry.
+	pure fn get(name: &str) -> ~str
+	{
+		match self.find(name)
+		{
+			option::Some(value) =>
+			{
+				value
+			}
+			option::None =>
+			{
+				fail fmt!("Failed to find header %s", name);
+			}
+		}
+	}
+
+	/// Returns the values for all the entries named name in the order they were added.
+	pure fn get_all(name: &str) -> ~[~str]
+	{
+		do vec::filter_map(self.entries)
+		|e|
+		{
+			if same_name(e.first(), name) {option::Some(e.second())} else {option::None}
+		}
+	}
+
+	/// Returns the comma separated elements of all the entries named name, e.g. "no-cache, no-store"
+	/// will return ~[~"no-cache", ~"no-store"]. This should only be used for headers whose grammar
+	/// is a comma separated list (see section 4.2).
+	pure fn get_list(name: &str) -> ~[~str]
+	{
+		let mut result = ~[];
+		for vec::each(self.get_all(name))
+		|value|
+		{
+			for vec::each(str::split_char(*value, ','))
+			|element|
+			{
+				let element = str::trim(*element);
+				if str::is_not_empty(element)
+				{
+					vec::push(&mut result, element);
+				}
+			}
+		}
+		result
+	}
+
+	/// Replaces all the entries named name with a single entry (or adds an entry if there
+	/// was no existing entry).
+	fn set(name: &str, value: &str)
+	{
+		match vec::position(self.entries, |e| {same_name(e.first(), name)})
+		{
+			option::Some(i) =>
+			{
+				let mut entries = ~[];
+				for vec::eachi(self.entries)
+				|j, e|
+				{
+					if j == i
+					{
+						vec::push(&mut entries, (name.to_unique(), value.to_unique()));
+					}
+					else if !same_name(e.first(), name)
+					{
+						vec::push(&mut entries, copy *e);
+					}
+				}
+				self.entries = entries;
+			}
+			option::None =>
+			{
+				self.add(name, value);
+			}
+		}
+	}
+
+	/// Adds a new entry even if there is already one named name.
+	fn add(name: &str, value: &str)
+	{
+		self.entries += ~[(name.to_unique(), value.to_unique())];
+	}
+
+	/// Adds an element to a comma separated list header (like Vary or Allow) unless it is already present.
+	fn add_to_list(name: &str, element: &str)
+	{
+		let lower = str::to_lower(element);
+		if !vec::any(self.get_list(name), |e| {str::to_lower(*e) == lower})
+		{
+			match self.find(name)
+			{
+				option::Some(value) if str::is_not_empty(str::trim(value)) =>
+				{
+					self.set(name, value + ~", " + element);
+				}
+				_ =>
+				{
+					self.set(name, element);
+				}
+			}
+		}
+	}
+
+	fn remove(name: &str)
+	{
+		self.entries = vec::filter(self.entries, |e| {!same_name(e.first(), name)});
+	}
+
+	/// Calls block with the name and value of each entry in the order they were added.
+	pure fn each(block: fn(name: &str, value: &str) -> bool)
+	{
+		for vec::each(self.entries)
+		|e|
+		{
+			match *e
+			{
+				(ref name, ref value) =>
+				{
+					if !block(*name, *value)
+					{
+						break;
+					}
+				}
+			}
+		}
+	}
+
+	// ---- Typed accessors for common headers ------------------------------
+	/// Returns the mime type from Content-Type without any parameters, e.g. "text/html".
+	pure fn content_type() -> Option<~str>
+	{
+		do self.find(~"Content-Type").map |value|
+		{
+			match str::find_char(*value, ';')
+			{
+				option::Some(i) => str::to_lower(str::trim(value.slice(0, i))),
+				option::None => str::to_lower(str::trim(*value)),
+			}
+		}
+	}
+
+	/// Returns the charset parameter from Content-Type, e.g. "UTF-8".
+	pure fn content_charset() -> Option<~str>
+	{
+		match self.find(~"Content-Type")
+		{
+			option::Some(value) =>
+			{
+				let params = str::split_char(value, ';');
+				for vec::each(params)
+				|param|
+				{
+					let param = str::trim(*param);
+					if str::starts_with(str::to_lower(param), "charset=")
+					{
+						return option::Some(str::trim_chars(param.slice(8, param.len()), ~['"']));
+					}
+				}
+				option::None
+			}
+			option::None =>
+			{
+				option::None
+			}
+		}
+	}
+
+	fn set_content_type(mime_type: &str)
+	{
+		self.set(~"Content-Type", mime_type);
+	}
+
+	/// Returns None if Content-Length is missing or malformed.
+	pure fn content_length() -> Option<uint>
+	{
+		do self.find(~"Content-Length").chain |value| {uint::from_str(str::trim(value))}
+	}
+
+	fn set_content_length(length: uint)
+	{
+		self.set(~"Content-Length", uint::to_str(length, 10u));
+	}
+
+	/// Returns the Cache-Control directives, e.g. ~[~"max-age=60", ~"public"]. Directive names are lower cased.
+	pure fn cache_control() -> ~[~str]
+	{
+		do vec::map(self.get_list(~"Cache-Control"))
+		|directive|
+		{
+			match str::find_char(*directive, '=')
+			{
+				option::Some(i) => str::to_lower(directive.slice(0, i)) + directive.slice(i, directive.len()),
+				option::None => str::to_lower(*directive),
+			}
+		}
+	}
+
+	/// Returns true if Cache-Control includes the named directive (e.g. "no-cache" or "max-age").
+	pure fn has_cache_directive(name: &str) -> bool
+	{
+		let name = str::to_lower(name);
+		do vec::any(self.cache_control())
+		|directive|
+		{
+			*directive == name || str::starts_with(*directive, name + ~"=")
+		}
+	}
+
+	fn set_cache_control(directives: &[~str])
+	{
+		self.set(~"Cache-Control", str::connect(directives, ~", "));
+	}
+
+	/// Returns the Date as seconds since the epoch (or None if it is missing or malformed).
+	pure fn date() -> Option<i64>
+	{
+		self.find_date(~"Date")
+	}
+
+	fn set_date(secs: i64)
+	{
+		self.set(~"Date", http_date::format_http_date(secs));
+	}
+
+	pure fn last_modified() -> Option<i64>
+	{
+		self.find_date(~"Last-Modified")
+	}
+
+	fn set_last_modified(secs: i64)
+	{
+		self.set(~"Last-Modified", http_date::format_http_date(secs));
+	}
+
+	/// Note that section 5.3 of RFC 7234 says that invalid dates (like "0") mean already expired.
+	pure fn expires() -> Option<i64>
+	{
+		self.find_date(~"Expires")
+	}
+
+	fn set_expires(secs: i64)
+	{
+		self.set(~"Expires", http_date::format_http_date(secs));
+	}
+
+	pure fn if_modified_since() -> Option<i64>
+	{
+		self.find_date(~"If-Modified-Since")
+	}
+
+	/// Adds a Set-Cookie header for cookie replacing any earlier Set-Cookie header with the same
+	/// cookie name, domain, and path (other Set-Cookie headers are retained).
+	fn set_cookie(cookie: &cookies::Cookie)
+	{
+		self.entries = vec::filter(self.entries, |e| {!same_name(e.first(), ~"Set-Cookie") || !cookies::same_cookie(e.second(), cookie)});
+		self.add(~"Set-Cookie", cookie.to_header());
+	}
+
+	priv pure fn find_date(name: &str) -> Option<i64>
+	{
+		do self.find(name).chain |value| {http_date::parse_http_date(value)}
+	}
+}
+
+priv pure fn same_name(a: &str, b: &str) -> bool
+{
+	str::len(a) == str::len(b) && str::to_lower(a) == str::to_lower(b)
+}
+
+#[test]
+fn case_insensitive()
+{
+	let headers = headers_from(~[(~"content-type", ~"text/html"), (~"X-Custom", ~"1")]);
+	assert headers.contains_key(~"Content-Type");
+	assert headers.find(~"CONTENT-TYPE") == option::Some(~"text/html");
+	assert headers.find(~"x-custom") == option::Some(~"1");
+	assert headers.find(~"x-missing") == option::None;
+}
+
+#[test]
+fn multiple_values()
+{
+	let headers = Headers();
+	headers.add(~"Set-Cookie", ~"a=1");
+	headers.add(~"Server", ~"unit test");
+	headers.add(~"set-cookie", ~"b=2");
+	assert utils::check_vectors(headers.get_all(~"Set-Cookie"), ~[~"a=1", ~"b=2"]);
+	assert headers.len() == 3u;
+
+	headers.set(~"Set-Cookie", ~"c=3");
+	assert utils::check_vectors(headers.get_all(~"Set-Cookie"), ~[~"c=3"]);
+
+	let mut names = ~[];
+	for headers.each |name, _value| {vec::push(&mut names, name.to_unique())};
+	assert utils::check_vectors(names, ~[~"Set-Cookie", ~"Server"]);
+
+	headers.remove(~"SERVER");
+	assert headers.len() == 1u;
+}
+
+#[test]
+fn list_headers()
+{
+	let headers = headers_from(~[(~"Vary", ~"Accept-Encoding")]);
+	headers.add_to_list(~"Vary", ~"Accept-Language");
+	headers.add_to_list(~"Vary", ~"accept-encoding");
+	assert utils::check_vectors(headers.get_list(~"vary"), ~[~"Accept-Encoding", ~"Accept-Language"]);
+}
+
+#[test]
+fn typed_headers()
+{
+	let headers = headers_from(~[(~"Content-Type", ~"Text/HTML; charset=\"UTF-8\""), (~"Cache-Control", ~"Max-Age=60, public")]);
+	assert headers.content_type() == option::Some(~"text/html");
+	assert headers.content_charset() == option::Some(~"UTF-8");
+	assert headers.content_length() == option::None;
+	assert headers.has_cache_directive(~"max-age");
+	assert !headers.has_cache_directive(~"no-cache");
+
+	headers.set_content_length(42u);
+	assert headers.content_length() == option::Some(42u);
+	headers.set_cache_control(~[~"no-cache"]);
+	assert utils::check_vectors(headers.cache_control(), ~[~"no-cache"]);
+
+	headers.set_date(784111777i64);
+	assert headers.find(~"date") == option::Some(~"Sun, 06 Nov 1994 08:49:37 GMT");
+	assert headers.date() == option::Some(784111777i64);
+	assert headers.expires() == option::None;
+}
@@ -0,0 +1,119 @@
+//! Escaping of values inserted into html templates.
+//
+// Templates rendered as html have every string in their context escaped so that values
+// which came from clients (e.g. request-path or route matches) cannot inject markup.
+// Views that want to insert markup must explicitly mark it as safe using raw_html.
+
+/// Returns text with the characters which are special in html text and quoted attribute
+/// values replaced with character references.
+pub fn escape_html(text: &str) -> ~str
+{
+	let mut result = ~"";
+	str::reserve(&mut result, str::len(text));
+	for str::each_char(text)
+	|ch|
+	{
+		match ch
+		{
+			'&' => result += "&amp;",
+			'<' => result += "&lt;",
+			'>' => result += "&gt;",
+			'"' => result += "&quot;",
+			'\'' => result += "&#39;",
+			_ => str::push_char(&mut result, ch),
+		}
+	}
+	result
+}
+
+/// Returns a context value which will be inserted into html templates as is.
+///
+/// Only use this for markup generated by the server: never for text from the request.
+pub fn raw_html(markup: ~str) -> mustache::Data
+{
+	let wrapper = std::map::HashMap();
+	wrapper.insert(@~"rwebserve-raw-html", mustache::Str(@markup));
+	mustache::Map(wrapper)
+}
+
+/// Returns true if a response with the Content-Type mime_type should be escaped.
+pub fn is_html_type(mime_type: &str) -> bool
+{
+	str::eq_slice(mime_type, "text/html") || str::eq_slice(mime_type, "application/xhtml+xml")
+}
+
+/// Returns a copy of context with all of the strings escaped (except those created with raw_html).
+pub fn escape_context(context: HashMap<@~str, mustache::Data>) -> HashMap<@~str, mustache::Data>
+{
+	let result = std::map::HashMap();
+	for context.each
+	|key, value|
+	{
+		result.insert(key, escape_data(&value));
+	}
+	result
+}
+
+priv fn escape_data(data: &mustache::Data) -> mustache::Data
+{
+	match *data
+	{
+		mustache::Str(s) =>
+		{
+			mustache::Str(@escape_html(*s))
+		}
+		mustache::Vec(items) =>
+		{
+			mustache::Vec(@vec::map(*items, |item| {escape_data(item)}))
+		}
+		mustache::Map(table) =>
+		{
+			match raw_markup(table)
+			{
+				option::Some(markup) => mustache::Str(markup),
+				option::None => mustache::Map(escape_context(table)),
+			}
+		}
+		_ =>
+		{
+			copy *data
+		}
+	}
+}
+
+priv fn raw_markup(table: HashMap<@~str, mustache::Data>) -> Option<@~str>
+{
+	if table.size() == 1u
+	{
+		match table.find(@~"rwebserve-raw-html")
+		{
+			option::Some(mustache::Str(markup)) => return option::Some(markup),
+			_ => {}
+		}
+	}
+	option::None
+}
+
+#[test]
+fn escaping()
+{
+	assert utils::check_strs(escape_html(~"<a href=\"x\">Tom & Jerry's</a>"), ~"&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+	assert utils::check_strs(escape_html(~"plain text"), ~"plain text");
+}
+
+#[test]
+fn escaped_context()
+{
+	let nested = std::map::HashMap();
+	nested.insert(@~"name", mustache::Str(@~"<b>"));
+
+	let context = std::map::HashMap();
+	context.insert(@~"path", mustache::Str(@~"/<script>"));
+	context.insert(@~"menu", raw_html(~"<ul></ul>"));
+	context.insert(@~"user", mustache::Map(nested));
+	context.insert(@~"admin", mustache::Bool(true));
+
+	let context = escape_context(context);
+	let render = |text: &str| {mustache::compile_str(text).render_data(mustache::Map(context))};
+	assert utils::check_strs(render(~"{{path}} {{menu}} {{#user}}{{name}}{{/user}}"), ~"/&lt;script&gt; <ul></ul> &lt;b&gt;");
+}
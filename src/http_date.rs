@@ -0,0 +1,214 @@
+//! Formatting and parsing of HTTP-date values (see section 7.1.1.1 of RFC 7231).
+//
+// Times are represented as seconds since the Unix epoch (like std::time::Timespec.sec).
+
+/// Caches the formatted current time so that we only need to format the Date header once a second.
+pub struct DateCache
+{
+	priv mut secs: i64,
+	priv mut text: ~str,
+}
+
+pub fn DateCache() -> @DateCache
+{
+	@DateCache {secs: -1i64, text: ~""}
+}
+
+pub impl DateCache
+{
+	/// Returns the current time formatted as an IMF-fixdate.
+	fn now() -> ~str
+	{
+		let secs = std::time::get_time().sec;
+		if secs != self.secs
+		{
+			self.text = format_http_date(secs);
+			self.secs = secs;
+		}
+		copy self.text
+	}
+}
+
+/// Returns a string like "Sun, 06 Nov 1994 08:49:37 GMT".
+///
+/// This is the preferred format: the obsolete formats should only be used when parsing.
+pub fn format_http_date(secs: i64) -> ~str
+{
+	let days = floor_div(secs, 86400i64);
+	let seconds = secs - days*86400i64;
+	let (year, month, day) = civil_from_days(days);
+	let weekday = ((days % 7i64) + 11i64) % 7i64;		// 1970-01-01 was a Thursday
+
+	fmt!("%s, %02d %s %04d %02d:%02d:%02d GMT",
+		day_names()[weekday as uint], day as int, month_names()[(month - 1i64) as uint], year as int,
+		(seconds/3600i64) as int, ((seconds % 3600i64)/60i64) as int, (seconds % 60i64) as int)
+}
+
+/// Returns a string like "06/Nov/1994:08:49:37 +0000" (the format used by Common Log Format files).
+pub fn format_log_date(secs: i64) -> ~str
+{
+	let days = floor_div(secs, 86400i64);
+	let seconds = secs - days*86400i64;
+	let (year, month, day) = civil_from_days(days);
+
+	fmt!("%02d/%s/%04d:%02d:%02d:%02d +0000",
+		day as int, month_names()[(month - 1i64) as uint], year as int,
+		(seconds/3600i64) as int, ((seconds % 3600i64)/60i64) as int, (seconds % 60i64) as int)
+}
+
+/// Parses an IMF-fixdate or one of the obsolete RFC 850 and asctime formats.
+///
+/// Returns None if the date is malformed. Note that section 7.1.1.1 says that recipients
+/// should treat malformed dates as if they were not present.
+pub fn parse_http_date(text: &str) -> Option<i64>
+{
+	let text = str::trim(text);
+	let parts = str::split_char_nonempty(text, ' ');
+
+	if vec::len(parts) == 6u && str::ends_with(parts[0], ",")
+	{
+		// IMF-fixdate: Sun, 06 Nov 1994 08:49:37 GMT
+		if parts[5] != ~"GMT"
+		{
+			return option::None;
+		}
+		to_secs(parts[3], parts[2], parts[1], parts[4], false)
+	}
+	else if vec::len(parts) == 4u && str::ends_with(parts[0], ",")
+	{
+		// RFC 850: Sunday, 06-Nov-94 08:49:37 GMT
+		let date = str::split_char(parts[1], '-');
+		if parts[3] != ~"GMT" || vec::len(date) != 3u
+		{
+			return option::None;
+		}
+		to_secs(date[2], date[1], date[0], parts[2], true)
+	}
+	else if vec::len(parts) == 5u
+	{
+		// asctime: Sun Nov  6 08:49:37 1994
+		to_secs(parts[4], parts[1], parts[2], parts[3], false)
+	}
+	else
+	{
+		option::None
+	}
+}
+
+/// Returns true if a resource last modified at last_modified can be considered unchanged
+/// given an If-Modified-Since header value (see section 3.3 of RFC 7232).
+pub fn is_unmodified_since(if_modified_since: &str, last_modified: i64) -> bool
+{
+	match parse_http_date(if_modified_since)
+	{
+		option::Some(since) => last_modified <= since,
+		option::None => false,
+	}
+}
+
+priv pure fn day_names() -> ~[~str]
+{
+	~[~"Sun", ~"Mon", ~"Tue", ~"Wed", ~"Thu", ~"Fri", ~"Sat"]
+}
+
+priv pure fn month_names() -> ~[~str]
+{
+	~[~"Jan", ~"Feb", ~"Mar", ~"Apr", ~"May", ~"Jun", ~"Jul", ~"Aug", ~"Sep", ~"Oct", ~"Nov", ~"Dec"]
+}
+
+priv fn to_secs(year: &str, month: &str, day: &str, time: &str, two_digit_year: bool) -> Option<i64>
+{
+	let year = match int::from_str(year) {option::Some(y) => y as i64, option::None => return option::None};
+	let year =
+		if two_digit_year
+		{
+			// Section 7.1.1.1 says that years which appear to be more than 50 years in
+			// the future are in the past. We use a fixed pivot to keep things simple.
+			if year < 70i64 {year + 2000i64} else {year + 1900i64}
+		}
+		else
+		{
+			year
+		};
+	let month = match vec::position_elem(month_names(), &month.to_unique()) {option::Some(m) => (m + 1u) as i64, option::None => return option::None};
+	let day = match int::from_str(day) {option::Some(d) if d >= 1 && d <= 31 => d as i64, _ => return option::None};
+
+	let hms = str::split_char(time, ':');
+	if vec::len(hms) != 3u
+	{
+		return option::None;
+	}
+	let hour = match int::from_str(hms[0]) {option::Some(h) if h >= 0 && h < 24 => h as i64, _ => return option::None};
+	let minute = match int::from_str(hms[1]) {option::Some(m) if m >= 0 && m < 60 => m as i64, _ => return option::None};
+	let second = match int::from_str(hms[2]) {option::Some(s) if s >= 0 && s <= 60 => s as i64, _ => return option::None};	// 60 is a leap second
+
+	option::Some(days_from_civil(year, month, day)*86400i64 + hour*3600i64 + minute*60i64 + second)
+}
+
+priv pure fn floor_div(x: i64, y: i64) -> i64
+{
+	if x >= 0i64 {x/y} else {(x - y + 1i64)/y}
+}
+
+// These use the algorithms from http://howardhinnant.github.io/date_algorithms.html
+priv pure fn days_from_civil(year: i64, month: i64, day: i64) -> i64
+{
+	let y = if month <= 2i64 {year - 1i64} else {year};
+	let era = floor_div(y, 400i64);
+	let yoe = y - era*400i64;
+	let mp = if month > 2i64 {month - 3i64} else {month + 9i64};
+	let doy = (153i64*mp + 2i64)/5i64 + day - 1i64;
+	let doe = yoe*365i64 + yoe/4i64 - yoe/100i64 + doy;
+	era*146097i64 + doe - 719468i64
+}
+
+priv pure fn civil_from_days(days: i64) -> (i64, i64, i64)
+{
+	let z = days + 719468i64;
+	let era = floor_div(z, 146097i64);
+	let doe = z - era*146097i64;
+	let yoe = (doe - doe/1460i64 + doe/36524i64 - doe/146096i64)/365i64;
+	let doy = doe - (365i64*yoe + yoe/4i64 - yoe/100i64);
+	let mp = (5i64*doy + 2i64)/153i64;
+	let day = doy - (153i64*mp + 2i64)/5i64 + 1i64;
+	let month = if mp < 10i64 {mp + 3i64} else {mp - 9i64};
+	let year = yoe + era*400i64 + if month <= 2i64 {1i64} else {0i64};
+	(year, month, day)
+}
+
+#[test]
+fn format_dates()
+{
+	assert utils::check_strs(format_http_date(784111777i64), ~"Sun, 06 Nov 1994 08:49:37 GMT");
+	assert utils::check_strs(format_http_date(0i64), ~"Thu, 01 Jan 1970 00:00:00 GMT");
+	assert utils::check_strs(format_http_date(951782400i64), ~"Tue, 29 Feb 2000 00:00:00 GMT");
+	assert utils::check_strs(format_log_date(784111777i64), ~"06/Nov/1994:08:49:37 +0000");
+}
+
+#[test]
+fn parse_dates()
+{
+	assert parse_http_date(~"Sun, 06 Nov 1994 08:49:37 GMT") == option::Some(784111777i64);
+	assert parse_http_date(~"Sunday, 06-Nov-94 08:49:37 GMT") == option::Some(784111777i64);
+	assert parse_http_date(~"Sun Nov  6 08:49:37 1994") == option::Some(784111777i64);
+	assert parse_http_date(~"Tue, 29 Feb 2000 00:00:00 GMT") == option::Some(951782400i64);
+}
+
+#[test]
+fn parse_bad_dates()
+{
+	assert parse_http_date(~"").is_none();
+	assert parse_http_date(~"Sun, 06 Nov 1994 08:49:37 PST").is_none();
+	assert parse_http_date(~"Sun, 06 Foo 1994 08:49:37 GMT").is_none();
+	assert parse_http_date(~"Sun, 06 Nov 1994 25:49:37 GMT").is_none();
+	assert parse_http_date(~"yesterday").is_none();
+}
+
+#[test]
+fn modified_since()
+{
+	assert is_unmodified_since(~"Sun, 06 Nov 1994 08:49:37 GMT", 784111777i64);
+	assert is_unmodified_since(~"Sun, 06 Nov 1994 08:49:37 GMT", 784111700i64);
+	assert !is_unmodified_since(~"Sun, 06 Nov 1994 08:49:37 GMT", 784111778i64);
+	assert !is_unmodified_since(~"garbage", 0i64);
+}
@@ -15,9 +15,12 @@ pub struct HttpRequest
 	pub method: ~str,				// per 5.1.1 these are case sensitive
 	pub major_version: int,
 	pub minor_version: int,
-	pub url: ~str,
+	pub url: ~str,					// percent decoded
+	pub raw_url: ~str,				// as sent by the client
 	pub headers: ~[(~str, ~str)],		// these are not case sensitive so we lower case them
 	pub body: ~str,					// set elsewhere
+	pub parse_time: u64,			// microseconds spent parsing the headers (set elsewhere)
+	pub id: ~str,					// see tracing::request_id (set elsewhere, empty to generate one)
 }
 
 // We return a closure so that we can build the parser just once.
@@ -132,7 +135,7 @@ priv fn request_parser() -> Parser<HttpRequest>
 		|a1, h, _a2|
 		{
 			let (n, u, (v1, v2)) = a1;
-			result::Ok(HttpRequest {method: *n, major_version: v1, minor_version: v2, url: decode(*u), headers: *h, body: ~""})};
+			result::Ok(HttpRequest {method: *n, major_version: v1, minor_version: v2, url: decode(*u), raw_url: copy *u, headers: *h, body: ~"", parse_time: 0u64, id: ~""})};
 	
 	return request;
 }
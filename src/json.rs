@@ -0,0 +1,536 @@
+//! JSON values along with a parser and serializer (see RFC 8259).
+
+/// A JSON value. Objects keep their members in the order they were added (which is also
+/// the order they are serialized in). Unlike std::json this is sendable.
+pub enum Json
+{
+	Null,
+	Boolean(bool),
+	Number(float),
+	String(~str),
+	List(~[Json]),
+	Object(~[(~str, Json)]),
+}
+
+pub impl Json : cmp::Eq
+{
+	pure fn eq(rhs: &Json) -> bool
+	{
+		match self
+		{
+			Null => match *rhs {Null => true, _ => false},
+			Boolean(a) => match *rhs {Boolean(b) => a == b, _ => false},
+			Number(a) => match *rhs {Number(b) => a == b, _ => false},
+			String(ref a) => match *rhs {String(ref b) => *a == *b, _ => false},
+			List(ref a) => match *rhs {List(ref b) => *a == *b, _ => false},
+			Object(ref a) => match *rhs {Object(ref b) => *a == *b, _ => false},
+		}
+	}
+
+	pure fn ne(rhs: &Json) -> bool
+	{
+		!self.eq(rhs)
+	}
+}
+
+/// Returns compact JSON text, e.g. {"name":"Bob","ids":[1,2]}.
+pub impl Json : ToStr
+{
+	pure fn to_str() -> ~str
+	{
+		match self
+		{
+			Null => ~"null",
+			Boolean(value) => if value {~"true"} else {~"false"},
+			Number(value) => number_to_str(value),
+			String(ref value) => quote_str(*value),
+			List(ref items) =>
+			{
+				~"[" + str::connect(vec::map(*items, |item| {item.to_str()}), ~",") + ~"]"
+			}
+			Object(ref members) =>
+			{
+				let members = do vec::map(*members) |member| {quote_str(member.first()) + ~":" + member.second().to_str()};
+				~"{" + str::connect(members, ~",") + ~"}"
+			}
+		}
+	}
+}
+
+pub impl Json
+{
+	/// Returns the value of the named object member (None if this isn't an object or has no such member).
+	pure fn find(name: &str) -> Option<Json>
+	{
+		match self
+		{
+			Object(ref members) =>
+			{
+				match vec::find(*members, |m| {str::eq_slice(m.first(), name)})
+				{
+					option::Some((_, move value)) => option::Some(value),
+					option::None => option::None,
+				}
+			}
+			_ =>
+			{
+				option::None
+			}
+		}
+	}
+
+	pure fn as_str() -> Option<~str>
+	{
+		match self {String(ref value) => option::Some(copy *value), _ => option::None}
+	}
+
+	pure fn as_float() -> Option<float>
+	{
+		match self {Number(value) => option::Some(value), _ => option::None}
+	}
+
+	pure fn as_bool() -> Option<bool>
+	{
+		match self {Boolean(value) => option::Some(value), _ => option::None}
+	}
+}
+
+/// Parses JSON text. On errors returns a message including the offset of the problem.
+pub fn parse_json(text: &str) -> result::Result<Json, ~str>
+{
+	let parser = Parser {text: text.to_unique(), index: 0u};
+	do result::chain(parser.parse_value(0u))
+	|value|
+	{
+		parser.skip_space();
+		if parser.index < str::len(parser.text)
+		{
+			result::Err(fmt!("expected end of text at offset %?", parser.index))
+		}
+		else
+		{
+			result::Ok(value)
+		}
+	}
+}
+
+/// Parses the request body as JSON.
+///
+/// If the Content-Type isn't JSON a 415 response is returned. If the body isn't valid JSON a 400
+/// response is returned. In both cases the view can simply return the response.
+pub impl Request
+{
+	fn json_body(response: &Response) -> result::Result<Json, Response>
+	{
+		let mime_type = self.headers.content_type().get_default(~"");
+		if !is_json_type(mime_type)
+		{
+			response.headers.set(~"Accept", ~"application/json");
+			let mesg = fmt!("expected application/json but the Content-Type was '%s'", mime_type);
+			return result::Err(json_response(response, status::UnsupportedMediaType, &Object(~[(~"error", String(mesg))])));
+		}
+
+		match parse_json(self.body)
+		{
+			result::Ok(move value) =>
+			{
+				result::Ok(value)
+			}
+			result::Err(move mesg) =>
+			{
+				result::Err(json_response(response, status::BadRequest, &Object(~[(~"error", String(mesg))])))
+			}
+		}
+	}
+}
+
+/// Returns a response with the specified status whose body is value.
+pub fn json_response(response: &Response, code: status::Status, value: &Json) -> Response
+{
+	response.headers.set_content_type(~"application/json; charset=UTF-8");
+	Response {status: code, body: StringBody(@value.to_str()), template: ~"", context: std::map::HashMap(), ..*response}
+}
+
+/// Returns true for application/json and types like application/hal+json.
+pub fn is_json_type(mime_type: &str) -> bool
+{
+	let mime_type = str::to_lower(mime_type);
+	mime_type == ~"application/json" || (str::starts_with(mime_type, "application/") && str::ends_with(mime_type, "+json"))
+}
+
+// ---- Internal items ------------------------------------------------------
+priv struct Parser
+{
+	text: ~str,
+	mut index: uint,
+}
+
+priv impl Parser
+{
+	fn parse_value(depth: uint) -> result::Result<Json, ~str>
+	{
+		// Guard against stack overflows from bodies like [[[[[[...
+		if depth > 512u
+		{
+			return self.error(~"values are nested too deeply");
+		}
+
+		self.skip_space();
+		match self.peek()
+		{
+			option::Some('n') => self.parse_literal(~"null", Null),
+			option::Some('t') => self.parse_literal(~"true", Boolean(true)),
+			option::Some('f') => self.parse_literal(~"false", Boolean(false)),
+			option::Some('"') => self.parse_string().map(|s| {String(copy **s)}),
+			option::Some('[') => self.parse_list(depth),
+			option::Some('{') => self.parse_object(depth),
+			option::Some(ch) if ch == '-' || char::is_digit(ch) => self.parse_number(),
+			option::Some(ch) => self.error(fmt!("unexpected '%c'", ch)),
+			option::None => self.error(~"unexpected end of text"),
+		}
+	}
+
+	fn parse_literal(literal: ~str, value: Json) -> result::Result<Json, ~str>
+	{
+		if str::find_str_from(self.text, literal, self.index) == option::Some(self.index)
+		{
+			self.index += str::len(literal);
+			result::Ok(value)
+		}
+		else
+		{
+			self.error(fmt!("expected %s", literal))
+		}
+	}
+
+	fn parse_number() -> result::Result<Json, ~str>
+	{
+		let start = self.index;
+		if self.peek() == option::Some('-')
+		{
+			self.index += 1u;
+		}
+		if !self.skip_digits()
+		{
+			return self.error(~"expected a digit");
+		}
+		if self.peek() == option::Some('.')
+		{
+			self.index += 1u;
+			if !self.skip_digits()
+			{
+				return self.error(~"expected a digit after the decimal point");
+			}
+		}
+		if self.peek() == option::Some('e') || self.peek() == option::Some('E')
+		{
+			self.index += 1u;
+			if self.peek() == option::Some('+') || self.peek() == option::Some('-')
+			{
+				self.index += 1u;
+			}
+			if !self.skip_digits()
+			{
+				return self.error(~"expected a digit in the exponent");
+			}
+		}
+
+		match float::from_str(self.text.slice(start, self.index))
+		{
+			option::Some(value) => result::Ok(Number(value)),
+			option::None => result::Err(fmt!("bad number at offset %?", start)),
+		}
+	}
+
+	fn parse_string() -> result::Result<@~str, ~str>
+	{
+		self.index += 1u;		// skip the opening quote
+		let mut result = ~"";
+		loop
+		{
+			match self.next()
+			{
+				option::Some('"') =>
+				{
+					return result::Ok(@result);
+				}
+				option::Some('\\') =>
+				{
+					match self.next()
+					{
+						option::Some('"') => str::push_char(&mut result, '"'),
+						option::Some('\\') => str::push_char(&mut result, '\\'),
+						option::Some('/') => str::push_char(&mut result, '/'),
+						option::Some('b') => str::push_char(&mut result, '\x08'),
+						option::Some('f') => str::push_char(&mut result, '\x0c'),
+						option::Some('n') => str::push_char(&mut result, '\n'),
+						option::Some('r') => str::push_char(&mut result, '\r'),
+						option::Some('t') => str::push_char(&mut result, '\t'),
+						option::Some('u') =>
+						{
+							match self.parse_escape()
+							{
+								result::Ok(ch) => str::push_char(&mut result, ch),
+								result::Err(move mesg) => return result::Err(mesg),
+							}
+						}
+						_ =>
+						{
+							return self.error(~"bad escape sequence");
+						}
+					}
+				}
+				option::Some(ch) if ch < ' ' =>
+				{
+					return self.error(~"control characters must be escaped");
+				}
+				option::Some(ch) =>
+				{
+					str::push_char(&mut result, ch);
+				}
+				option::None =>
+				{
+					return self.error(~"unterminated string");
+				}
+			}
+		}
+	}
+
+	// Parses the XXXX in \uXXXX (and a following low surrogate if XXXX is a high surrogate).
+	fn parse_escape() -> result::Result<char, ~str>
+	{
+		let high = match self.parse_hex() {option::Some(n) => n, option::None => return self.error(~"bad \\u escape")};
+		if high >= 0xD800u && high < 0xDC00u
+		{
+			if self.next() != option::Some('\\') || self.next() != option::Some('u')
+			{
+				return self.error(~"expected a low surrogate");
+			}
+			match self.parse_hex()
+			{
+				option::Some(low) if low >= 0xDC00u && low < 0xE000u =>
+				{
+					result::Ok((0x10000u + ((high - 0xD800u) << 10u) + (low - 0xDC00u)) as char)
+				}
+				_ =>
+				{
+					self.error(~"expected a low surrogate")
+				}
+			}
+		}
+		else if high >= 0xDC00u && high < 0xE000u
+		{
+			self.error(~"unexpected low surrogate")
+		}
+		else
+		{
+			result::Ok(high as char)
+		}
+	}
+
+	fn parse_hex() -> Option<uint>
+	{
+		if self.index + 4u > str::len(self.text)
+		{
+			return option::None;
+		}
+		// Check the bytes first so that we don't slice through a multi-byte character.
+		for uint::range(self.index, self.index + 4u)
+		|i|
+		{
+			if char::to_digit(self.text[i] as char, 16u).is_none()
+			{
+				return option::None;
+			}
+		}
+		let digits = self.text.slice(self.index, self.index + 4u);
+		self.index += 4u;
+		uint::from_str_radix(digits, 16u)
+	}
+
+	fn parse_list(depth: uint) -> result::Result<Json, ~str>
+	{
+		self.index += 1u;
+		let mut items = ~[];
+		self.skip_space();
+		if self.peek() == option::Some(']')
+		{
+			self.index += 1u;
+			return result::Ok(List(items));
+		}
+
+		loop
+		{
+			match self.parse_value(depth + 1u)
+			{
+				result::Ok(move item) => vec::push(&mut items, item),
+				result::Err(move mesg) => return result::Err(mesg),
+			}
+			self.skip_space();
+			match self.next()
+			{
+				option::Some(',') => loop,
+				option::Some(']') => return result::Ok(List(items)),
+				_ => return self.error(~"expected ',' or ']'"),
+			}
+		}
+	}
+
+	fn parse_object(depth: uint) -> result::Result<Json, ~str>
+	{
+		self.index += 1u;
+		let mut members = ~[];
+		self.skip_space();
+		if self.peek() == option::Some('}')
+		{
+			self.index += 1u;
+			return result::Ok(Object(members));
+		}
+
+		loop
+		{
+			self.skip_space();
+			if self.peek() != option::Some('"')
+			{
+				return self.error(~"expected a member name");
+			}
+			let name = match self.parse_string() {result::Ok(name) => name, result::Err(move mesg) => return result::Err(mesg)};
+
+			self.skip_space();
+			if self.next() != option::Some(':')
+			{
+				return self.error(~"expected ':'");
+			}
+
+			match self.parse_value(depth + 1u)
+			{
+				result::Ok(move value) => vec::push(&mut members, (copy *name, value)),
+				result::Err(move mesg) => return result::Err(mesg),
+			}
+			self.skip_space();
+			match self.next()
+			{
+				option::Some(',') => loop,
+				option::Some('}') => return result::Ok(Object(members)),
+				_ => return self.error(~"expected ',' or '}'"),
+			}
+		}
+	}
+
+	fn skip_space()
+	{
+		while self.peek() == option::Some(' ') || self.peek() == option::Some('\t') || self.peek() == option::Some('\r') || self.peek() == option::Some('\n')
+		{
+			self.index += 1u;
+		}
+	}
+
+	fn skip_digits() -> bool
+	{
+		let start = self.index;
+		while self.peek().map_default(false, |ch| {char::is_digit(*ch)})
+		{
+			self.index += 1u;
+		}
+		self.index > start
+	}
+
+	fn peek() -> Option<char>
+	{
+		if self.index < str::len(self.text)
+		{
+			option::Some(str::char_at(self.text, self.index))
+		}
+		else
+		{
+			option::None
+		}
+	}
+
+	fn next() -> Option<char>
+	{
+		if self.index < str::len(self.text)
+		{
+			let {ch, next} = str::char_range_at(self.text, self.index);
+			self.index = next;
+			option::Some(ch)
+		}
+		else
+		{
+			option::None
+		}
+	}
+
+	fn error<T>(mesg: ~str) -> result::Result<T, ~str>
+	{
+		result::Err(fmt!("%s at offset %?", mesg, self.index))
+	}
+}
+
+// JSON has no representation for NaN or the infinities so, like JavaScript, we use null.
+priv pure fn number_to_str(value: float) -> ~str
+{
+	if !float::is_finite(value)
+	{
+		~"null"
+	}
+	else if value == float::floor(value) && float::abs(value) < 1e15
+	{
+		fmt!("%d", value as int)
+	}
+	else
+	{
+		float::to_str(value, 15u)
+	}
+}
+
+priv pure fn quote_str(text: &str) -> ~str
+{
+	let mut result = ~"\"";
+	for str::each_char(text)
+	|ch|
+	{
+		match ch
+		{
+			'"' => result += "\\\"",
+			'\\' => result += "\\\\",
+			'\n' => result += "\\n",
+			'\r' => result += "\\r",
+			'\t' => result += "\\t",
+			_ if ch < ' ' => result += fmt!("\\u%04x", ch as uint),
+			_ => unsafe {str::push_char(&mut result, ch)},
+		}
+	}
+	result + ~"\""
+}
+
+#[test]
+fn parse_values()
+{
+	assert parse_json(~" null ") == result::Ok(Null);
+	assert parse_json(~"-12.5e1") == result::Ok(Number(-125.0));
+	assert parse_json(~"\"a\\n\\u00e9\\ud83d\\ude00\"") == result::Ok(String(~"a\né\U0001f600"));
+	assert parse_json(~"[1, true, {\"x\": []}]") == result::Ok(List(~[Number(1.0), Boolean(true), Object(~[(~"x", List(~[]))])]));
+}
+
+#[test]
+fn parse_errors()
+{
+	assert utils::check_strs(result::get_err(&parse_json(~"[1, 2")), ~"expected ',' or ']' at offset 5");
+	assert parse_json(~"{\"x\" 1}").is_err();
+	assert parse_json(~"01x").is_err();
+	assert parse_json(~"\"unterminated").is_err();
+	assert parse_json(~"").is_err();
+	assert parse_json(~"\"\\u00\u00e90\"").is_err();
+	assert parse_json(~"\"\\u12\"").is_err();
+}
+
+#[test]
+fn serialize_values()
+{
+	let value = Object(~[(~"name", String(~"Bob \"B\"")), (~"ids", List(~[Number(1.0), Number(2.5)])), (~"admin", Boolean(false)), (~"boss", Null)]);
+	assert utils::check_strs(value.to_str(), ~"{\"name\":\"Bob \\\"B\\\"\",\"ids\":[1,2.5],\"admin\":false,\"boss\":null}");
+	assert parse_json(value.to_str()) == result::Ok(value);
+	assert value.find(~"ids").get().to_str() == ~"[1,2.5]";
+	assert List(~[Number(float::NaN), Number(float::infinity), Number(float::neg_infinity)]).to_str() == ~"[null,null,null]";
+}
@@ -0,0 +1,343 @@
+//! Optional request metrics which may be exposed in the Prometheus text format.
+//
+// Like the response cache and the access log the metrics are kept by a task shared by all
+// connections. Connections send it a message after each request (and with the request's timing
+// spans once the response has been written) and when connections and sse streams are opened
+// and closed. Snapshots of the current values can be requested at any time (and are served
+// from Config.metrics_path).
+use io::WriterUtil;
+
+/// Upper bounds (in microseconds) of the latency histogram buckets and the corresponding Prometheus le labels.
+pub fn latency_buckets() -> ~[(u64, ~str)]
+{
+	~[(5000u64, ~"0.005"), (10000u64, ~"0.01"), (25000u64, ~"0.025"), (50000u64, ~"0.05"),
+	(100000u64, ~"0.1"), (250000u64, ~"0.25"), (500000u64, ~"0.5"), (1000000u64, ~"1"),
+	(2500000u64, ~"2.5"), (5000000u64, ~"5"), (10000000u64, ~"10")]
+}
+
+/// Latency histogram for a route.
+///
+/// * route: the route name (empty for requests which didn't match a route, e.g. static files).
+/// * buckets: cumulative counts of requests which took at most the corresponding latency_buckets time.
+/// * count: total number of requests.
+/// * sum: total time spent handling the requests (in seconds).
+pub struct RouteLatency
+{
+	pub route: ~str,
+	pub buckets: ~[uint],
+	pub count: uint,
+	pub sum: float,
+}
+
+/// Returned by metrics_snapshot. Requests and latencies are sorted by route (and status code).
+pub struct MetricsSnapshot
+{
+	pub requests: ~[(~str, uint, uint)],	// route, status code, count
+	pub latencies: ~[RouteLatency],
+	pub spans: ~[(~str, uint, float)],		// span name, count, total seconds (see tracing::Trace)
+	pub active_connections: int,
+	pub active_sse_streams: int,
+	pub bytes_in: uint,						// request bodies
+	pub bytes_out: uint,					// response bodies and data pushed to sse streams
+}
+
+/// Messages handled by the metrics task. Normally the helper functions below are used instead
+/// of sending these directly.
+pub enum MetricsMesg
+{
+	RequestMesg(~str, uint, u64, uint, uint),	// route, status code, duration in microseconds, bytes in, bytes out
+	ConnectionsMesg(int),						// change in the number of open connections
+	SseStreamsMesg(int),						// change in the number of open sse streams
+	SseDataMesg(uint),							// bytes pushed to an sse stream
+	SpansMesg(~[tracing::Span]),				// timing spans for a request
+	SnapshotMesg(comm::Chan<MetricsSnapshot>),
+	ExitMesg,
+}
+
+/// Used to communicate with the metrics task. Set Config.metrics to enable metrics.
+pub type MetricsChan = comm::Chan<MetricsMesg>;
+
+/// Starts up a new metrics task.
+pub fn start_metrics() -> MetricsChan
+{
+	do task::spawn_listener
+	|port: comm::Port<MetricsMesg>|
+	{
+		run_metrics(port);
+	}
+}
+
+/// Returns the current values of the metrics.
+pub fn metrics_snapshot(metrics: MetricsChan) -> MetricsSnapshot
+{
+	let port = comm::Port();
+	comm::send(metrics, SnapshotMesg(comm::Chan(&port)));
+	comm::recv(port)
+}
+
+/// Stops the metrics task.
+pub fn stop_metrics(metrics: MetricsChan)
+{
+	comm::send(metrics, ExitMesg);
+}
+
+/// Returns snapshot in the Prometheus text exposition format (version 0.0.4).
+pub fn format_prometheus(snapshot: &MetricsSnapshot) -> ~str
+{
+	let buckets = latency_buckets();
+	do io::with_str_writer
+	|writer|
+	{
+		writer.write_line("# HELP rwebserve_requests_total Number of requests handled.");
+		writer.write_line("# TYPE rwebserve_requests_total counter");
+		for vec::each(snapshot.requests)
+		|entry|
+		{
+			match *entry
+			{
+				(ref route, code, count) => writer.write_line(fmt!("rwebserve_requests_total{route=\"%s\",status=\"%u\"} %u", escape_label(*route), code, count)),
+			}
+		}
+
+		writer.write_line("# HELP rwebserve_request_duration_seconds Time spent handling requests.");
+		writer.write_line("# TYPE rwebserve_request_duration_seconds histogram");
+		for vec::each(snapshot.latencies)
+		|latency|
+		{
+			let route = escape_label(latency.route);
+			for vec::eachi(buckets)
+			|i, bucket|
+			{
+				writer.write_line(fmt!("rwebserve_request_duration_seconds_bucket{route=\"%s\",le=\"%s\"} %u", route, bucket.second(), latency.buckets[i]));
+			}
+			writer.write_line(fmt!("rwebserve_request_duration_seconds_bucket{route=\"%s\",le=\"+Inf\"} %u", route, latency.count));
+			writer.write_line(fmt!("rwebserve_request_duration_seconds_sum{route=\"%s\"} %s", route, float::to_str(latency.sum, 6u)));
+			writer.write_line(fmt!("rwebserve_request_duration_seconds_count{route=\"%s\"} %u", route, latency.count));
+		}
+
+		writer.write_line("# HELP rwebserve_request_span_seconds Time spent in each stage of handling requests.");
+		writer.write_line("# TYPE rwebserve_request_span_seconds summary");
+		for vec::each(snapshot.spans)
+		|entry|
+		{
+			match *entry
+			{
+				(ref name, count, sum) =>
+				{
+					writer.write_line(fmt!("rwebserve_request_span_seconds_sum{span=\"%s\"} %s", escape_label(*name), float::to_str(sum, 6u)));
+					writer.write_line(fmt!("rwebserve_request_span_seconds_count{span=\"%s\"} %u", escape_label(*name), count));
+				}
+			}
+		}
+
+		writer.write_line("# HELP rwebserve_active_connections Number of open client connections.");
+		writer.write_line("# TYPE rwebserve_active_connections gauge");
+		writer.write_line(fmt!("rwebserve_active_connections %d", snapshot.active_connections));
+
+		writer.write_line("# HELP rwebserve_active_sse_streams Number of open server-sent event streams.");
+		writer.write_line("# TYPE rwebserve_active_sse_streams gauge");
+		writer.write_line(fmt!("rwebserve_active_sse_streams %d", snapshot.active_sse_streams));
+
+		writer.write_line("# HELP rwebserve_received_bytes_total Bytes received in request bodies.");
+		writer.write_line("# TYPE rwebserve_received_bytes_total counter");
+		writer.write_line(fmt!("rwebserve_received_bytes_total %u", snapshot.bytes_in));
+
+		writer.write_line("# HELP rwebserve_sent_bytes_total Bytes sent in response bodies and sse streams.");
+		writer.write_line("# TYPE rwebserve_sent_bytes_total counter");
+		writer.write_line(fmt!("rwebserve_sent_bytes_total %u", snapshot.bytes_out));
+	}
+}
+
+/// View which returns the metrics in the Prometheus text format. This is used for requests
+/// to Config.metrics_path when Config.metrics is set.
+pub fn metrics_view(config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+{
+	match config.metrics
+	{
+		option::Some(metrics) =>
+		{
+			response.headers.set_content_type(~"text/plain; version=0.0.4; charset=utf-8");
+			let body = format_prometheus(&metrics_snapshot(metrics));
+			Response {body: StringBody(@body), template: ~"", context: std::map::HashMap(), ..*response}
+		}
+		option::None =>
+		{
+			error_pages::error_response(response, status::NotFound)
+		}
+	}
+}
+
+// ---- Functions used by the connection code -----------------------------------
+pub fn record_request(config: &connection::ConnConfig, route: &str, code: uint, start_ns: u64, bytes_in: uint, bytes_out: uint)
+{
+	match config.metrics
+	{
+		option::Some(metrics) =>
+		{
+			let duration = (std::time::precise_time_ns() - start_ns)/1000u64;
+			comm::send(metrics, RequestMesg(route.to_unique(), code, duration, bytes_in, bytes_out));
+		}
+		option::None =>
+		{
+		}
+	}
+}
+
+pub fn record_event(config: &connection::ConnConfig, mesg: MetricsMesg)
+{
+	match config.metrics
+	{
+		option::Some(metrics) => comm::send(metrics, mesg),
+		option::None => {}
+	}
+}
+
+// ---- Internal Items ----------------------------------------------------------
+priv struct Latency
+{
+	mut buckets: ~[uint],
+	mut count: uint,
+	mut sum: u64,
+}
+
+priv fn run_metrics(port: comm::Port<MetricsMesg>)
+{
+	let counts: HashMap<@~str, uint> = std::map::HashMap();		// key is route and status code separated by a tab
+	let latencies: HashMap<@~str, @Latency> = std::map::HashMap();
+	let spans: HashMap<@~str, (uint, u64)> = std::map::HashMap();	// count and total microseconds
+	let buckets = latency_buckets();
+	let mut connections = 0;
+	let mut streams = 0;
+	let mut bytes_in = 0u;
+	let mut bytes_out = 0u;
+
+	loop
+	{
+		match comm::recv(port)
+		{
+			RequestMesg(route, code, duration, received, sent) =>
+			{
+				let key = @fmt!("%s\t%u", route, code);
+				counts.insert(key, counts.find(key).get_default(0u) + 1u);
+
+				let latency = match latencies.find(@copy route)
+					{
+						option::Some(latency) => latency,
+						option::None => {let latency = @Latency {buckets: vec::from_elem(vec::len(buckets), 0u), count: 0u, sum: 0u64}; latencies.insert(@copy route, latency); latency}
+					};
+				latency.buckets = do vec::mapi(latency.buckets) |i, n| {if duration <= buckets[i].first() {*n + 1u} else {*n}};
+				latency.count += 1u;
+				latency.sum += duration;
+
+				bytes_in += received;
+				bytes_out += sent;
+			}
+			ConnectionsMesg(delta) =>
+			{
+				connections += delta;
+			}
+			SseStreamsMesg(delta) =>
+			{
+				streams += delta;
+			}
+			SseDataMesg(sent) =>
+			{
+				bytes_out += sent;
+			}
+			SpansMesg(move entries) =>
+			{
+				for vec::each(entries)
+				|span|
+				{
+					let (count, sum) = spans.find(@copy span.name).get_default((0u, 0u64));
+					spans.insert(@copy span.name, (count + 1u, sum + span.duration));
+				}
+			}
+			SnapshotMesg(reply) =>
+			{
+				let mut requests = ~[];
+				for counts.each
+				|key, count|
+				{
+					let parts = str::split_char(*key, '\t');
+					vec::push(&mut requests, (copy parts[0], uint::from_str(parts[1]).get(), count));
+				}
+
+				let mut routes = ~[];
+				for latencies.each
+				|route, latency|
+				{
+					vec::push(&mut routes, RouteLatency {route: copy *route, buckets: copy latency.buckets, count: latency.count, sum: (latency.sum as float)/1000000.0});
+				}
+
+				let mut totals = ~[];
+				for spans.each
+				|name, entry|
+				{
+					match entry
+					{
+						(count, sum) => vec::push(&mut totals, (copy *name, count, (sum as float)/1000000.0)),
+					}
+				}
+
+				pure fn request_le(a: &(~str, uint, uint), b: &(~str, uint, uint)) -> bool {a.first() < b.first() || (a.first() == b.first() && a.second() <= b.second())}
+				pure fn latency_le(a: &RouteLatency, b: &RouteLatency) -> bool {a.route <= b.route}
+				pure fn span_le(a: &(~str, uint, float), b: &(~str, uint, float)) -> bool {a.first() <= b.first()}
+				comm::send(reply, MetricsSnapshot {
+					requests: std::sort::merge_sort(request_le, requests),
+					latencies: std::sort::merge_sort(latency_le, routes),
+					spans: std::sort::merge_sort(span_le, totals),
+					active_connections: connections,
+					active_sse_streams: streams,
+					bytes_in: bytes_in,
+					bytes_out: bytes_out});
+			}
+			ExitMesg =>
+			{
+				break;
+			}
+		}
+	}
+}
+
+priv fn escape_label(value: &str) -> ~str
+{
+	str::replace(str::replace(str::replace(value, "\\", "\\\\"), "\"", "\\\""), "\n", "\\n")
+}
+
+#[test]
+fn metrics_values()
+{
+	let metrics = start_metrics();
+	comm::send(metrics, ConnectionsMesg(1));
+	comm::send(metrics, RequestMesg(~"home", 200u, 3000u64, 0u, 100u));
+	comm::send(metrics, RequestMesg(~"home", 200u, 30000u64, 0u, 100u));
+	comm::send(metrics, RequestMesg(~"home", 404u, 30000u64, 10u, 50u));
+	comm::send(metrics, RequestMesg(~"", 200u, 20000000u64, 0u, 1000u));
+	comm::send(metrics, SseStreamsMesg(2));
+	comm::send(metrics, SseStreamsMesg(-1));
+	comm::send(metrics, SseDataMesg(5u));
+	comm::send(metrics, SpansMesg(~[tracing::Span {name: ~"routing", duration: 10u64}, tracing::Span {name: ~"handler", duration: 500u64}]));
+	comm::send(metrics, SpansMesg(~[tracing::Span {name: ~"routing", duration: 30u64}]));
+
+	let snapshot = metrics_snapshot(metrics);
+	stop_metrics(metrics);
+
+	assert snapshot.requests == ~[(~"", 200u, 1u), (~"home", 200u, 2u), (~"home", 404u, 1u)];
+	assert snapshot.latencies[1].route == ~"home";
+	assert snapshot.latencies[1].buckets == ~[1u, 1u, 1u, 3u, 3u, 3u, 3u, 3u, 3u, 3u, 3u];
+	assert snapshot.latencies[0].buckets == vec::from_elem(11u, 0u);
+	assert snapshot.active_connections == 1;
+	assert snapshot.active_sse_streams == 1;
+	assert snapshot.bytes_in == 10u;
+	assert snapshot.bytes_out == 1255u;
+	assert snapshot.spans == ~[(~"handler", 1u, 0.0005), (~"routing", 2u, 0.00004)];
+
+	let text = format_prometheus(&snapshot);
+	assert text.contains("rwebserve_requests_total{route=\"home\",status=\"404\"} 1\n");
+	assert text.contains("rwebserve_request_duration_seconds_bucket{route=\"home\",le=\"0.05\"} 3\n");
+	assert text.contains("rwebserve_request_duration_seconds_bucket{route=\"\",le=\"+Inf\"} 1\n");
+	assert text.contains("rwebserve_request_duration_seconds_count{route=\"home\"} 3\n");
+	assert text.contains("rwebserve_active_sse_streams 1\n");
+	assert text.contains("rwebserve_request_span_seconds_count{span=\"routing\"} 2\n");
+}
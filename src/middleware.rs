@@ -0,0 +1,186 @@
+//! Layers which wrap the handlers for every request.
+
+/// Function called for every request before the handler (a view, static_handler, missing, an
+/// error view, or the sse handler). Next calls the next layer (or the handler if this is the last
+/// layer) so middleware can do work before and after the inner handler or return its own
+/// response without calling next (e.g. to reject unauthenticated requests).
+///
+/// Note that when next returns the response's template has not been rendered yet (so layers
+/// should change headers and the context rather than the body for template responses). When a
+/// response is found in the response_cache next returns it without calling the handler and the
+/// cached response (which went through the layers when it was stored) is used unless a layer
+/// returns its own response without calling next. Changes layers make to the response after next
+/// returns are discarded for cached responses so headers which depend on the request (e.g. a CORS
+/// header derived from Origin) are replayed from the request which was stored.
+pub type Middleware = fn~ (config: &connection::ConnConfig, request: &Request, response: &Response, next: fn (&Request, &Response) -> Response) -> Response;
+
+/// Returns the layers to use for a request to path: Config.middleware followed by the middleware
+/// for the route groups whose prefix includes path (outer groups first).
+pub fn request_layers(config: &connection::ConnConfig, path: &str) -> ~[Middleware]
+{
+	let mut layers = copy config.middleware;
+	for vec::each(config.group_middleware)
+	|entry|
+	{
+		match *entry
+		{
+			(ref prefix, ref group_layers) =>
+			{
+				if str::eq_slice(path, *prefix) || str::starts_with(path, *prefix + ~"/")
+				{
+					layers += copy *group_layers;
+				}
+			}
+		}
+	}
+	layers
+}
+
+/// Calls handler via layers.
+pub fn run_middleware(layers: &[Middleware], handler: ResponseHandler, config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+{
+	run_layers(layers, config, request, response, |r, s| {handler(config, r, s)})
+}
+
+/// Like run_middleware except that inner is called if every layer calls next.
+pub fn run_layers(layers: &[Middleware], config: &connection::ConnConfig, request: &Request, response: &Response, inner: fn (&Request, &Response) -> Response) -> Response
+{
+	if vec::is_empty(layers)
+	{
+		inner(request, response)
+	}
+	else
+	{
+		let rest = vec::view(layers, 1u, vec::len(layers));
+		(layers[0])(config, request, response, |r, s| {run_layers(rest, config, r, s, inner)})
+	}
+}
+
+/// Returns middleware which sets headers on every response, e.g.
+/// add_headers(~[(~"Access-Control-Allow-Origin", ~"*")]).
+pub fn add_headers(headers: ~[(~str, ~str)]) -> Middleware
+{
+	|_config: &connection::ConnConfig, request: &Request, response: &Response, next: fn (&Request, &Response) -> Response, copy headers|
+	{
+		let response = next(request, response);
+		for vec::each(headers) |header| {response.headers.set(header.first(), header.second())};
+		response
+	}
+}
+
+#[test]
+fn middleware_order()
+{
+	fn outer(_config: &connection::ConnConfig, request: &Request, response: &Response, next: fn (&Request, &Response) -> Response) -> Response
+	{
+		let response = next(request, response);
+		if str::is_empty(response.template)
+		{
+			Response {body: StringBody(@(~"outer(" + response.body.to_str() + ~")")), ..response}
+		}
+		else
+		{
+			response
+		}
+	}
+	
+	fn guard(_config: &connection::ConnConfig, request: &Request, response: &Response, next: fn (&Request, &Response) -> Response) -> Response
+	{
+		if request.path == ~"/secret"
+		{
+			Response {status: status::Forbidden, body: StringBody(@~"denied"), ..*response}
+		}
+		else
+		{
+			next(request, response)
+		}
+	}
+	
+	fn body_view(_config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+	{
+		Response {body: StringBody(@copy request.path), ..*response}
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/public", ~"public"), (~"GET", ~"/secret", ~"secret")],
+		views: ~[(~"public", body_view), (~"secret", body_view)],
+		middleware: ~[outer, guard, add_headers(~[(~"X-Layer", ~"yes")])]
+		, .. initialize_config()};
+	
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = request::make_request(~"/public", ~"text/html");
+	let (header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("X-Layer: yes");
+	assert utils::check_strs(body.to_str(), ~"outer(/public)");
+	
+	let request = request::make_request(~"/secret", ~"text/html");
+	let (header, body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("403 Forbidden");
+	assert !header.contains("X-Layer");
+	assert utils::check_strs(body.to_str(), ~"outer(denied)");
+	
+	let request = request::make_request(~"/missing", ~"text/html");
+	let (header, _body) = request::process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("404 Not Found");
+	assert header.contains("X-Layer: yes");
+}
+
+#[test]
+fn cached_responses_use_middleware()
+{
+	fn auth(_config: &connection::ConnConfig, request: &Request, response: &Response, next: fn (&Request, &Response) -> Response) -> Response
+	{
+		if request.headers.contains_key(~"authorization")
+		{
+			next(request, response)
+		}
+		else
+		{
+			Response {status: status::Unauthorized, body: StringBody(@~"login"), ..*response}
+		}
+	}
+	
+	fn public_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		response.headers.set_cache_control(~[~"public", ~"max-age=60"]);
+		Response {body: StringBody(@~"report"), ..*response}
+	}
+	
+	let cache = response_cache::start_cache(response_cache::default_limits());
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/report", ~"report")],
+		views: ~[(~"report", public_view)],
+		middleware: ~[auth],
+		response_cache: option::Some(cache)
+		, .. initialize_config()};
+	
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let base = request::make_request(~"/report", ~"text/html");
+	let authorized = http_parser::HttpRequest {headers: base.headers + ~[(~"authorization", ~"Basic Ym9iOnB3")], ..base};
+	let (header, body) = request::process_request(&iconfig, &authorized, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("200 OK");
+	assert utils::check_strs(body.to_str(), ~"report");
+	
+	let (header, body) = request::process_request(&iconfig, &base, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("401 Unauthorized");
+	assert utils::check_strs(body.to_str(), ~"login");
+	
+	let (header, body) = request::process_request(&iconfig, &authorized, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Age:");
+	assert str::from_bytes(response_cache::body_bytes(&body)) == ~"report";
+	
+	assert response_cache::cache_stats(cache).hits == 1u;		// the rejected request never reached the cache
+	response_cache::stop_cache(cache);
+}
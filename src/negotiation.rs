@@ -0,0 +1,209 @@
+//! Server-driven content negotiation using quality values (see sections 3.9 and 14 of RFC 2616).
+
+/// An entry from a header like Accept-Language along with its quality value.
+pub struct QualityItem
+{
+	pub value: ~str,
+	pub quality: float,
+}
+
+/// Parses headers like "en-us,en;q=0.5" into a list sorted by descending quality.
+///
+/// Values are lower cased. Entries with the same quality retain their original order.
+/// Malformed quality values are treated as 1.0 (clients are not always careful about these).
+pub fn parse_quality_list(header: &str) -> ~[QualityItem]
+{
+	let mut items = ~[];
+
+	let entries = str::split_char(header, ',');
+	for vec::each(entries)
+	|entry|
+	{
+		let parts = str::split_char(*entry, ';');
+		let value = str::to_lower(str::trim(parts[0]));
+		if str::is_not_empty(value)
+		{
+			let mut quality = 1.0;
+			for vec::eachi(parts)
+			|i, param|
+			{
+				let param = str::trim(*param);
+				if i > 0u && (str::starts_with(param, "q=") || str::starts_with(param, "Q="))
+				{
+					match float::from_str(param.slice(2, param.len()))
+					{
+						option::Some(q) if q >= 0.0 && q <= 1.0 =>
+						{
+							quality = q;
+						}
+						_ =>
+						{
+							info!("ignoring bad quality value in '%s'", *entry);
+						}
+					}
+				}
+			}
+			vec::push(&mut items, QualityItem {value: value, quality: quality});
+		}
+	}
+
+	pure fn ge(a: &QualityItem, b: &QualityItem) -> bool {a.quality >= b.quality}
+	std::sort::merge_sort(ge, items)		// merge_sort is stable so ties keep the client's order
+}
+
+/// Returns the best language from available given an Accept-Language header value.
+///
+/// Available languages are language tags like "en" or "fr-ca". The first available language
+/// is the default and is used if the header is empty or nothing in the header matches. None
+/// is returned only if available is empty.
+pub fn negotiate_language(available: &[~str], header: &str) -> Option<~str>
+{
+	if vec::is_empty(available)
+	{
+		return option::None;
+	}
+
+	let ranges = parse_quality_list(header);
+	for vec::each(ranges)
+	|range|
+	{
+		if range.quality > 0.0
+		{
+			if range.value == ~"*"
+			{
+				break;
+			}
+
+			match match_language(available, range.value)
+			{
+				option::Some(language) =>
+				{
+					return option::Some(language);
+				}
+				option::None =>
+				{
+				}
+			}
+		}
+	}
+
+	// Section 14.4 allows servers to ignore Accept-Language so rather than returning
+	// 406 Not Acceptable we'll fall back onto the default language.
+	option::Some(copy available[0])
+}
+
+/// Returns true if a response using charset is acceptable according to an Accept-Charset header value.
+///
+/// Note that an empty header means that any charset is acceptable.
+pub fn accepts_charset(header: &str, charset: &str) -> bool
+{
+	if str::is_empty(str::trim(header))
+	{
+		return true;
+	}
+
+	let charset = str::to_lower(charset);
+	let mut wildcard = false;
+	let items = parse_quality_list(header);
+	for vec::each(items)
+	|item|
+	{
+		if item.value == charset
+		{
+			return item.quality > 0.0;		// explicit entries take precedence over *
+		}
+		else if item.value == ~"*"
+		{
+			wildcard = item.quality > 0.0;
+		}
+	}
+	wildcard
+}
+
+/// Returns the path to the variant of template for language, e.g. "home.html" becomes "home.fr.html".
+pub fn localized_path(template: &str, language: &str) -> ~str
+{
+	let slash = str::rfind_char(template, '/');
+	match str::rfind_char(template, '.')
+	{
+		option::Some(i) if slash.is_none() || slash.get() < i =>
+		{
+			fmt!("%s.%s%s", template.slice(0, i), language, template.slice(i, template.len()))
+		}
+		_ =>
+		{
+			fmt!("%s.%s", template, language)
+		}
+	}
+}
+
+// Uses the lookup scheme from RFC 4647: "fr-ca" matches "fr-ca", then "fr".
+// We also allow a range like "fr" to match a more specific tag like "fr-ca".
+priv fn match_language(available: &[~str], range: &str) -> Option<~str>
+{
+	let mut range = range.to_unique();
+	loop
+	{
+		for vec::each(available)
+		|language|
+		{
+			let tag = str::to_lower(*language);
+			if tag == range || str::starts_with(tag, range + ~"-")
+			{
+				return option::Some(copy *language);
+			}
+		}
+
+		match str::rfind_char(range, '-')
+		{
+			option::Some(i) =>
+			{
+				range = range.slice(0, i);
+			}
+			option::None =>
+			{
+				return option::None;
+			}
+		}
+	}
+}
+
+#[test]
+fn quality_ordering()
+{
+	let items = parse_quality_list(~"en;q=0.5, fr-CA, de;q=0.8,ja;q=0.8, it;q=bogus");
+	let values = do vec::map(items) |item| {copy item.value};
+	assert utils::check_vectors(values, ~[~"fr-ca", ~"it", ~"de", ~"ja", ~"en"]);
+	assert items[4].quality == 0.5;
+}
+
+#[test]
+fn language_matching()
+{
+	let available = ~[~"en", ~"fr", ~"pt-br"];
+	assert negotiate_language(available, ~"fr, en;q=0.5") == option::Some(~"fr");
+	assert negotiate_language(available, ~"fr-ca, en;q=0.5") == option::Some(~"fr");
+	assert negotiate_language(available, ~"pt, en;q=0.5") == option::Some(~"pt-br");
+	assert negotiate_language(available, ~"de, fr;q=0.1") == option::Some(~"fr");
+	assert negotiate_language(available, ~"fr;q=0, de") == option::Some(~"en");
+	assert negotiate_language(available, ~"") == option::Some(~"en");
+	assert negotiate_language(~[], ~"fr") == option::None;
+}
+
+#[test]
+fn charset_matching()
+{
+	assert accepts_charset(~"", ~"UTF-8");
+	assert accepts_charset(~"iso-8859-5, utf-8;q=0.8", ~"UTF-8");
+	assert accepts_charset(~"iso-8859-5, *;q=0.5", ~"UTF-8");
+	assert !accepts_charset(~"iso-8859-5", ~"UTF-8");
+	assert !accepts_charset(~"utf-8;q=0, *", ~"UTF-8");
+}
+
+#[test]
+fn localized_paths()
+{
+	assert utils::check_strs(localized_path(~"home.html", ~"fr"), ~"home.fr.html");
+	assert utils::check_strs(localized_path(~"/blog/v1.0/index.html", ~"en-us"), ~"/blog/v1.0/index.en-us.html");
+	assert utils::check_strs(localized_path(~"/blog/v1.0/index", ~"fr"), ~"/blog/v1.0/index.fr");
+}
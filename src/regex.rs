@@ -0,0 +1,335 @@
+//! Minimal backtracking regular expressions used to constrain URI template variables.
+//
+// Supports literals, ".", escapes (\d, \w, \s, and escaped punctuation), character classes
+// ([a-z], [^/]), groups with alternation ("(jpg|png)"), and the *, +, ?, {n}, {n,}, and {n,m}
+// quantifiers. Patterns always match the entire string (there are no anchors).
+
+/// A compiled regular expression.
+pub struct Regex
+{
+	pub source: ~str,
+	priv alternatives: ~[~[Node]],
+}
+
+priv enum Node
+{
+	Char(char),
+	AnyChar,
+	Class(~[(char, char)], bool),		// ranges and whether the class is negated
+	Group(~[~[Node]]),					// alternatives
+	Repeat(~Node, uint, uint),			// node, min count, max count
+}
+
+/// Compiles pattern or returns an error describing the problem.
+pub fn compile_regex(pattern: &str) -> result::Result<Regex, ~str>
+{
+	let chars = str::chars(pattern);
+	let mut index = 0u;
+	do result::chain(parse_alternatives(chars, &mut index))
+	|alternatives|
+	{
+		if index < vec::len(chars)
+		{
+			result::Err(fmt!("unbalanced ) in '%s'", pattern))
+		}
+		else
+		{
+			result::Ok(Regex {source: pattern.to_unique(), alternatives: alternatives})
+		}
+	}
+}
+
+pub impl Regex
+{
+	/// Returns true if the regex matches all of text.
+	fn matches(text: &str) -> bool
+	{
+		let chars = str::chars(text);
+		let len = vec::len(chars);
+		vec::any(self.alternatives, |seq| {match_seq(*seq, 0u, chars, |i| {i == len})})
+	}
+}
+
+// ---- Parsing -------------------------------------------------------------
+priv fn parse_alternatives(chars: &[char], index: &mut uint) -> result::Result<~[~[Node]], ~str>
+{
+	let mut alternatives = ~[];
+	loop
+	{
+		match parse_sequence(chars, index)
+		{
+			result::Ok(move seq) => vec::push(&mut alternatives, seq),
+			result::Err(move mesg) => return result::Err(mesg),
+		}
+		if *index < vec::len(chars) && chars[*index] == '|'
+		{
+			*index += 1u;
+		}
+		else
+		{
+			return result::Ok(alternatives);
+		}
+	}
+}
+
+priv fn parse_sequence(chars: &[char], index: &mut uint) -> result::Result<~[Node], ~str>
+{
+	let mut seq = ~[];
+	while *index < vec::len(chars) && chars[*index] != '|' && chars[*index] != ')'
+	{
+		let atom = match parse_atom(chars, index) {result::Ok(move n) => n, result::Err(move mesg) => return result::Err(mesg)};
+		let node = match parse_quantifier(chars, index)
+			{
+				result::Ok(option::Some((min, max))) => Repeat(~atom, min, max),
+				result::Ok(option::None) => atom,
+				result::Err(move mesg) => return result::Err(mesg),
+			};
+		vec::push(&mut seq, node);
+	}
+	result::Ok(seq)
+}
+
+priv fn parse_atom(chars: &[char], index: &mut uint) -> result::Result<Node, ~str>
+{
+	let ch = chars[*index];
+	*index += 1u;
+	match ch
+	{
+		'(' =>
+		{
+			do result::chain(parse_alternatives(chars, index))
+			|alternatives|
+			{
+				if *index < vec::len(chars) && chars[*index] == ')'
+				{
+					*index += 1u;
+					result::Ok(Group(alternatives))
+				}
+				else
+				{
+					result::Err(~"missing )")
+				}
+			}
+		}
+		'[' =>
+		{
+			parse_class(chars, index)
+		}
+		'.' =>
+		{
+			result::Ok(AnyChar)
+		}
+		'\\' =>
+		{
+			if *index == vec::len(chars)
+			{
+				return result::Err(~"trailing \\");
+			}
+			let escaped = chars[*index];
+			*index += 1u;
+			result::Ok(escape_node(escaped))
+		}
+		'*' | '+' | '?' | '{' =>
+		{
+			result::Err(fmt!("nothing to repeat before '%c'", ch))
+		}
+		_ =>
+		{
+			result::Ok(Char(ch))
+		}
+	}
+}
+
+priv fn escape_node(ch: char) -> Node
+{
+	match ch
+	{
+		'd' => Class(~[('0', '9')], false),
+		'D' => Class(~[('0', '9')], true),
+		'w' => Class(~[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], false),
+		'W' => Class(~[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')], true),
+		's' => Class(~[(' ', ' '), ('\t', '\t'), ('\r', '\r'), ('\n', '\n')], false),
+		'S' => Class(~[(' ', ' '), ('\t', '\t'), ('\r', '\r'), ('\n', '\n')], true),
+		_ => Char(ch),
+	}
+}
+
+priv fn parse_class(chars: &[char], index: &mut uint) -> result::Result<Node, ~str>
+{
+	let mut ranges = ~[];
+	let negated = *index < vec::len(chars) && chars[*index] == '^';
+	if negated
+	{
+		*index += 1u;
+	}
+
+	let mut first = true;
+	while *index < vec::len(chars) && (first || chars[*index] != ']')
+	{
+		let mut lower = chars[*index];
+		*index += 1u;
+		if lower == '\\' && *index < vec::len(chars)
+		{
+			match escape_node(chars[*index])
+			{
+				Class(ref class_ranges, false) => {*index += 1u; ranges += copy *class_ranges; first = false; loop;}
+				_ => {lower = chars[*index]; *index += 1u;}
+			}
+		}
+
+		if *index + 1u < vec::len(chars) && chars[*index] == '-' && chars[*index + 1u] != ']'
+		{
+			let upper = chars[*index + 1u];
+			*index += 2u;
+			if upper < lower
+			{
+				return result::Err(fmt!("bad range %c-%c", lower, upper));
+			}
+			vec::push(&mut ranges, (lower, upper));
+		}
+		else
+		{
+			vec::push(&mut ranges, (lower, lower));
+		}
+		first = false;
+	}
+
+	if *index < vec::len(chars)
+	{
+		*index += 1u;		// skip the ]
+		result::Ok(Class(ranges, negated))
+	}
+	else
+	{
+		result::Err(~"missing ]")
+	}
+}
+
+priv fn parse_quantifier(chars: &[char], index: &mut uint) -> result::Result<Option<(uint, uint)>, ~str>
+{
+	if *index == vec::len(chars)
+	{
+		return result::Ok(option::None);
+	}
+
+	let unbounded = uint::max_value;
+	match chars[*index]
+	{
+		'*' => {*index += 1u; result::Ok(option::Some((0u, unbounded)))}
+		'+' => {*index += 1u; result::Ok(option::Some((1u, unbounded)))}
+		'?' => {*index += 1u; result::Ok(option::Some((0u, 1u)))}
+		'{' =>
+		{
+			let start = *index + 1u;
+			let mut end = start;
+			while end < vec::len(chars) && chars[end] != '}'
+			{
+				end += 1u;
+			}
+			if end == vec::len(chars)
+			{
+				return result::Err(~"missing }");
+			}
+			*index = end + 1u;
+
+			let body = str::from_chars(vec::slice(chars, start, end));
+			let parts = str::split_char(body, ',');
+			let min = uint::from_str(parts[0]);
+			let max =
+				if vec::len(parts) == 1u {min}
+				else if vec::len(parts) == 2u && str::is_empty(parts[1]) {option::Some(unbounded)}
+				else if vec::len(parts) == 2u {uint::from_str(parts[1])}
+				else {option::None};
+			match (min, max)
+			{
+				(option::Some(min), option::Some(max)) if min <= max => result::Ok(option::Some((min, max))),
+				_ => result::Err(fmt!("bad repeat count {%s}", body)),
+			}
+		}
+		_ =>
+		{
+			result::Ok(option::None)
+		}
+	}
+}
+
+// ---- Matching ------------------------------------------------------------
+// These call k with the index after each way the node can match, stopping if k returns true.
+priv fn match_seq(seq: &[Node], i: uint, text: &[char], k: fn(uint) -> bool) -> bool
+{
+	if vec::is_empty(seq)
+	{
+		k(i)
+	}
+	else
+	{
+		let rest = vec::view(seq, 1u, vec::len(seq));
+		match_node(&seq[0], i, text, |j| {match_seq(rest, j, text, k)})
+	}
+}
+
+priv fn match_node(node: &Node, i: uint, text: &[char], k: fn(uint) -> bool) -> bool
+{
+	match *node
+	{
+		Char(ch) =>
+		{
+			i < vec::len(text) && text[i] == ch && k(i + 1u)
+		}
+		AnyChar =>
+		{
+			i < vec::len(text) && k(i + 1u)
+		}
+		Class(ref ranges, negated) =>
+		{
+			i < vec::len(text) && vec::any(*ranges, |r| {text[i] >= r.first() && text[i] <= r.second()}) != negated && k(i + 1u)
+		}
+		Group(ref alternatives) =>
+		{
+			vec::any(*alternatives, |seq| {match_seq(*seq, i, text, k)})
+		}
+		Repeat(ref inner, min, max) =>
+		{
+			match_repeat(*inner, min, max, 0u, i, text, k)
+		}
+	}
+}
+
+// Repeats are greedy: we try to match one more time before trying the continuation.
+priv fn match_repeat(node: &Node, min: uint, max: uint, count: uint, i: uint, text: &[char], k: fn(uint) -> bool) -> bool
+{
+	if count < max && match_node(node, i, text, |j| {(j != i || count < min) && match_repeat(node, min, max, count + 1u, j, text, k)})
+	{
+		return true;
+	}
+	count >= min && k(i)
+}
+
+#[test]
+fn regex_matching()
+{
+	let check = |pattern: &str, text: &str| {result::unwrap(compile_regex(pattern)).matches(text)};
+
+	assert check(~"abc", ~"abc");
+	assert !check(~"abc", ~"abcd");
+	assert check(~"a.c", ~"axc");
+	assert check(~"[0-9]{4}", ~"2012");
+	assert !check(~"[0-9]{4}", ~"201");
+	assert check(~"[a-z-]+", ~"hello-world");
+	assert !check(~"[^/]+", ~"a/b");
+	assert check(~"\\d+(\\.\\d+)?", ~"3.14");
+	assert check(~"(jpg|png|gif)", ~"png");
+	assert !check(~"(jpg|png|gif)", ~"bmp");
+	assert check(~"a*a*b", ~"aaab");
+	assert check(~"x{2,}", ~"xxxx");
+}
+
+#[test]
+fn regex_errors()
+{
+	assert compile_regex(~"(abc").is_err();
+	assert compile_regex(~"abc)").is_err();
+	assert compile_regex(~"[abc").is_err();
+	assert compile_regex(~"*a").is_err();
+	assert compile_regex(~"a{3,1}").is_err();
+}
@@ -6,21 +6,81 @@ use http_parser::{HttpRequest};
 // include last-modified and maybe etag
 pub fn process_request(config: &connection::ConnConfig, request: &HttpRequest, local_addr: &str, remote_addr: &str) -> (~str, Body)
 {
-	info!("Servicing %s for %s", request.method, utils::truncate_str(request.url, 80));
+	let (header, body, _trace) = process_traced_request(config, request, local_addr, remote_addr);
+	(header, body)
+}
+
+/// Like process_request except that the request's trace is also returned so that the caller
+/// can add the write span and then call tracing::finish_trace.
+pub fn process_traced_request(config: &connection::ConnConfig, request: &HttpRequest, local_addr: &str, remote_addr: &str) -> (~str, Body, @tracing::Trace)
+{
+	let start_ns = std::time::precise_time_ns();
+	let url = copy request.raw_url;
+	let headers = headers::headers_from(request.headers);
+	let id = if str::is_not_empty(request.id) {copy request.id} else {tracing::request_id(config, headers)};
+	info!("[%s] Servicing %s for %s", id, request.method, utils::truncate_str(request.url, 80));
 	
-	let version = fmt!("%d.%d", request.major_version, request.minor_version);
-	let (path, params) = parse_url(request.url);
-	let request = Request {version: version, method: request.method, local_addr: local_addr.to_unique(), remote_addr: remote_addr.to_unique(), path: path, matches: std::map::HashMap(), params: params, headers: utils::to_boxed_str_hash(request.headers), body: request.body};
-	let types = if request.headers.contains_key(@~"accept") {str::split_char(*request.headers.get(@~"accept"), ',')} else {~[~"text/html"]};
-	let (response, body) = get_body(config, &request, types);
+	let trace = tracing::Trace(id);
+	trace.add(~"parse", request.parse_time);
+	
+	let request = to_request(request, headers, id, trace, local_addr, remote_addr);
+	let types = if request.headers.contains_key(~"accept") {str::split_char(request.headers.get(~"accept"), ',')} else {~[~"text/html"]};
+	let (response, body, route) = get_body(config, &request, types);
+	let response = if is_unmodified(&request, &response) {Response {status: status::NotModified, ..response}} else {response};
+	response.headers.set(~"X-Request-ID", request.id);
+	
+	if config.settings.contains_key(@~"debug") && config.settings.get(@~"debug") == @~"true"
+	{
+		let problems = status::validate_response(request.method, response.status, response.headers, body_len(&body));
+		for vec::each(problems) |problem| {warn!("[%s] %s %s: %s", request.id, request.method, request.path, *problem)};
+	}
 	
 	let (header, body) = make_header_and_body(&response, body);
-	debug!("response header: %s", header);
-	debug!("response body: %?", body);		// TODO: only print this if its a text mime type (and convert it to a str if so)
+	let body = if request.method == ~"HEAD" {StringBody(@~"")} else {body};	// HEAD responses have the same headers as GET (including Content-Length) but no body
+	access_log::log_request(config, url, &request, &response, body_len(&body), start_ns);
+	metrics::record_request(config, route, response.status.code(), start_ns, str::len(request.body), body_len(&body));
+	debug!("[%s] response header: %s", request.id, header);
+	debug!("[%s] response body: %?", request.id, body);		// TODO: only print this if its a text mime type (and convert it to a str if so)
+	
+	(header, body, trace)
+}
+
+/// Used by the connection code instead of process_request when the task servicing request fails.
+/// Returns the header and body of a 500 response (see error_pages::failure_response) after
+/// logging the failure and recording the request in the access log and metrics.
+pub fn process_failed_request(config: &connection::ConnConfig, request: &HttpRequest, local_addr: &str, remote_addr: &str, start_ns: u64) -> (~str, Body)
+{
+	let headers = headers::headers_from(request.headers);
+	let id = if str::is_not_empty(request.id) {copy request.id} else {tracing::request_id(config, headers)};
+	error!("[%s] servicing %s %s for %s failed", id, request.method, utils::truncate_str(request.url, 80), remote_addr);
+	
+	let (header, body) = error_pages::failure_response(config, &HttpRequest {id: copy id, ..copy *request});
+	let url = copy request.raw_url;
+	let request = to_request(request, headers, copy id, tracing::Trace(id), local_addr, remote_addr);
+	let response = Response {status: status::InternalServerError, headers: headers::Headers(), body: StringBody(@~""), template: ~"", context: std::map::HashMap(), layout: ~""};
+	access_log::log_request(config, url, &request, &response, body_len(&body), start_ns);
+	metrics::record_request(config, ~"", response.status.code(), start_ns, str::len(request.body), body_len(&body));
 	
 	(header, body)
 }
 
+priv fn to_request(request: &HttpRequest, headers: headers::Headers, id: ~str, trace: @tracing::Trace, local_addr: &str, remote_addr: &str) -> Request
+{
+	let version = fmt!("%d.%d", request.major_version, request.minor_version);
+	let (path, params) = parse_url(request.url);
+	Request {version: version, method: copy request.method, local_addr: local_addr.to_unique(), remote_addr: remote_addr.to_unique(), path: path, matches: std::map::HashMap(), route: ~"", params: params, query: raw_query(request.raw_url), headers: headers, body: copy request.body, cookies: cookies::parse_cookies(headers), id: id, trace: trace}
+}
+
+// Returns the (still percent encoded) query portion of url.
+priv fn raw_query(url: &str) -> ~str
+{
+	match str::find_char(url, '?')
+	{
+		option::Some(i) => url.slice(i+1, url.len()),
+		option::None => ~"",
+	}
+}
+
 priv fn parse_url(url: &str) -> (~str, IMap<@~str, @~str>)
 {
 	match str::find_char(url, '?')
@@ -65,26 +125,55 @@ priv fn parse_url(url: &str) -> (~str, IMap<@~str, @~str>)
 	}
 }
 
-pub fn make_initial_response(config: &connection::ConnConfig, status_code: ~str, status_mesg: ~str, mime_type: ~str, request: &Request) -> Response
+// Views may set Last-Modified so we need to handle If-Modified-Since here as well as in static_view.
+priv fn is_unmodified(request: &Request, response: &Response) -> bool
+{
+	if (request.method == ~"GET" || request.method == ~"HEAD") && response.status == status::OK
+	{
+		match (request.headers.find(~"if-modified-since"), response.headers.last_modified())
+		{
+			(option::Some(ref since), option::Some(modified)) => http_date::is_unmodified_since(*since, modified),
+			_ => false,
+		}
+	}
+	else
+	{
+		false
+	}
+}
+
+pub fn make_initial_response(config: &connection::ConnConfig, code: status::Status, mime_type: ~str, request: &Request) -> Response
 {
-	let headers = utils::to_boxed_str_hash(~[
+	let headers = headers::headers_from(~[
 		(~"Content-Type", copy mime_type),
-		(~"Date", std::time::now_utc().rfc822()),
+		(~"Date", config.date_cache.now()),
 		(~"Server", copy config.server_info),
 	]);
 	
 	if config.settings.contains_key(@~"debug") && config.settings.get(@~"debug") == @~"true"
 	{
-		headers.insert(@~"Cache-Control", @~"no-cache");
+		headers.set_cache_control(~[~"no-cache"]);
 	}
 	
 	let context = std::map::HashMap();
 	context.insert(@~"request-path", mustache::Str(@copy request.path));
-	context.insert(@~"status-code", mustache::Str(@copy status_code));
-	context.insert(@~"status-mesg", mustache::Str(@copy status_mesg));
+	context.insert(@~"status-code", mustache::Str(@uint::to_str(code.code(), 10u)));
+	context.insert(@~"status-mesg", mustache::Str(@code.reason()));
 	context.insert(@~"request-version", mustache::Str(@copy request.version));
 	
-	Response {status: status_code + ~" " + status_mesg, headers: headers, body: StringBody(@~""), template: ~"", context: context}
+	let accept_language = request.headers.find(~"accept-language").get_default(~"");
+	match negotiation::negotiate_language(config.languages, accept_language)
+	{
+		option::Some(ref locale) =>
+		{
+			context.insert(@~"locale", mustache::Str(@copy *locale));
+		}
+		option::None =>
+		{
+		}
+	}
+	
+	Response {status: code, headers: headers, body: StringBody(@~""), template: ~"", context: context, layout: copy config.layout}
 }
 
 priv fn body_len(body: &Body) -> uint
@@ -111,26 +200,32 @@ pub fn make_header_and_body(response: &Response, body: Body) -> (~str, Body)
 	let mut headers = ~"";
 	let mut has_content_len = false;
 	let mut is_chunked = false;
+	let no_body = response.status.forbids_body();		// e.g. 204 and 304
 	
 	for response.headers.each()
 	|name, value|
 	{
-		if *name == ~"Content-Length"
+		let is_content_len = str::to_lower(name) == ~"content-length";
+		if no_body && is_content_len
+		{
+			loop;
+		}
+		else if is_content_len
 		{
 			has_content_len = true;
 		}
-		else if *name == ~"Transfer-Encoding" && *value == ~"chunked"
+		else if str::to_lower(name) == ~"transfer-encoding" && str::to_lower(value) == ~"chunked"
 		{
 			is_chunked = true;
 		}
 		
-		if *name == ~"Content-Length" && *value == ~"0"
+		if is_content_len && str::eq_slice(value, "0")
 		{
 			headers += fmt!("Content-Length: %?\r\n", body_len(&body));
 		}
 		else
 		{
-			headers += fmt!("%s: %s\r\n", *name, *value);
+			headers += fmt!("%s: %s\r\n", name, value);
 		}
 	};
 	
@@ -138,14 +233,18 @@ pub fn make_header_and_body(response: &Response, body: Body) -> (~str, Body)
 	{
 		assert !has_content_len;
 	}
-	else if !has_content_len
+	else if !has_content_len && !no_body
 	{
 		headers += fmt!("Content-Length: %?\r\n", body_len(&body));
 	}
 	
 	(
-		fmt!("HTTP/1.1 %s\r\n%s\r\n", response.status, headers),
-		if is_chunked
+		fmt!("HTTP/1.1 %s\r\n%s\r\n", response.status.to_str(), headers),
+		if no_body
+		{
+			StringBody(@~"")
+		}
+		else if is_chunked
 		{
 			CompoundBody(@[@StringBody(@fmt!("%X\r\n", body_len(&body))), @body, @StringBody(@~"\r\n")])
 		}
@@ -156,99 +255,199 @@ pub fn make_header_and_body(response: &Response, body: Body) -> (~str, Body)
 	)
 }
 
-priv fn get_body(config: &connection::ConnConfig, request: &Request, types: ~[~str]) -> (Response, Body)
+// Returns the response, the body, and the name of the route used (if any).
+priv fn get_body(config: &connection::ConnConfig, request: &Request, types: ~[~str]) -> (Response, Body, ~str)
 {
-	if vec::contains(types, &~"text/event-stream") 
+	let layers = middleware::request_layers(config, request.path);
+	if request.method == ~"GET" && vec::contains(types, &~"text/event-stream")
 	{
-		process_sse(config, request)
+		let response = make_initial_response(config, status::OK, ~"text/event-stream; charset=utf-8", request);
+		let response = middleware::run_middleware(layers, sse::sse_view, config, request, &response);
+		let body = response.body;
+		(response, body, ~"")
 	}
 	else
 	{
-		let (status_code, status_mesg, mime_type, handler, matches) = find_handler(config, request.method, request.path, types, request.version);
+		let (code, mime_type, handler, matches, route) =
+			if is_metrics_request(config, request)
+			{
+				let handler: ResponseHandler = metrics::metrics_view;
+				(status::OK, ~"text/plain; charset=UTF-8", handler, std::map::HashMap(), ~"")
+			}
+			else
+			{
+				do request.trace.time(~"routing") {find_handler(config, request.id, request.method, request.path, &request.params, types, request.version)}
+			};
 		
-		let response = make_initial_response(config, status_code, status_mesg, mime_type, request);
-		let response = handler(config, &Request {matches: matches, ..*request}, &response);
+		let request = Request {matches: matches, route: route, ..*request};
+		let response = make_initial_response(config, code, mime_type, &request);
 		
+		// Cached responses went through the layers when they were stored but the layers still
+		// need to see the request, e.g. so that they can reject unauthenticated requests. So the
+		// cache is checked only once every layer has called next.
+		let mut cached = option::None;
+		let response = do request.trace.time(~"handler")
+		{
+			do middleware::run_layers(layers, config, &request, &response)
+			|r, s|
+			{
+				match response_cache::find_cached(config, r)
+				{
+					option::Some(move hit) =>
+					{
+						let response = match hit {(ref response, _, _) => Response {template: copy response.template, ..*response}};
+						cached = option::Some(hit);
+						response
+					}
+					option::None =>
+					{
+						handler(config, r, s)
+					}
+				}
+			}
+		};
+		match cached
+		{
+			option::Some(move hit) => hit,
+			option::None => finish_response(config, &request, response),
+		}
+	}
+}
+
+// Adds the error page (if needed), renders the template (if any), applies the cache policy, and
+// stores the response in the cache (if it's cacheable).
+priv fn finish_response(config: &connection::ConnConfig, request: &Request, response: Response) -> (Response, Body, ~str)
+{
+	let response = if error_pages::needs_error_page(&response) {error_pages::apply_error_page(config, request, &response)} else {response};
+	
+	let (response, body) =
 		if str::is_not_empty(response.template.to_str())
 		{
-			process_template(config, &response, request)
+			do request.trace.time(~"render") {process_template(config, &response, request)}
 		}
 		else
 		{
 			let body = response.body;
 			(response, body)
-		}
-	}
+		};
+	cache_policy::apply_cache_policy(config.cache_policies, request, &response);
+	response_cache::store_response(config, request, &response, &body);
+	(response, body, copy request.route)
 }
 
-priv fn find_handler(config: &connection::ConnConfig, method: &str, request_path: &str, types: &[~str], version: &str) -> (~str, ~str, ~str, ResponseHandler, HashMap<@~str, @~str>)
+priv fn is_metrics_request(config: &connection::ConnConfig, request: &Request) -> bool
+{
+	config.metrics.is_some() && str::is_not_empty(config.metrics_path) && request.path == config.metrics_path && (request.method == ~"GET" || request.method == ~"HEAD")
+}
+
+priv fn find_handler(config: &connection::ConnConfig, id: &str, method: &str, request_path: &str, params: &IMap<@~str, @~str>, types: &[~str], version: &str) -> (status::Status, ~str, ResponseHandler, HashMap<@~str, @~str>, ~str)
 {
 	let mut handler = option::None;
-	let mut status_code = ~"200";
-	let mut status_mesg = ~"OK";
+	let mut code = status::OK;
 	let mut result_type = ~"text/html; charset=UTF-8";
 	let mut matches = std::map::HashMap();
+	let mut route_name = ~"";
+	let mut is_static = false;
 	
 	// According to section 3.1 servers are supposed to accept new minor version editions.
 	if !str::starts_with(version, "1.")
 	{
-		status_code = ~"505";
-		status_mesg = ~"HTTP Version Not Supported";
-		let (_, _, _, h, _) = find_handler(config, method, ~"not-supported.html", ~[~"types/html"], ~"1.1");
-		handler = option::Some(h);
-		info!("responding with %s %s", status_code, status_mesg);
+		code = status::HTTPVersionNotSupported;
+		handler = option::Some(error_pages::error_view);
+		info!("[%s] responding with %s", id, code.to_str());
+	}
+	
+	// Section 5.1.1 says that we should return 501 for methods we don't recognize.
+	if option::is_none(&handler) && !is_known_method(config, method)
+	{
+		code = status::NotImplemented;
+		handler = option::Some(method_error_view(~""));
+		info!("[%s] responding with %s (method was %s)", id, code.to_str(), method);
+	}
+	
+	// OPTIONS * is a request for the server's capabilities (see 9.2).
+	if option::is_none(&handler) && str::eq_slice(method, "OPTIONS") && str::eq_slice(request_path, "*")
+	{
+		let mut methods = ~[~"GET"];
+		for vec::each(config.route_list) |entry| {vec::push(&mut methods, copy entry.method)};
+		handler = option::Some(options_view(allow_header(methods)));
 	}
 	
-	// See if the url matches a file under the resource root (i.e. the url can't have too many .. components).
+	// See if the url matches a file under the resource root or a group's static root (i.e. the url can't have too many .. components).
 	if option::is_none(&handler)
 	{
-		let path = utils::url_to_path(&config.resources_root, request_path);
+		let (root, path) = route_group::static_path(config, request_path);
 		let path = path.normalize();
-		if str::starts_with(path.to_str(), config.resources_root.to_str())
+		if str::starts_with(path.to_str(), root.to_str())
 		{
 			if config.valid_rsrc(&path)
 			{
 				let mime_type = path_to_type(config, request_path);
 				if vec::contains(types, &~"*/*") || vec::contains(types, &mime_type)
 				{
-					result_type = mime_type + ~"; charset=UTF-8";
-					handler = option::Some(copy config.static_handler);
+					is_static = true;
+					if str::eq_slice(method, "GET") || str::eq_slice(method, "HEAD")
+					{
+						result_type = mime_type + ~"; charset=UTF-8";
+						handler = option::Some(copy config.static_handler);
+					}
 				}
 			}
 		}
 		else
 		{
-			status_code = ~"403";			// don't allow access to files not under resources_root
-			status_mesg = ~"Forbidden";
-			let (_, _, _, h, _) = find_handler(config, method, ~"forbidden.html", ~[~"types/html"], version);
-			handler = option::Some(h);
-			info!("responding with %s %s (path wasn't under resources_root)", status_code, status_mesg);
+			code = status::Forbidden;			// don't allow access to files not under resources_root
+			handler = option::Some(error_pages::error_view);
+			info!("[%s] responding with %s (path wasn't under resources_root)", id, code.to_str());
 		}
 	}
 	
-	// Then look for the first matching route.
+	// Then look for the most specific matching route. HEAD is handled by GET routes unless
+	// there is an explicit HEAD route (the body is discarded in process_request).
 	if option::is_none(&handler)
 	{
-		for vec::each(config.route_list)
-		|entry|
+		let mut route = find_route(config, id, method, request_path, params, types);
+		if option::is_none(&route) && str::eq_slice(method, "HEAD")
+		{
+			route = find_route(config, id, ~"GET", request_path, params, types);
+		}
+		
+		match route
 		{
-			if str::eq_slice(entry.method, method)
+			option::Some((ref h, ref t, m, ref r)) =>
 			{
-				let m = uri_template::match_template(request_path, entry.template);
-				if m.size() > 0u
-				{
-					if vec::contains(types, &entry.mime_type)
-					{
-						handler = option::Some(config.views_table.get(@copy entry.route));
-						result_type = entry.mime_type + ~"; charset=UTF-8";
-						matches = m;
-						break;
-					}
-					else
-					{
-						info!("request matches route but route type is %s not one of: %s", entry.mime_type, str::connect(types, ~", "));
-					}
-				}
+				handler = option::Some(copy *h);
+				result_type = *t + ~"; charset=UTF-8";
+				matches = m;
+				route_name = copy *r;
+			}
+			option::None =>
+			{
+			}
+		}
+	}
+	
+	// If the path is OK but the method is not then we have an OPTIONS request or a 405.
+	if option::is_none(&handler)
+	{
+		let mut methods = if is_static {~[~"GET"]} else {~[]};
+		for vec::each(config.router.candidates(request_path))
+		|index|
+		{
+			vec::push(&mut methods, copy config.route_list[*index].method);
+		}
+		
+		if vec::is_not_empty(methods)
+		{
+			if str::eq_slice(method, "OPTIONS")
+			{
+				handler = option::Some(options_view(allow_header(methods)));
+			}
+			else if !vec::contains(allowed_methods(methods), &method.to_unique())	// if the method is allowed then the problem was the mime type
+			{
+				code = status::MethodNotAllowed;
+				handler = option::Some(method_error_view(allow_header(methods)));
+				info!("[%s] responding with %s (method was %s)", id, code.to_str(), method);
 			}
 		}
 	}
@@ -256,107 +455,250 @@ priv fn find_handler(config: &connection::ConnConfig, method: &str, request_path
 	// Otherwise use the missing handler.
 	if option::is_none(&handler)
 	{
-		status_code = ~"404";
-		status_mesg = ~"Not Found";
+		code = status::NotFound;
 		handler = option::Some(copy(config.missing));
-		info!("responding with %s %s", status_code, status_mesg);
+		info!("[%s] responding with %s", id, code.to_str());
 	}
 	
-	return (status_code, status_mesg, result_type, option::get(&handler), matches);
+	return (code, result_type, option::get(&handler), matches, route_name);
 }
 
-priv fn load_template(config: &connection::ConnConfig, path: &Path) -> result::Result<@~str, ~str>
+priv fn find_route(config: &connection::ConnConfig, id: &str, method: &str, request_path: &str, params: &IMap<@~str, @~str>, types: &[~str]) -> Option<(ResponseHandler, ~str, HashMap<@~str, @~str>, ~str)>
 {
-	// {{ should be followed by }} (rust-mustache hangs if this is not the case).
-	fn match_curly_braces(text: &str) -> bool
+	for vec::each(config.router.candidates(request_path))
+	|index|
 	{
-		let mut index = 0u;
-		
-		while index < str::len(text)
+		let entry = &config.route_list[*index];
+		if str::eq_slice(entry.method, method)
 		{
-			match str::find_str_from(text, "{{", index)
+			let m = uri_template::match_template(request_path, entry.template);
+			if m.size() > 0u && uri_template::match_query(entry.template, params, m)
 			{
-				option::Some(i) =>
+				if vec::contains(types, &entry.mime_type)
 				{
-					match str::find_str_from(text, "}}", i + 2u)
-					{
-						option::Some(j) =>
-						{
-							index = j + 2u;
-						}
-						option::None() =>
-						{
-							return false;
-						}
-					}
+					return option::Some((config.views_table.get(@copy entry.route), copy entry.mime_type, m, copy entry.route));
 				}
-				option::None =>
+				else
 				{
-					break;
+					info!("[%s] request matches route but route type is %s not one of: %s", id, entry.mime_type, str::connect(types, ~", "));
 				}
 			}
 		}
-		return true;
+	}
+	option::None
+}
+
+// Methods from section 9 along with any extension methods used by routes.
+priv fn is_known_method(config: &connection::ConnConfig, method: &str) -> bool
+{
+	let standard = ~[~"GET", ~"HEAD", ~"POST", ~"PUT", ~"DELETE", ~"OPTIONS", ~"TRACE", ~"CONNECT", ~"PATCH"];
+	vec::contains(standard, &method.to_unique()) || vec::any(config.route_list, |entry| {str::eq_slice(entry.method, method)})
+}
+
+// Returns the methods that can be used given the methods of matching routes: this adds the
+// implicit HEAD and OPTIONS methods and removes duplicates.
+priv fn allowed_methods(methods: &[~str]) -> ~[~str]
+{
+	let mut result = ~[];
+	for vec::each(methods)
+	|method|
+	{
+		if !vec::contains(result, method)
+		{
+			vec::push(&mut result, copy *method);
+		}
+		if *method == ~"GET" && !vec::contains(result, &~"HEAD")
+		{
+			vec::push(&mut result, ~"HEAD");
+		}
+	}
+	if !vec::contains(result, &~"OPTIONS")
+	{
+		vec::push(&mut result, ~"OPTIONS");
+	}
+	result
+}
+
+priv fn allow_header(methods: &[~str]) -> ~str
+{
+	str::connect(allowed_methods(methods), ~", ")
+}
+
+// Used for OPTIONS requests that don't have an explicit route.
+priv fn options_view(allow: ~str) -> ResponseHandler
+{
+	|_config: &connection::ConnConfig, _request: &Request, response: &Response, copy allow|
+	{
+		response.headers.set(~"Allow", allow);
+		Response {body: StringBody(@~""), template: ~"", context: std::map::HashMap(), ..*response}
+	}
+}
+
+// Used for 405 and 501 errors. Allow should be empty for 501 errors.
+priv fn method_error_view(allow: ~str) -> ResponseHandler
+{
+	|_config: &connection::ConnConfig, _request: &Request, response: &Response, copy allow|
+	{
+		if str::is_not_empty(allow)
+		{
+			response.headers.set(~"Allow", allow);
+		}
+		error_pages::error_response(response, response.status)
+	}
+}
+
+// Returns the path to the template to use along with the language it is written in.
+priv fn localize_template(config: &connection::ConnConfig, response: &Response) -> (~str, Option<~str>)
+{
+	if vec::is_empty(config.languages)
+	{
+		return (copy response.template, option::None);
 	}
 	
-	do result::chain(config.load_rsrc(path))
-	|template|
+	match response.context.find(@~"locale")
 	{
-		let template = str::from_bytes(template);
-		if !config.settings.contains_key(@~"debug") || config.settings.get(@~"debug") == @~"false" || match_curly_braces(template)
+		option::Some(mustache::Str(locale)) =>
 		{
-			result::Ok(@template)
+			let template = negotiation::localized_path(response.template, *locale);
+			if config.valid_rsrc(&utils::url_to_path(&config.resources_root, template))
+			{
+				return (template, option::Some(copy *locale));
+			}
 		}
-		else
+		_ =>
 		{
-			result::Err(~"mismatched curly braces")
 		}
 	}
+	
+	// Templates without a language in their name are assumed to use the default language.
+	(copy response.template, option::Some(copy config.languages[0]))
 }
 
 priv fn process_template(config: &connection::ConnConfig, response: &Response, request: &Request) -> (Response, Body)
 {
-	let path = utils::url_to_path(&config.resources_root, response.template);
-	let (response, body) =
-		match load_template(config, &path)
+	let (template, language) = localize_template(config, response);
+	let path = utils::url_to_path(&config.resources_root, template);
+	let (response, body, compiled) =
+		match template_cache::find_template(config, &path, template_engine(config, template))
 		{
-			result::Ok(v) =>
+			result::Ok(cached) =>
 			{
 				// We found a legit template file.
-				(Response {status: response.status, ..*response}, v)		// hacky way to return a new Response without a copy
+				match language
+				{
+					option::Some(ref language) =>
+					{
+						response.headers.set(~"Content-Language", *language);
+						response.headers.add_to_list(~"Vary", ~"Accept-Language");
+					}
+					option::None =>
+					{
+					}
+				}
+				
+				// We only render UTF-8 but section 14.2 allows us to send it even if the client didn't ask for it.
+				match request.headers.find(~"accept-charset")
+				{
+					option::Some(ref charsets) if !negotiation::accepts_charset(*charsets, ~"UTF-8") =>
+					{
+						info!("[%s] client wants one of '%s' but we're sending UTF-8", request.id, *charsets);
+					}
+					_ =>
+					{
+					}
+				}
+				(Response {status: response.status, ..*response}, cached.text, option::Some(cached.template))		// hacky way to return a new Response without a copy
 			}
 			result::Err(ref mesg) =>
 			{
 				// We failed to load the template so use the hard-coded config.read_error body.
 				let context = std::map::HashMap();
 				context.insert(@~"request-path", mustache::Str(@copy request.path));
-				let body = mustache::compile_str(config.read_error).render_data(mustache::Map(context));
+				let body = mustache::compile_str(config.read_error).render_data(mustache::Map(html::escape_context(context)));
 				
 				if config.server_info != ~"unit test"
 				{
-					error!("Error '%s' tying to read '%s'", *mesg, path.to_str());
+					error!("[%s] Error '%s' tying to read '%s'", request.id, *mesg, path.to_str());
 				}
-				(make_initial_response(config, ~"403", ~"Forbidden", ~"text/html; charset=UTF-8", request), @body)
+				(make_initial_response(config, status::Forbidden, ~"text/html; charset=UTF-8", request), @body, option::None)
 			}
 		};
 	
-	if !str::starts_with(response.status, "403") && response.context.size() > 0u
+	match compiled
 	{
-		// If we were able to load a template, and we have context, then use the
-		// context to expand the template.
-		let base_dir = url_dirname(response.template);
-		let base_url = fmt!("http://%s:%?/%s/", request.local_addr, config.port, base_dir);
-		response.context.insert(@~"base-path", mustache::Str(@base_url));
-		
-		let body = mustache::compile_str(*body).render_data(mustache::Map(response.context));
-		(response, StringBody(@body))
+		option::Some(template) if response.context.size() > 0u =>
+		{
+			// If we were able to load a template, and we have context, then use the
+			// context to expand the template.
+			let base_dir = url_dirname(response.template);
+			let base_url = fmt!("http://%s:%?/%s/", request.local_addr, config.port, base_dir);
+			response.context.insert(@~"base-path", mustache::Str(@base_url));
+			if !response.context.contains_key(@~"urls")
+			{
+				response.context.insert(@~"urls", urls::route_urls(config, request));
+			}
+			
+			for vec::each(config.global_context(config, request))
+			|entry|
+			{
+				match *entry
+				{
+					(ref key, ref value) if !response.context.contains_key(@copy *key) =>
+					{
+						response.context.insert(@copy *key, copy *value);
+					}
+					_ =>
+					{
+					}
+				}
+			}
+			
+			let body = if is_html(&response) {template.render(html::escape_context(response.context))} else {template.render(response.context)};
+			(response, StringBody(@apply_layout(config, &response, body)))
+		}
+		_ =>
+		{
+			(response, StringBody(body))
+		}
 	}
-	else
+}
+
+// Renders the response's layout with the context's content entry set to body.
+priv fn apply_layout(config: &connection::ConnConfig, response: &Response, body: ~str) -> ~str
+{
+	if str::is_empty(response.layout) || !is_html(response)
+	{
+		return body;
+	}
+	
+	let path = utils::url_to_path(&config.resources_root, response.layout);
+	match template_cache::find_template(config, &path, template_engine(config, response.layout))
 	{
-		(response, StringBody(body))
+		result::Ok(layout) =>
+		{
+			response.context.insert(@~"content", html::raw_html(body));
+			layout.template.render(html::escape_context(response.context))
+		}
+		result::Err(ref mesg) =>
+		{
+			error!("Error '%s' tying to read layout '%s'", *mesg, path.to_str());
+			body
+		}
 	}
 }
 
+priv fn is_html(response: &Response) -> bool
+{
+	html::is_html_type(response.headers.content_type().get_default(~""))
+}
+
+// Views can use any file as a template so if config.is_template doesn't know which engine to use
+// we fall back to mustache.
+priv fn template_engine(config: &connection::ConnConfig, template: &str) -> ~str
+{
+	config.is_template(config, template).get_default(~".mustache")
+}
+
 priv fn url_dirname(path: &str) -> ~str
 {
 	match str::find_char(path, '/')
@@ -422,7 +764,7 @@ fn make_request(url: ~str, mime_type: ~str) -> HttpRequest
 		(~"accept-Language", ~"en-us,en"),
 		(~"accept-encoding", ~"gzip, deflate"),
 		(~"connection", ~"keep-alive")];
-	HttpRequest {method: ~"GET", major_version: 1, minor_version: 1, url: url, headers: headers, body: ~""}
+	HttpRequest {method: ~"GET", major_version: 1, minor_version: 1, url: copy url, raw_url: url, headers: headers, body: ~"", parse_time: 0u64, id: ~""}
 }
 
 #[test]
@@ -592,7 +934,7 @@ fn path_outside_root()
 	
 	assert header.contains("Content-Type: text/html");
 	assert header.contains("403 Forbidden");
-	assert str::contains(body.to_str(), "server/html/not-found.html contents");
+	assert str::contains(body.to_str(), "server/html/forbidden.html contents");
 }
 
 #[test]
@@ -642,44 +984,543 @@ fn bad_version()
 	
 	assert header.contains("Content-Type: text/html");
 	assert header.contains("505 HTTP Version Not Supported");
-	assert str::contains(body.to_str(), "server/html/not-found.html contents");
+	assert str::contains(body.to_str(), "server/html/not-supported.html contents");
 }
 
 #[test]
-fn bad_template()
+fn localized_template()
 {
-	fn bad_loader(_path: &Path) ->  result::Result<~[u8], ~str>
-	{
-		result::Ok(str::to_bytes(~"unbalanced {{curly}} {{braces}"))
-	}
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader,
+		valid_rsrc: |path| {str::ends_with(path.to_str(), "test.fr.html")},
+		languages: ~[~"en", ~"fr"]
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {headers: ~[(~"accept", ~"text/html"), (~"accept-language", ~"fr-ca, en;q=0.5")], .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("Content-Language: fr");
+	assert header.contains("Vary: Accept-Language");
+	assert body.to_str() == ~"server/html/test.fr.html contents";
+	
+	let request = HttpRequest {headers: ~[(~"accept", ~"text/html"), (~"accept-language", ~"de, en;q=0.5")], .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("Content-Language: en");
+	assert body.to_str() == ~"server/html/test.html contents";
+}
+
+#[test]
+fn head_request()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
 	
+	let request = HttpRequest {method: ~"HEAD", .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("200 OK");
+	assert header.contains(fmt!("Content-Length: %?", str::len("server/html/test.html contents")));
+	assert body.to_str() == ~"";
+}
+
+#[test]
+fn options_request()
+{
 	let config = Config {
 		hosts: ~[~"localhost"],
 		server_info: ~"unit test",
 		resources_root: path::from_str(~"server/html"),
-		routes: ~[(~"GET", ~"/foo/baz", ~"foo")],
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo"), (~"POST", ~"/foo/{name}", ~"foo")],
 		views: ~[(~"foo",  test_view)],
-		load_rsrc: bad_loader,
-		valid_rsrc: |_path| {true},
-		settings: ~[(~"debug", ~"true")],
-		..initialize_config()};
+		load_rsrc: null_loader
+		, .. initialize_config()};
 		
 	let eport = comm::Port();
 	let ech = comm::Chan(&eport);
 	let iconfig = connection::config_to_conn(&config, ech);
 	
-	match load_template(&iconfig, &path::from_str(~"blah.html"))
-	{
-		result::Ok(ref v) =>
-		{
-			io::stderr().write_line(~"Expected error but found: " + **v);
-			assert false;
-		}
-		result::Err(ref s) =>
-		{
-			assert str::contains(*s, "mismatched curly braces");
-		}
-	}
+	let request = HttpRequest {method: ~"OPTIONS", .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("200 OK");
+	assert header.contains("Allow: GET, HEAD, POST, OPTIONS");
+	assert header.contains("Content-Length: 0");
+	assert body.to_str() == ~"";
+}
+
+#[test]
+fn method_not_allowed()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {method: ~"DELETE", .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("405 Method Not Allowed");
+	assert header.contains("Allow: GET, HEAD, OPTIONS");
+	assert str::contains(body.to_str(), "The DELETE method is not supported");
+}
+
+#[test]
+fn unknown_method()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {method: ~"Explode", .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("501 Not Implemented");
+	assert !header.contains("Allow:");
+}
+
+#[test]
+fn no_content()
+{
+	fn deleted_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		Response {status: status::NoContent, body: StringBody(@~"oops"), ..*response}
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"DELETE", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  deleted_view)],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {method: ~"DELETE", .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("204 No Content");
+	assert !header.contains("Content-Length");
+	assert body.to_str() == ~"";
+}
+
+#[test]
+fn if_modified_since()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader,
+		valid_rsrc: |_path| {true},
+		rsrc_modified: |_path| {option::Some(784111777i64)}
+		, ..initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {headers: ~[(~"accept", ~"image/jpeg"), (~"if-modified-since", ~"Sun, 06 Nov 1994 08:49:37 GMT")], .. make_request(~"/foo/baz.jpg", ~"image/jpeg")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("304 Not Modified");
+	assert header.contains("Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT");
+	assert body.to_str() == ~"";
+	
+	let request = HttpRequest {headers: ~[(~"accept", ~"image/jpeg"), (~"if-modified-since", ~"Sat, 05 Nov 1994 08:49:37 GMT")], .. make_request(~"/foo/baz.jpg", ~"image/jpeg")};
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	assert header.contains("200 OK");
+}
+
+#[test]
+fn cache_policies()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader,
+		valid_rsrc: |path| {str::ends_with(path.to_str(), ".js")},
+		cache_policies: ~[
+			cache_policy::immutable_policy(),
+			cache_policy::CachePolicy {target: cache_policy::RouteName(~"foo"), cache_control: ~[~"private", ~"max-age=60"], vary: ~[~"Cookie"]},
+		]
+		, ..initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/js/app.3f9a2c1b.js", ~"*/*");
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Cache-Control: public, max-age=31536000, immutable");
+	assert header.contains("Expires: ");
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Cache-Control: private, max-age=60");
+	assert header.contains("Vary: Cookie");
+	
+	let request = make_request(~"/js/app.js", ~"*/*");
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert !header.contains("Cache-Control");
+}
+
+#[test]
+fn cached_response()
+{
+	fn cached_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		response.headers.set_cache_control(~[~"max-age=60"]);
+		Response {body: StringBody(@~"hello"), ..*response}
+	}
+	
+	let cache = response_cache::start_cache(response_cache::default_limits());
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  cached_view)],
+		load_rsrc: null_loader,
+		response_cache: option::Some(cache)
+		, ..initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert !header.contains("Age:");
+	assert body.to_str() == ~"hello";
+	
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Age:");
+	assert header.contains("Cache-Control: max-age=60");
+	assert str::from_bytes(response_cache::body_bytes(&body)) == ~"hello";
+	
+	let stats = response_cache::cache_stats(cache);
+	assert stats.hits == 1u;
+	assert stats.misses == 1u;
+	response_cache::stop_cache(cache);
+}
+
+#[test]
+fn layout_template()
+{
+	fn layout_loader(path: &Path) -> result::Result<~[u8], ~str>
+	{
+		if str::ends_with(path.to_str(), "layout.html")
+		{
+			result::Ok(str::to_bytes(~"<body>{{{content}}}</body>"))
+		}
+		else
+		{
+			result::Ok(str::to_bytes(~"hello from {{site-name}}"))
+		}
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo"), (~"GET", ~"/data<text/plain>", ~"data")],
+		views: ~[(~"foo",  test_view), (~"data",  test_view)],
+		load_rsrc: layout_loader,
+		layout: ~"layout.html",
+		settings: ~[(~"site-name", ~"Example")]
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	let (_header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert utils::check_strs(body.to_str(), ~"<body>hello from Example</body>");
+	
+	// Layouts are only used for html.
+	let request = make_request(~"/data", ~"text/plain");
+	let (_header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert utils::check_strs(body.to_str(), ~"hello from Example");
+}
+
+#[test]
+fn template_engines()
+{
+	fn tmpl_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		Response {template: ~"page.tmpl", ..*response}
+	}
+	
+	fn tmpl_loader(_path: &Path) -> result::Result<~[u8], ~str>
+	{
+		result::Ok(str::to_bytes(~"{{path}} is ${request-path}"))
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  tmpl_view)],
+		load_rsrc: tmpl_loader,
+		template_engines: ~[(~".mustache", template_engine::mustache_engine), (~".tmpl", template_engine::substitution_engine)]
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	let (_header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert utils::check_strs(body.to_str(), ~"{{path}} is /foo/bar");
+}
+
+#[test]
+fn escaped_template()
+{
+	fn path_loader(_path: &Path) -> result::Result<~[u8], ~str>
+	{
+		result::Ok(str::to_bytes(~"<p>{{request-path}}</p>"))
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/hello/{name}", ~"hello")],
+		views: ~[(~"hello",  test_view)],
+		load_rsrc: path_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/hello/<script>", ~"text/html");
+	let (_header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert utils::check_strs(body.to_str(), ~"<p>/hello/&lt;script&gt;</p>");
+}
+
+#[test]
+fn json_request()
+{
+	fn echo_view(_config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+	{
+		match request.json_body(response)
+		{
+			result::Ok(ref value) =>
+			{
+				json::json_response(response, status::OK, &json::Object(~[(~"name", value.find(~"name").get())]))
+			}
+			result::Err(move response) =>
+			{
+				response
+			}
+		}
+	}
+	
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"POST", ~"/items<application/json>", ~"echo")],
+		views: ~[(~"echo",  echo_view)]
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let headers = ~[(~"accept", ~"application/json"), (~"content-type", ~"application/json")];
+	let request = HttpRequest {method: ~"POST", headers: copy headers, body: ~"{\"name\": \"Bob\", \"id\": 3}", .. make_request(~"/items", ~"application/json")};
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("200 OK");
+	assert header.contains("Content-Type: application/json; charset=UTF-8");
+	assert utils::check_strs(body.to_str(), ~"{\"name\":\"Bob\"}");
+	
+	let request = HttpRequest {method: ~"POST", headers: copy headers, body: ~"{\"name\": ", .. make_request(~"/items", ~"application/json")};
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("400 Bad Request");
+	
+	let request = HttpRequest {method: ~"POST", headers: ~[(~"accept", ~"application/json"), (~"content-type", ~"text/plain")], body: ~"Bob", .. make_request(~"/items", ~"application/json")};
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("415 Unsupported Media Type");
+}
+
+#[test]
+fn route_groups()
+{
+	fn route_view(_config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
+	{
+		Response {body: StringBody(@fmt!("%s %s", request.route, *request.matches.get(@~"id"))), ..*response}
+	}
+	
+	let group = RouteGroup {
+		routes: ~[(~"GET", ~"/items/{id}", ~"item")],
+		views: ~[(~"item",  route_view)],
+		static_root: option::Some(path::from_str(~"server/html")),
+		..route_group::route_group(~"api", ~"/api")};
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"/tmp"),
+		groups: ~[group],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/api/items/7", ~"text/html");
+	let (_header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert utils::check_strs(body.to_str(), ~"api.item 7");
+	
+	let request = make_request(~"/api/home.js", ~"*/*");
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Content-Type: text/javascript");
+	assert utils::check_strs(body.to_str(), ~"<binary data>");
+}
+
+#[test]
+fn metrics_endpoint()
+{
+	let metrics = metrics::start_metrics();
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader,
+		metrics: option::Some(metrics)
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	
+	let request = make_request(~"/metrics", ~"text/plain");
+	let (header, body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	metrics::stop_metrics(metrics);
+	
+	assert header.contains("Content-Type: text/plain; version=0.0.4");
+	assert body.to_str().contains("rwebserve_requests_total{route=\"foo\",status=\"200\"} 1\n");
+}
+
+#[test]
+fn cached_metrics()
+{
+	fn cached_view(_config: &connection::ConnConfig, _request: &Request, response: &Response) -> Response
+	{
+		response.headers.set_cache_control(~[~"max-age=60"]);
+		Response {body: StringBody(@~"hello"), ..*response}
+	}
+	
+	let metrics = metrics::start_metrics();
+	let cache = response_cache::start_cache(response_cache::default_limits());
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  cached_view)],
+		response_cache: option::Some(cache),
+		metrics: option::Some(metrics)
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("Age:");
+	
+	// The cache hit is recorded under the route which produced the response.
+	let snapshot = metrics::metrics_snapshot(metrics);
+	assert snapshot.requests == ~[(~"foo", 200u, 2u)];
+	metrics::stop_metrics(metrics);
+	response_cache::stop_cache(cache);
+}
+
+#[test]
+fn request_tracing()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/foo/bar", ~"foo")],
+		views: ~[(~"foo",  test_view)],
+		load_rsrc: null_loader
+		, .. initialize_config()};
+		
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	let request = HttpRequest {headers: ~[(~"accept", ~"text/html"), (~"x-request-id", ~"client-42")], .. make_request(~"/foo/bar", ~"text/html")};
+	let (header, _body, trace) = process_traced_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains("X-Request-ID: client-42\r\n");
+	assert trace.id == ~"client-42";
+	assert vec::map(trace.spans(), |s| {copy s.name}) == ~[~"parse", ~"routing", ~"handler", ~"render"];
+	
+	let request = make_request(~"/foo/bar", ~"text/html");
+	let (header, _body) = process_request(&iconfig, &request, ~"10.11.12.13", ~"1.2.3.4");
+	assert header.contains(fmt!("X-Request-ID: %s-1\r\n", iconfig.id_prefix));
 }
 
 #[test]
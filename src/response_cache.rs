@@ -0,0 +1,529 @@
+//! Optional in-memory cache of responses to GET requests (see RFC 7234).
+//
+// The cache is a task shared by all connections so entries are stored in a sendable form.
+// Only responses with explicit freshness information (s-maxage, max-age, or Expires) are
+// cached. Hits are served without calling the view at all.
+
+/// Limits on the resources used by the cache.
+///
+/// * max_bytes: the cache will evict least recently used entries to keep the total size of response bodies under this.
+/// * max_entry_bytes: responses with larger bodies are not cached.
+/// * max_ttl: maximum number of seconds that an entry will be used (even if the response allows longer caching).
+pub struct CacheLimits
+{
+	pub max_bytes: uint,
+	pub max_entry_bytes: uint,
+	pub max_ttl: i64,
+}
+
+/// Returns limits of 16 MB total, 1 MB per response, and a one hour time to live.
+pub fn default_limits() -> CacheLimits
+{
+	CacheLimits {max_bytes: 16u*1024u*1024u, max_entry_bytes: 1024u*1024u, max_ttl: 3600i64}
+}
+
+/// Returned by cache_stats.
+pub struct CacheStats
+{
+	pub entries: uint,
+	pub bytes: uint,
+	pub hits: uint,
+	pub misses: uint,
+}
+
+/// Sendable version of a Response (and the name of the route which produced it).
+pub struct CachedResponse
+{
+	pub route: ~str,
+	pub status: uint,
+	pub headers: ~[(~str, ~str)],
+	pub body: ~[u8],
+	pub stored_at: i64,
+	pub expires_at: i64,
+}
+
+/// Messages handled by the cache task. Normally the helper functions below are used instead
+/// of sending these directly.
+pub enum CacheMesg
+{
+	LookupMesg(~str, ~[(~str, ~str)], comm::Chan<Option<CachedResponse>>),	// key, request headers, reply channel
+	StoreMesg(~str, ~[(~str, ~str)], CachedResponse),						// key, request headers, response
+	InvalidateMesg(~str),													// path prefix
+	ClearMesg,
+	StatsMesg(comm::Chan<CacheStats>),
+	ExitMesg,
+}
+
+/// Used to communicate with the cache task. Set Config.response_cache to enable caching.
+pub type CacheChan = comm::Chan<CacheMesg>;
+
+/// Starts up a new cache task.
+pub fn start_cache(limits: CacheLimits) -> CacheChan
+{
+	do task::spawn_listener
+	|port: comm::Port<CacheMesg>|
+	{
+		run_cache(limits, port);
+	}
+}
+
+/// Removes all entries whose path starts with path_prefix (e.g. "/users/10" or "/").
+///
+/// Views which modify resources (e.g. via POST or PUT) should call this so that stale versions
+/// of the resource are not served.
+pub fn invalidate(cache: CacheChan, path_prefix: &str)
+{
+	comm::send(cache, InvalidateMesg(path_prefix.to_unique()));
+}
+
+/// Removes all entries.
+pub fn clear_cache(cache: CacheChan)
+{
+	comm::send(cache, ClearMesg);
+}
+
+pub fn cache_stats(cache: CacheChan) -> CacheStats
+{
+	let port = comm::Port();
+	comm::send(cache, StatsMesg(comm::Chan(&port)));
+	comm::recv(port)
+}
+
+/// Stops the cache task.
+pub fn stop_cache(cache: CacheChan)
+{
+	comm::send(cache, ExitMesg);
+}
+
+// ---- Functions used by process_request ---------------------------------------
+/// Returns a cached response for the request and the name of the route which produced it or None
+/// if there isn't a fresh entry.
+pub fn find_cached(config: &connection::ConnConfig, request: &Request) -> Option<(Response, Body, ~str)>
+{
+	match config.response_cache
+	{
+		option::Some(cache) if can_use_cache(request) && !bypass_cache(request) =>
+		{
+			let port = comm::Port();
+			comm::send(cache, LookupMesg(cache_key(request), header_list(request.headers), comm::Chan(&port)));
+			match comm::recv(port)
+			{
+				option::Some(cached) =>
+				{
+					debug!("serving %s from the cache", request.path);
+					let now = std::time::get_time().sec;
+					let headers = headers::headers_from(cached.headers);
+					headers.set(~"Date", config.date_cache.now());
+					headers.set(~"Age", i64::to_str(now - cached.stored_at, 10u));
+
+					let body = BinaryBody(@copy cached.body);
+					let response = Response {status: status::from_code(cached.status).get(), headers: headers, body: copy body, template: ~"", context: std::map::HashMap(), layout: ~""};
+					option::Some((response, body, copy cached.route))
+				}
+				option::None =>
+				{
+					option::None
+				}
+			}
+		}
+		_ =>
+		{
+			option::None
+		}
+	}
+}
+
+/// Adds the response to the cache if it is cacheable.
+pub fn store_response(config: &connection::ConnConfig, request: &Request, response: &Response, body: &Body)
+{
+	match config.response_cache
+	{
+		option::Some(cache) if can_use_cache(request) =>
+		{
+			match freshness_lifetime(request, response)
+			{
+				option::Some(lifetime) =>
+				{
+					let now = std::time::get_time().sec;
+					let mut headers = ~[];
+					for response.headers.each
+					|name, value|
+					{
+						if str::to_lower(name) != ~"date"		// we'll set this when we use the entry
+						{
+							vec::push(&mut headers, (name.to_unique(), value.to_unique()));
+						}
+					}
+
+					let cached = CachedResponse {route: copy request.route, status: response.status.code(), headers: headers, body: body_bytes(body), stored_at: now, expires_at: now + lifetime};
+					comm::send(cache, StoreMesg(cache_key(request), header_list(request.headers), cached));
+				}
+				option::None =>
+				{
+				}
+			}
+		}
+		_ =>
+		{
+		}
+	}
+}
+
+priv fn can_use_cache(request: &Request) -> bool
+{
+	let no_store = vec::contains(request.headers.cache_control(), &~"no-store");
+	let is_sse = vec::any(request.headers.get_list(~"accept"), |t| {str::starts_with(*t, "text/event-stream")});
+	(request.method == ~"GET" || request.method == ~"HEAD") && !no_store && !is_sse
+}
+
+// Clients can ask us to revalidate cached responses (see section 5.2.1 of RFC 7234).
+priv fn bypass_cache(request: &Request) -> bool
+{
+	let directives = request.headers.cache_control();
+	vec::contains(directives, &~"no-cache") || vec::contains(directives, &~"max-age=0") ||
+		vec::any(request.headers.get_list(~"pragma"), |p| {str::to_lower(*p) == ~"no-cache"})
+}
+
+// Returns the number of seconds that the response may be cached for or None if it should not be cached.
+priv fn freshness_lifetime(request: &Request, response: &Response) -> Option<i64>
+{
+	let directives = response.headers.cache_control();
+	let cacheable = response.status == status::OK || response.status == status::MovedPermanently;
+	if !cacheable || response.headers.contains_key(~"Set-Cookie") || vec::contains(response.headers.get_list(~"Vary"), &~"*")
+	{
+		return option::None;
+	}
+	if vec::any(directives, |d| {*d == ~"no-store" || *d == ~"no-cache" || *d == ~"private"})
+	{
+		return option::None;
+	}
+	if request.headers.contains_key(~"authorization") && !vec::contains(directives, &~"public")
+	{
+		return option::None;		// see section 3.2
+	}
+
+	// s-maxage is for shared caches like us so it takes precedence over max-age.
+	for vec::each(~[~"s-maxage=", ~"max-age="])
+	|prefix|
+	{
+		match vec::find(directives, |d| {str::starts_with(*d, *prefix)})
+		{
+			option::Some(ref directive) =>
+			{
+				return do int::from_str(directive.slice(prefix.len(), directive.len())).chain |secs|
+				{
+					if secs > 0 {option::Some(secs as i64)} else {option::None}
+				};
+			}
+			option::None =>
+			{
+			}
+		}
+	}
+
+	match (response.headers.expires(), response.headers.date())
+	{
+		(option::Some(expires), option::Some(date)) if expires > date => option::Some(expires - date),
+		_ => option::None,
+	}
+}
+
+// Keys look like "GET /users?page=2". The raw query is used because the URL was percent decoded
+// before it was split into params so "?a=1%26b%3D2" and "?a=1&b=2" have the same params.
+priv fn cache_key(request: &Request) -> ~str
+{
+	if str::is_empty(request.query)
+	{
+		fmt!("%s %s", request.method, request.path)
+	}
+	else
+	{
+		fmt!("%s %s?%s", request.method, request.path, request.query)
+	}
+}
+
+// Returns the path portion of a cache key.
+priv fn key_path(key: &str) -> ~str
+{
+	match str::find_char(key, ' ')
+	{
+		option::Some(i) => key.slice(i+1u, key.len()),
+		option::None => key.to_unique(),
+	}
+}
+
+priv fn header_list(headers: headers::Headers) -> ~[(~str, ~str)]
+{
+	let mut result = ~[];
+	for headers.each |name, value| {vec::push(&mut result, (str::to_lower(name), value.to_unique()))};
+	result
+}
+
+/// Returns the contents of body as bytes.
+pub fn body_bytes(body: &Body) -> ~[u8]
+{
+	match *body
+	{
+		StringBody(text) =>
+		{
+			str::to_bytes(*text)
+		}
+		BinaryBody(binary) =>
+		{
+			copy *binary
+		}
+		CompoundBody(parts) =>
+		{
+			let mut result = ~[];
+			for parts.each |part| {result += body_bytes(*part)};
+			result
+		}
+	}
+}
+
+// ---- Cache task --------------------------------------------------------------
+priv struct Entry
+{
+	vary: ~[(~str, ~str)],			// lower cased header names and the values from the request which produced the response
+	response: CachedResponse,
+	size: uint,
+	mut last_used: uint,			// value of the cache's clock when the entry was last stored or returned
+}
+
+priv fn run_cache(limits: CacheLimits, port: comm::Port<CacheMesg>)
+{
+	let table: HashMap<@~str, @~[@Entry]> = std::map::HashMap();
+	let mut bytes = 0u;
+	let mut hits = 0u;
+	let mut misses = 0u;
+	let mut clock = 0u;
+
+	loop
+	{
+		clock += 1u;
+		match comm::recv(port)
+		{
+			LookupMesg(key, request_headers, reply) =>
+			{
+				let now = std::time::get_time().sec;
+				let mut result = option::None;
+				match table.find(@copy key)
+				{
+					option::Some(entries) =>
+					{
+						match vec::find(*entries, |e| {vary_matches(e.vary, request_headers)})
+						{
+							option::Some(entry) if now < entry.response.expires_at =>
+							{
+								entry.last_used = clock;
+								result = option::Some(copy entry.response);
+							}
+							option::Some(entry) =>
+							{
+								bytes -= remove_entry(table, key, entry);
+							}
+							option::None =>
+							{
+							}
+						}
+					}
+					option::None =>
+					{
+					}
+				}
+				if result.is_some() {hits += 1u} else {misses += 1u}
+				comm::send(reply, result);
+			}
+			StoreMesg(key, request_headers, response) =>
+			{
+				let size = vec::len(response.body);
+				if size <= limits.max_entry_bytes && size <= limits.max_bytes
+				{
+					let vary = vary_values(response.headers, request_headers);
+					match table.find(@copy key)
+					{
+						option::Some(entries) =>
+						{
+							match vec::find(*entries, |e| {e.vary == vary})
+							{
+								option::Some(old) => {bytes -= remove_entry(table, key, old);}
+								option::None => {}
+							}
+						}
+						option::None =>
+						{
+						}
+					}
+
+					let max_expires = response.stored_at + limits.max_ttl;
+					let expires_at = if response.expires_at < max_expires {response.expires_at} else {max_expires};
+					let entry = @Entry {vary: vary, response: CachedResponse {expires_at: expires_at, ..response}, size: size, last_used: clock};
+					let entries = match table.find(@copy key) {option::Some(entries) => *entries + ~[entry], option::None => ~[entry]};
+					table.insert(@copy key, @entries);
+					bytes += size;
+
+					while bytes > limits.max_bytes
+					{
+						let (lru_key, lru) = least_recently_used(table);
+						bytes -= remove_entry(table, lru_key, lru);
+					}
+				}
+			}
+			InvalidateMesg(prefix) =>
+			{
+				let mut keys = ~[];
+				for table.each_key |key| {if str::starts_with(key_path(*key), prefix) {vec::push(&mut keys, key)}};
+				for vec::each(keys)
+				|key|
+				{
+					for table.get(*key).each |entry| {bytes -= entry.size};
+					table.remove(*key);
+				}
+			}
+			ClearMesg =>
+			{
+				let mut keys = ~[];
+				for table.each_key |key| {vec::push(&mut keys, key)};
+				for vec::each(keys) |key| {table.remove(*key);};
+				bytes = 0u;
+			}
+			StatsMesg(reply) =>
+			{
+				let mut entries = 0u;
+				for table.each_value |v| {entries += vec::len(*v)};
+				comm::send(reply, CacheStats {entries: entries, bytes: bytes, hits: hits, misses: misses});
+			}
+			ExitMesg =>
+			{
+				break;
+			}
+		}
+	}
+}
+
+// Always varying on Accept is conservative but routes are selected using Accept so responses
+// may depend upon it even if they don't include a Vary header.
+priv fn vary_values(response_headers: &[(~str, ~str)], request_headers: &[(~str, ~str)]) -> ~[(~str, ~str)]
+{
+	let mut names = ~[~"accept"];
+	for vec::each(response_headers)
+	|header|
+	{
+		if str::to_lower(header.first()) == ~"vary"
+		{
+			for vec::each(str::split_char(header.second(), ',')) |name| {vec::push(&mut names, str::to_lower(str::trim(*name)))};
+		}
+	}
+
+	do vec::map(names)
+	|name|
+	{
+		let values = do vec::filter_map(request_headers) |h| {if h.first() == *name {option::Some(h.second())} else {option::None}};
+		(copy *name, str::connect(values, ~", "))
+	}
+}
+
+priv fn vary_matches(vary: &[(~str, ~str)], request_headers: &[(~str, ~str)]) -> bool
+{
+	do vec::all(vary)
+	|v|
+	{
+		let values = do vec::filter_map(request_headers) |h| {if h.first() == v.first() {option::Some(h.second())} else {option::None}};
+		str::connect(values, ~", ") == v.second()
+	}
+}
+
+// Returns the size of the removed entry.
+priv fn remove_entry(table: HashMap<@~str, @~[@Entry]>, key: &str, entry: @Entry) -> uint
+{
+	let key = @key.to_unique();
+	let remaining = vec::filter(*table.get(key), |e| {!box::ptr_eq(*e, entry)});
+	if vec::is_empty(remaining)
+	{
+		table.remove(key);
+	}
+	else
+	{
+		table.insert(key, @remaining);
+	}
+	entry.size
+}
+
+priv fn least_recently_used(table: HashMap<@~str, @~[@Entry]>) -> (~str, @Entry)
+{
+	let mut result = option::None;
+	for table.each
+	|key, entries|
+	{
+		for entries.each
+		|entry|
+		{
+			let older = match result {option::Some((_, old)) => entry.last_used < old.last_used, option::None => true};
+			if older
+			{
+				result = option::Some((copy *key, *entry));
+			}
+		}
+	}
+	option::get(&result)
+}
+
+#[test]
+fn vary_keys()
+{
+	let request = ~[(~"accept", ~"text/html"), (~"accept-language", ~"fr"), (~"user-agent", ~"Mozilla/5.0")];
+	let vary = vary_values(~[(~"Content-Type", ~"text/html"), (~"Vary", ~"Accept-Language")], request);
+	assert vary == ~[(~"accept", ~"text/html"), (~"accept-language", ~"fr")];
+
+	assert vary_matches(vary, ~[(~"accept-language", ~"fr"), (~"accept", ~"text/html")]);
+	assert !vary_matches(vary, ~[(~"accept-language", ~"en"), (~"accept", ~"text/html")]);
+	assert !vary_matches(vary, ~[(~"accept", ~"text/html")]);
+}
+
+#[test]
+fn cache_limits()
+{
+	let cache = start_cache(CacheLimits {max_bytes: 10u, max_entry_bytes: 6u, max_ttl: 60i64});
+	let now = std::time::get_time().sec;
+	let headers = ~[(~"accept", ~"text/html")];
+	let response = |body: ~str| {CachedResponse {route: ~"", status: 200u, headers: ~[], body: str::to_bytes(body), stored_at: now, expires_at: now + 600i64}};
+
+	comm::send(cache, StoreMesg(~"GET /a", copy headers, response(~"aaaa")));
+	comm::send(cache, StoreMesg(~"GET /b", copy headers, response(~"bbbb")));
+	comm::send(cache, StoreMesg(~"GET /c", copy headers, response(~"ccccccc")));		// too large
+	let stats = cache_stats(cache);
+	assert stats.entries == 2u;
+	assert stats.bytes == 8u;
+
+	comm::send(cache, StoreMesg(~"GET /c", copy headers, response(~"cccc")));			// evicts /a
+	let stats = cache_stats(cache);
+	assert stats.entries == 2u;
+	assert stats.bytes == 8u;
+
+	invalidate(cache, ~"/b");
+	assert cache_stats(cache).entries == 1u;
+
+	let port = comm::Port();
+	comm::send(cache, LookupMesg(~"GET /c", copy headers, comm::Chan(&port)));
+	match comm::recv(port)
+	{
+		option::Some(ref cached) => assert cached.expires_at == now + 60i64,		// clamped to max_ttl
+		option::None => fail ~"expected a cache hit",
+	}
+	stop_cache(cache);
+}
+
+#[test]
+fn cache_keys()
+{
+	let request = |method: ~str, path: ~str, query: ~str| {
+		Request {version: ~"1.1", method: method, local_addr: ~"", remote_addr: ~"", path: path, matches: std::map::HashMap(), route: ~"",
+			params: ~[], query: query, headers: headers::Headers(), body: ~"", cookies: ~[], id: ~"1", trace: tracing::Trace(~"1")}
+	};
+
+	assert cache_key(&request(~"GET", ~"/users", ~"")) == ~"GET /users";
+	assert cache_key(&request(~"HEAD", ~"/users", ~"")) == ~"HEAD /users";
+	assert cache_key(&request(~"GET", ~"/search", ~"a=1%26b%3D2")) == ~"GET /search?a=1%26b%3D2";
+	assert cache_key(&request(~"GET", ~"/search", ~"a=1&b=2")) == ~"GET /search?a=1&b=2";
+	assert key_path(~"GET /users?page=2") == ~"/users?page=2";
+}
@@ -0,0 +1,194 @@
+//! Route groups allow an application to be composed from modules which are mounted beneath a path prefix.
+
+/// A set of routes and views (and optionally static files) mounted beneath prefix.
+///
+/// * name: prepended to the group's route names, e.g. with "api" the route "users" is named "api.users"
+/// (use this name with url_for and the urls template entry). If empty the route names are used as is.
+/// * prefix: path the group is mounted at, e.g. "/api/v1" or "/admin". This is prepended to the templates
+/// of the group's routes and the prefixes of nested groups.
+/// * routes: like Config.routes except that the templates are relative to prefix.
+/// * views: maps the group's (unqualified) route names to view handler functions.
+/// * static_root: if set URLs beneath prefix which don't match a route are served from this directory
+/// (instead of from resources_root). Note that files in static_root are served as is, i.e. they are not
+/// rendered as templates.
+/// * middleware: layers used for requests whose path is beneath prefix (after Config.middleware and the
+/// middleware of enclosing groups). This includes requests for static files and URLs which don't match a route.
+/// * groups: nested groups.
+///
+/// route_group can be used to initialize the group. Like Config this is a sendable and copyable type.
+pub struct RouteGroup
+{
+	pub name: ~str,
+	pub prefix: ~str,
+	pub routes: ~[(~str, ~str, ~str)],
+	pub views: ~[(~str, ResponseHandler)],
+	pub static_root: Option<Path>,
+	pub middleware: ~[middleware::Middleware],
+	pub groups: ~[RouteGroup],
+}
+
+/// Returns an empty group which may be mounted using Config.groups, e.g.
+/// RouteGroup {routes: ~[(~"GET", ~"/users/{id}", ~"user")], views: ~[(~"user", user_view)], ..route_group(~"api", ~"/api/v1")}
+pub fn route_group(name: &str, prefix: &str) -> RouteGroup
+{
+	RouteGroup {name: name.to_unique(), prefix: prefix.to_unique(), routes: ~[], views: ~[], static_root: option::None, middleware: ~[], groups: ~[]}
+}
+
+/// Returns a copy of group with a new name mounted at prefix (so that a group can be mounted more than once).
+pub fn mount(group: &RouteGroup, name: &str, prefix: &str) -> RouteGroup
+{
+	RouteGroup {name: name.to_unique(), prefix: prefix.to_unique(), ..copy *group}
+}
+
+/// Returns the routes, views, static mounts, and middleware for groups with their names and templates qualified.
+pub fn flatten_groups(groups: &[RouteGroup]) -> (~[(~str, ~str, ~str)], ~[(~str, ResponseHandler)], ~[(~str, Path)], ~[(~str, ~[middleware::Middleware])])
+{
+	let mut routes = ~[];
+	let mut views = ~[];
+	let mut mounts = ~[];
+	let mut layers = ~[];
+	for vec::each(groups)
+	|group|
+	{
+		add_group(group, ~"", ~"", &mut routes, &mut views, &mut mounts, &mut layers);
+	}
+	(routes, views, mounts, layers)
+}
+
+/// Returns the root directory and path for the file associated with url. This will use resources_root
+/// unless url is beneath the prefix of a group with a static_root.
+pub fn static_path(config: &connection::ConnConfig, url: &str) -> (Path, Path)
+{
+	for vec::each(config.static_mounts)
+	|mount|
+	{
+		match *mount
+		{
+			(ref prefix, ref root) =>
+			{
+				if str::eq_slice(url, *prefix) || str::starts_with(url, *prefix + ~"/")
+				{
+					return (copy *root, utils::url_to_path(root, url.slice(prefix.len(), url.len())));
+				}
+			}
+		}
+	}
+	(copy config.resources_root, utils::url_to_path(&config.resources_root, url))
+}
+
+priv fn add_group(group: &RouteGroup, outer_name: &str, outer_prefix: &str, routes: &mut ~[(~str, ~str, ~str)], views: &mut ~[(~str, ResponseHandler)], mounts: &mut ~[(~str, Path)], layers: &mut ~[(~str, ~[middleware::Middleware])])
+{
+	let name = qualify(outer_name, group.name);
+	let prefix = join_path(outer_prefix, group.prefix);
+	
+	for vec::each(group.routes)
+	|route|
+	{
+		match *route
+		{
+			(ref method, ref template, ref route_name) =>
+			{
+				vec::push(routes, (copy *method, join_path(prefix, *template), qualify(name, *route_name)));
+			}
+		}
+	}
+	
+	for vec::each(group.views)
+	|view|
+	{
+		match *view
+		{
+			(ref route_name, ref handler) =>
+			{
+				vec::push(views, (qualify(name, *route_name), copy *handler));
+			}
+		}
+	}
+	
+	match group.static_root
+	{
+		option::Some(ref root) => vec::push(mounts, (copy prefix, copy *root)),
+		option::None => {}
+	}
+	
+	if vec::is_not_empty(group.middleware)
+	{
+		vec::push(layers, (copy prefix, copy group.middleware));
+	}
+	
+	for vec::each(group.groups)
+	|nested|
+	{
+		add_group(nested, name, prefix, routes, views, mounts, layers);
+	}
+}
+
+priv fn qualify(outer: &str, name: &str) -> ~str
+{
+	if str::is_empty(outer) {name.to_unique()} else if str::is_empty(name) {outer.to_unique()} else {fmt!("%s.%s", outer, name)}
+}
+
+// Joins a prefix like "/api/v1/" with a template like "/users/{id}", "{id}", or "{?page}". Expressions
+// whose operator adds its own separator (e.g. "{/path}" or "{?page}") and type suffixes are appended
+// directly: everything else is a new path segment.
+priv fn join_path(prefix: &str, template: &str) -> ~str
+{
+	let prefix = str::trim_right_chars(prefix, ~['/']);
+	if str::is_empty(template) || str::eq_slice(template, "/")
+	{
+		if str::is_empty(prefix) {~"/"} else {prefix}
+	}
+	else if str::starts_with(template, "/") || str::starts_with(template, "<") || starts_with_separator_expression(template)
+	{
+		prefix + template
+	}
+	else
+	{
+		prefix + "/" + template
+	}
+}
+
+priv fn starts_with_separator_expression(template: &str) -> bool
+{
+	str::len(template) > 1u && template[0] == '{' as u8 && str::contains_char("/?&#;.", template[1] as char)
+}
+
+#[test]
+fn flattened_groups()
+{
+	let users = RouteGroup {
+		routes: ~[(~"GET", ~"/", ~"list"), (~"GET", ~"{id:int}<application/json>", ~"user")],
+		views: ~[(~"list", missing_view), (~"user", missing_view)],
+		..route_group(~"users", ~"/users/")};
+	let api = RouteGroup {
+		routes: ~[(~"GET", ~"/status", ~"status")],
+		views: ~[(~"status", missing_view)],
+		static_root: option::Some(path::from_str(~"server/html/api-docs")),
+		groups: ~[users],
+		..route_group(~"api", ~"/api/v1")};
+	
+	let v2 = mount(&api, ~"v2", ~"/api/v2");
+	let (routes, views, mounts, _) = flatten_groups(~[api, v2]);
+	assert routes == ~[
+		(~"GET", ~"/api/v1/status", ~"api.status"),
+		(~"GET", ~"/api/v1/users", ~"api.users.list"),
+		(~"GET", ~"/api/v1/users/{id:int}<application/json>", ~"api.users.user"),
+		(~"GET", ~"/api/v2/status", ~"v2.status"),
+		(~"GET", ~"/api/v2/users", ~"v2.users.list"),
+		(~"GET", ~"/api/v2/users/{id:int}<application/json>", ~"v2.users.user")];
+	assert utils::check_vectors(vec::map(views, |v| {v.first()}), ~[~"api.status", ~"api.users.list", ~"api.users.user", ~"v2.status", ~"v2.users.list", ~"v2.users.user"]);
+	assert vec::map(mounts, |m| {m.first()}) == ~[~"/api/v1", ~"/api/v2"];
+}
+
+#[test]
+fn joined_paths()
+{
+	assert join_path(~"/users/", ~"{id}") == ~"/users/{id}";
+	assert join_path(~"/users/", ~"{+rest}") == ~"/users/{+rest}";
+	assert join_path(~"/users/", ~"{?page}") == ~"/users{?page}";
+	assert join_path(~"/users/", ~"{/id}") == ~"/users{/id}";
+	assert join_path(~"/users/", ~"{.format}") == ~"/users{.format}";
+	assert join_path(~"/users", ~"<application/json>") == ~"/users<application/json>";
+	assert join_path(~"/users", ~"/") == ~"/users";
+	assert join_path(~"", ~"") == ~"/";
+}
@@ -0,0 +1,296 @@
+//! Tree used to find the routes whose URI templates match a request path.
+//
+// Each node of the tree corresponds to a path segment. Children are keyed by literal text
+// (using a hashmap so lookups stay fast with lots of routes), by pattern (e.g. "{id:int}" or
+// "{name}.{ext}"), or by plain variable (variable names don't matter so "/users/{id}" and
+// "/users/{name}" share a node). Routes are recorded at the node for their last path segment
+// or, for templates ending with a trailer, in the trailers list of the node before it.
+
+/// Routes compiled into a tree.
+pub struct Router
+{
+	priv root: @Node,
+	pub conflicts: ~[~str],		// descriptions of routes which can never be selected
+}
+
+priv struct Node
+{
+	literals: HashMap<@~str, @Node>,
+	mut patterns: ~[(~str, uri_template::Component, @Node)],	// key is the pattern with variable names removed
+	mut variable: Option<@Node>,
+	mut trailers: ~[uint],		// indexes into the route list
+	mut routes: ~[uint],
+}
+
+/// Returns a router for routes. Routes which have the same method, mime type, and
+/// template (ignoring variable names) are reported in conflicts. Because query variables
+/// are optional this includes routes which differ only in their queries when the earlier
+/// route's query variables are unconstrained (e.g. "/search{?q}" and "/search{?page}").
+pub fn Router(routes: &[Route]) -> Router
+{
+	let root = new_node();
+	let mut conflicts = ~[];
+	for vec::eachi(routes)
+	|index, route|
+	{
+		let (node, is_trailer) = find_node(root, route.template);
+		let existing = if is_trailer {copy node.trailers} else {copy node.routes};
+		for vec::each(existing)
+		|i|
+		{
+			let other = &routes[*i];
+			if other.method == route.method && other.mime_type == route.mime_type && (query_key(other.template) == query_key(route.template) || matches_any_query(other.template))
+			{
+				vec::push(&mut conflicts, fmt!("Route %s conflicts with %s.", route.route, other.route));
+			}
+		}
+
+		if is_trailer
+		{
+			node.trailers += ~[index];
+		}
+		else
+		{
+			node.routes += ~[index];
+		}
+	}
+	Router {root: root, conflicts: conflicts}
+}
+
+pub impl Router
+{
+	/// Returns the indexes of the routes whose paths match path, most specific first. Literal
+	/// segments are preferred to patterns, patterns to plain variables, and variables to trailers.
+	/// Segments to the left take precedence over segments to the right. Routes which are equally
+	/// specific are returned in the order they were registered.
+	fn candidates(path: &str) -> ~[uint]
+	{
+		let parts = str::split_char_nonempty(path, '/');
+		let mut result = ~[];
+		collect(self.root, parts, 0u, &mut result);
+		result
+	}
+}
+
+priv fn new_node() -> @Node
+{
+	@Node {literals: std::map::HashMap(), patterns: ~[], variable: option::None, trailers: ~[], routes: ~[]}
+}
+
+// Returns the node for template (adding nodes as needed) and whether the route is a trailer route.
+priv fn find_node(root: @Node, template: &[uri_template::Component]) -> (@Node, bool)
+{
+	let mut node = root;
+	let count = uri_template::path_count(template);
+	let mut i = 0u;
+	while i < count
+	{
+		node = match template[i]
+			{
+				uri_template::Literal(ref s) =>
+				{
+					match node.literals.find(@copy *s)
+					{
+						option::Some(child) => child,
+						option::None => {let child = new_node(); node.literals.insert(@copy *s, child); child}
+					}
+				}
+				uri_template::Variable(_) =>
+				{
+					match node.variable
+					{
+						option::Some(child) => child,
+						option::None => {let child = new_node(); node.variable = option::Some(child); child}
+					}
+				}
+				uri_template::Pattern(ref parts) =>
+				{
+					let key = pattern_key(*parts);
+					match vec::find(node.patterns, |p| {p.first() == key})
+					{
+						option::Some((_, _, child)) => child,
+						option::None => {let child = new_node(); node.patterns += ~[(key, copy template[i], child)]; child}
+					}
+				}
+				uri_template::Trailer(_) =>
+				{
+					return (node, true);
+				}
+				uri_template::Query(_) | uri_template::Fragment(_) =>
+				{
+					fail ~"path_count should have stopped at query and fragment components";
+				}
+			};
+		i += 1u;
+	}
+	(node, false)
+}
+
+priv fn collect(node: @Node, parts: &[~str], i: uint, result: &mut ~[uint])
+{
+	if i == vec::len(parts)
+	{
+		*result += copy node.routes;
+		return;
+	}
+
+	match node.literals.find(@copy parts[i])
+	{
+		option::Some(child) => collect(child, parts, i + 1u, result),
+		option::None => {}
+	}
+
+	for vec::each(node.patterns)
+	|entry|
+	{
+		match *entry
+		{
+			(_, ref component, child) =>
+			{
+				if uri_template::match_segment(component, parts[i])
+				{
+					collect(child, parts, i + 1u, result);
+				}
+			}
+		}
+	}
+
+	match node.variable
+	{
+		option::Some(child) => collect(child, parts, i + 1u, result),
+		option::None => {}
+	}
+
+	*result += copy node.trailers;
+}
+
+// Patterns which differ only in their variable names match the same paths.
+priv fn pattern_key(parts: &[uri_template::Part]) -> ~str
+{
+	str::concat(do vec::map(parts)
+	|part|
+	{
+		match *part
+		{
+			uri_template::Text(ref s) => copy *s,
+			uri_template::Expr(ref e) => expression_key(e),
+		}
+	})
+}
+
+priv fn query_key(template: &[uri_template::Component]) -> ~str
+{
+	str::concat(do vec::map(vec::view(template, uri_template::path_count(template), vec::len(template)))
+	|component|
+	{
+		match *component
+		{
+			uri_template::Query(ref e) | uri_template::Fragment(ref e) => expression_key(e),
+			_ => ~"",
+		}
+	})
+}
+
+// Returns true if template's query expressions accept every query (variables which are absent or
+// have any value are accepted so only prefixes and constraints can cause a query to be rejected).
+priv fn matches_any_query(template: &[uri_template::Component]) -> bool
+{
+	do vec::all(template)
+	|component|
+	{
+		match *component
+		{
+			uri_template::Query(ref e) =>
+			{
+				do vec::all(e.vars)
+				|var|
+				{
+					match var.constraint
+					{
+						uri_template::AnyValue => var.prefix == 0u,
+						_ => false,
+					}
+				}
+			}
+			_ => true,
+		}
+	}
+}
+
+priv fn expression_key(e: &uri_template::Expression) -> ~str
+{
+	let vars = do vec::map(e.vars)
+	|var|
+	{
+		if e.op == '?' || e.op == '&' || e.op == ';'
+		{
+			fmt!("%s:%u:%?", var.name, var.prefix, var.constraint)		// names are part of the match for these
+		}
+		else
+		{
+			fmt!("%s:%u:%?", if var.explode {"*"} else {""}, var.prefix, var.constraint)
+		}
+	};
+	fmt!("{%c%s}", e.op, str::connect(vars, ~","))
+}
+
+#[cfg(test)]
+fn test_routes(routes: &[(~str, ~str, ~str)]) -> ~[Route]
+{
+	vec::map(routes, connection::to_route)
+}
+
+#[test]
+fn specific_routes()
+{
+	let routes = test_routes(~[
+		(~"GET", ~"/users/*rest", ~"trailer"),
+		(~"GET", ~"/users/{name}", ~"variable"),
+		(~"GET", ~"/users/{id:int}", ~"int"),
+		(~"GET", ~"/users/me", ~"me"),
+		(~"GET", ~"/", ~"home"),
+		(~"POST", ~"/users/{user}", ~"post")]);
+	let router = Router(routes);
+	assert vec::is_empty(router.conflicts);
+
+	let names = |path: &str| {vec::map(router.candidates(path), |i| {copy routes[*i].route})};
+	assert utils::check_vectors(names(~"/users/me"), ~[~"me", ~"variable", ~"post", ~"trailer"]);
+	assert utils::check_vectors(names(~"/users/42"), ~[~"int", ~"variable", ~"post", ~"trailer"]);
+	assert utils::check_vectors(names(~"/users/bob"), ~[~"variable", ~"post", ~"trailer"]);
+	assert utils::check_vectors(names(~"/users/bob/posts"), ~[~"trailer"]);
+	assert utils::check_vectors(names(~"/"), ~[~"home"]);
+	assert vec::is_empty(names(~"/users"));
+}
+
+#[test]
+fn conflicting_routes()
+{
+	let routes = test_routes(~[
+		(~"GET", ~"/users/{id}", ~"a"),
+		(~"GET", ~"/users/{name}", ~"b"),
+		(~"GET", ~"/files/{name}.{ext}", ~"c"),
+		(~"GET", ~"/files/{base}.{type}", ~"d"),
+		(~"GET", ~"/files/{name}.{ext}<application/json>", ~"e"),
+		(~"GET", ~"/search{?q}", ~"f"),
+		(~"GET", ~"/search{?page}", ~"g"),
+		(~"GET", ~"/items{?id:int}", ~"h"),
+		(~"GET", ~"/items{?name}", ~"i"),
+		(~"GET", ~"/items{?page}", ~"j"),
+		(~"GET", ~"/pages/{name}", ~"k"),
+		(~"GET", ~"/pages/{name}{?version}", ~"l")]);
+	let router = Router(routes);
+	assert utils::check_vectors(router.conflicts, ~[~"Route b conflicts with a.", ~"Route d conflicts with c.", ~"Route g conflicts with f.",
+		~"Route j conflicts with i.", ~"Route l conflicts with k."]);
+}
+
+#[test]
+fn many_routes()
+{
+	let mut input = ~[];
+	for uint::range(0u, 5000u) |i| {vec::push(&mut input, (~"GET", fmt!("/items%u/{id}", i), fmt!("item%u", i)))};
+	let routes = test_routes(input);
+	let router = Router(routes);
+
+	assert vec::is_empty(router.conflicts);
+	assert router.candidates(~"/items4321/7") == ~[4321u];
+}
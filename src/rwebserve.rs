@@ -2,7 +2,23 @@
 use std::map::*;
 use Path = path::Path;
 
+pub use access_log::*;
+pub use admin::*;
+pub use cache_policy::*;
 pub use configuration::*;
+pub use cookies::*;
+pub use error_pages::*;
+pub use hal::*;
+pub use headers::*;
+pub use html::*;
 pub use imap::*;
+pub use json::*;
+pub use metrics::*;
+pub use middleware::*;
+pub use route_group::*;
 pub use server::*;
 pub use sse::*;
+pub use status::*;
+pub use template_engine::*;
+pub use tracing::*;
+pub use urls::*;
@@ -4,9 +4,21 @@ use connection::{handle_connection};
 
 /// Startup the server.
 /// 
-/// Currently this will run until a client does a GET on '/shutdown' in which case exit is called.
+/// This runs until the process exits (e.g. from the on_drained function passed to admin::start_admin).
+/// Fails if any of the templates under resources_root cannot be compiled.
 pub fn start(config: &Config)
 {
+	let store = match config.template_store {option::Some(store) => store, option::None => template_cache::start_template_store()};
+	let config = Config {template_store: option::Some(store), ..copy *config};
+	let config = &config;
+	let errors = template_cache::precompile_templates(config);
+	if vec::is_not_empty(errors)
+	{
+		for vec::each(errors) |err| {error!("Bad template %s", *err)};
+		fail;
+	}
+	admin::register_routes(config);
+	
 	let port = comm::Port::<uint>();
 	let chan = comm::Chan::<uint>(&port);
 	let mut count = vec::len(config.hosts);
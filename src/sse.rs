@@ -34,12 +34,10 @@ pub enum ControlEvent
 	CloseEvent,
 }
 
-// This is invoked when the client sends a GET on behalf of an event source.
-pub fn process_sse(config: &connection::ConnConfig, request: &Request) -> (Response, Body)
+// This is the handler used when the client sends a GET on behalf of an event source.
+pub fn sse_view(config: &connection::ConnConfig, request: &Request, response: &Response) -> Response
 {
-	let mut code = ~"200";
-	let mut mesg = ~"OK";
-	let mut mime = ~"text/event-stream; charset=utf-8";
+	let mut code = status::OK;
 	
 	match config.sse_tasks.find(@copy request.path)
 	{
@@ -51,17 +49,15 @@ pub fn process_sse(config: &connection::ConnConfig, request: &Request) -> (Respo
 		{
 			if !OpenSse(config, request, config.sse_push)
 			{
-				code = ~"404";
-				mesg = ~"Not Found";
-				mime = ~"text/event-stream";
+				code = status::NotFound;
+				response.headers.set_content_type(~"text/event-stream");
 			}
 		}
 	}
 	
-	let response = request::make_initial_response(config, code, mesg, mime, request);
-	response.headers.insert(@~"Transfer-Encoding", @~"chunked");
-	response.headers.insert(@~"Cache-Control", @~"no-cache");
-	(response, StringBody(@~"\n\n"))
+	response.headers.set(~"Transfer-Encoding", ~"chunked");
+	response.headers.set_cache_control(~[~"no-cache"]);
+	Response {status: code, body: StringBody(@~"\n\n"), ..*response}
 }
 
 // TODO: Chrome, at least, doesn't seem to close EventSources so we need to time these out.
@@ -74,6 +70,9 @@ pub fn OpenSse(config: &connection::ConnConfig, request: &Request, push_data: Pu
 			info!("opening sse for %s", request.path);
 			let sse = (*opener)(config, request, push_data);
 			config.sse_tasks.insert(@copy request.path, sse);
+			connection::stream_opened(config, request.path, sse);
+			metrics::record_event(config, metrics::SseStreamsMesg(1));
+			admin::record_stream(config, request.path);
 			true
 		}
 		option::None =>
@@ -87,6 +86,7 @@ pub fn OpenSse(config: &connection::ConnConfig, request: &Request, push_data: Pu
 pub fn close_sses(config: &connection::ConnConfig)
 {
 	info!("closing all sse");
+	metrics::record_event(config, metrics::SseStreamsMesg(-(config.sse_tasks.size() as int)));
 	for config.sse_tasks.each_value
 	|control_ch|
 	{
@@ -96,14 +96,14 @@ pub fn close_sses(config: &connection::ConnConfig)
 
 pub fn make_response(config: &connection::ConnConfig) -> Response
 {
-	let headers = utils::to_boxed_str_hash(~[
+	let headers = headers::headers_from(~[
 		(~"Cache-Control", ~"no-cache"),
 		(~"Content-Type", ~"text/event-stream; charset=utf-8"),
-		(~"Date", std::time::now_utc().rfc822()),
+		(~"Date", config.date_cache.now()),
 		(~"Server", copy config.server_info),
 		(~"Transfer-Encoding", ~"chunked"),
 	]);
 	
-	Response {status: ~"200 OK", headers: headers, body: StringBody(@~""), template: ~"", context: std::map::HashMap()}
+	Response {status: status::OK, headers: headers, body: StringBody(@~""), template: ~"", context: std::map::HashMap(), layout: ~""}
 }
 
@@ -0,0 +1,258 @@
+//! HTTP status codes, see section 6 of RFC 7231.
+
+/// The status codes defined by RFC 7231 along with the commonly used codes from
+/// RFC 7232 (conditional requests), RFC 7233 (range requests), RFC 7235 (authentication),
+/// RFC 7538 (permanent redirect), and RFC 6585 (too many requests).
+pub enum Status
+{
+	Continue = 100,
+	SwitchingProtocols = 101,
+
+	OK = 200,
+	Created = 201,
+	Accepted = 202,
+	NonAuthoritativeInformation = 203,
+	NoContent = 204,
+	ResetContent = 205,
+	PartialContent = 206,
+
+	MultipleChoices = 300,
+	MovedPermanently = 301,
+	Found = 302,
+	SeeOther = 303,
+	NotModified = 304,
+	UseProxy = 305,
+	TemporaryRedirect = 307,
+	PermanentRedirect = 308,
+
+	BadRequest = 400,
+	Unauthorized = 401,
+	PaymentRequired = 402,
+	Forbidden = 403,
+	NotFound = 404,
+	MethodNotAllowed = 405,
+	NotAcceptable = 406,
+	ProxyAuthenticationRequired = 407,
+	RequestTimeout = 408,
+	Conflict = 409,
+	Gone = 410,
+	LengthRequired = 411,
+	PreconditionFailed = 412,
+	PayloadTooLarge = 413,
+	URITooLong = 414,
+	UnsupportedMediaType = 415,
+	RangeNotSatisfiable = 416,
+	ExpectationFailed = 417,
+	UpgradeRequired = 426,
+	TooManyRequests = 429,
+
+	InternalServerError = 500,
+	NotImplemented = 501,
+	BadGateway = 502,
+	ServiceUnavailable = 503,
+	GatewayTimeout = 504,
+	HTTPVersionNotSupported = 505,
+}
+
+pub impl Status : cmp::Eq
+{
+	pure fn eq(rhs: &Status) -> bool
+	{
+		(self as uint) == (*rhs as uint)
+	}
+
+	pure fn ne(rhs: &Status) -> bool
+	{
+		(self as uint) != (*rhs as uint)
+	}
+}
+
+/// Returns the text used in the status line, e.g. "404 Not Found".
+pub impl Status : ToStr
+{
+	pure fn to_str() -> ~str
+	{
+		fmt!("%u %s", self.code(), self.reason())
+	}
+}
+
+pub impl Status
+{
+	pure fn code() -> uint
+	{
+		self as uint
+	}
+
+	/// Returns the reason phrase recommended by the RFC.
+	pure fn reason() -> ~str
+	{
+		match self
+		{
+			Continue => ~"Continue",
+			SwitchingProtocols => ~"Switching Protocols",
+
+			OK => ~"OK",
+			Created => ~"Created",
+			Accepted => ~"Accepted",
+			NonAuthoritativeInformation => ~"Non-Authoritative Information",
+			NoContent => ~"No Content",
+			ResetContent => ~"Reset Content",
+			PartialContent => ~"Partial Content",
+
+			MultipleChoices => ~"Multiple Choices",
+			MovedPermanently => ~"Moved Permanently",
+			Found => ~"Found",
+			SeeOther => ~"See Other",
+			NotModified => ~"Not Modified",
+			UseProxy => ~"Use Proxy",
+			TemporaryRedirect => ~"Temporary Redirect",
+			PermanentRedirect => ~"Permanent Redirect",
+
+			BadRequest => ~"Bad Request",
+			Unauthorized => ~"Unauthorized",
+			PaymentRequired => ~"Payment Required",
+			Forbidden => ~"Forbidden",
+			NotFound => ~"Not Found",
+			MethodNotAllowed => ~"Method Not Allowed",
+			NotAcceptable => ~"Not Acceptable",
+			ProxyAuthenticationRequired => ~"Proxy Authentication Required",
+			RequestTimeout => ~"Request Timeout",
+			Conflict => ~"Conflict",
+			Gone => ~"Gone",
+			LengthRequired => ~"Length Required",
+			PreconditionFailed => ~"Precondition Failed",
+			PayloadTooLarge => ~"Payload Too Large",
+			URITooLong => ~"URI Too Long",
+			UnsupportedMediaType => ~"Unsupported Media Type",
+			RangeNotSatisfiable => ~"Range Not Satisfiable",
+			ExpectationFailed => ~"Expectation Failed",
+			UpgradeRequired => ~"Upgrade Required",
+			TooManyRequests => ~"Too Many Requests",
+
+			InternalServerError => ~"Internal Server Error",
+			NotImplemented => ~"Not Implemented",
+			BadGateway => ~"Bad Gateway",
+			ServiceUnavailable => ~"Service Unavailable",
+			GatewayTimeout => ~"Gateway Timeout",
+			HTTPVersionNotSupported => ~"HTTP Version Not Supported",
+		}
+	}
+
+	pure fn is_informational() -> bool
+	{
+		self.code() >= 100u && self.code() < 200u
+	}
+
+	pure fn is_success() -> bool
+	{
+		self.code() >= 200u && self.code() < 300u
+	}
+
+	pure fn is_redirection() -> bool
+	{
+		self.code() >= 300u && self.code() < 400u
+	}
+
+	pure fn is_error() -> bool
+	{
+		self.code() >= 400u
+	}
+
+	/// Returns true if responses with this status must not include a body (see section 3.3 of RFC 7230).
+	pure fn forbids_body() -> bool
+	{
+		self.is_informational() || self == NoContent || self == NotModified
+	}
+}
+
+/// Returns all of the supported status codes.
+pub pure fn all_statuses() -> ~[Status]
+{
+	~[
+		Continue, SwitchingProtocols,
+		OK, Created, Accepted, NonAuthoritativeInformation, NoContent, ResetContent, PartialContent,
+		MultipleChoices, MovedPermanently, Found, SeeOther, NotModified, UseProxy, TemporaryRedirect, PermanentRedirect,
+		BadRequest, Unauthorized, PaymentRequired, Forbidden, NotFound, MethodNotAllowed, NotAcceptable,
+		ProxyAuthenticationRequired, RequestTimeout, Conflict, Gone, LengthRequired, PreconditionFailed,
+		PayloadTooLarge, URITooLong, UnsupportedMediaType, RangeNotSatisfiable, ExpectationFailed,
+		UpgradeRequired, TooManyRequests,
+		InternalServerError, NotImplemented, BadGateway, ServiceUnavailable, GatewayTimeout, HTTPVersionNotSupported,
+	]
+}
+
+/// Returns the status with the specified code or None if the code is not one we know about.
+pub fn from_code(code: uint) -> Option<Status>
+{
+	vec::find(all_statuses(), |s| {s.code() == code})
+}
+
+/// Checks that a response follows the rules for its status code. Returns a list of
+/// problems which will be empty if the response looks OK.
+///
+/// Headers should be the response headers and body_len the number of bytes in the body.
+pub fn validate_response(method: &str, status: Status, headers: headers::Headers, body_len: uint) -> ~[~str]
+{
+	let mut problems = ~[];
+
+	// 201 and the redirects need to say where the resource is (sections 6.3.2 and 6.4).
+	let needs_location = ~[Created, MovedPermanently, Found, SeeOther, TemporaryRedirect, PermanentRedirect];
+	if vec::contains(needs_location, &status) && !headers.contains_key(~"Location")
+	{
+		vec::push(&mut problems, fmt!("%s responses should have a Location header", status.to_str()));
+	}
+
+	if status.forbids_body() && body_len > 0u
+	{
+		vec::push(&mut problems, fmt!("%s responses cannot have a body (body has %? bytes)", status.to_str(), body_len));
+	}
+
+	if status == MethodNotAllowed && !headers.contains_key(~"Allow")
+	{
+		vec::push(&mut problems, ~"405 responses must have an Allow header");
+	}
+
+	if status == Unauthorized && !headers.contains_key(~"WWW-Authenticate")
+	{
+		vec::push(&mut problems, ~"401 responses must have a WWW-Authenticate header");
+	}
+
+	if status == PartialContent && !headers.contains_key(~"Content-Range")
+	{
+		vec::push(&mut problems, ~"206 responses must have a Content-Range header");
+	}
+
+	// See sections 4.3.3 and 4.3.4.
+	if str::eq_slice(method, "POST") && status.is_success() && !vec::contains(~[OK, Created, Accepted, NoContent], &status)
+	{
+		vec::push(&mut problems, fmt!("POST should return 200, 201, 202, or 204 on success not %s", status.to_str()));
+	}
+	else if str::eq_slice(method, "PUT") && status.is_success() && !vec::contains(~[OK, Created, NoContent], &status)
+	{
+		vec::push(&mut problems, fmt!("PUT should return 200, 201, or 204 on success not %s", status.to_str()));
+	}
+
+	problems
+}
+
+#[test]
+fn status_text()
+{
+	assert utils::check_strs(OK.to_str(), ~"200 OK");
+	assert utils::check_strs(HTTPVersionNotSupported.to_str(), ~"505 HTTP Version Not Supported");
+	assert NotFound.code() == 404u;
+	assert from_code(418u).is_none();
+	assert from_code(304u) == option::Some(NotModified);
+}
+
+#[test]
+fn status_rules()
+{
+	let headers = headers::Headers();
+	assert utils::check_vectors(validate_response(~"GET", OK, headers, 10u), ~[]);
+	assert utils::check_vectors(validate_response(~"POST", Created, headers, 0u), ~[~"201 Created responses should have a Location header"]);
+	assert utils::check_vectors(validate_response(~"GET", NoContent, headers, 3u), ~[~"204 No Content responses cannot have a body (body has 3 bytes)"]);
+	assert utils::check_vectors(validate_response(~"PUT", Accepted, headers, 0u), ~[~"PUT should return 200, 201, or 204 on success not 202 Accepted"]);
+
+	headers.set(~"Location", ~"http://localhost/items/10");
+	assert utils::check_vectors(validate_response(~"POST", Created, headers, 0u), ~[]);
+}
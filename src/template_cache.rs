@@ -0,0 +1,542 @@
+//! Loading, compiling, and caching of templates.
+//
+// Compiled templates are not sendable so each task servicing requests has its own cache of them.
+// The loaded text (with partials expanded) is sendable though so it is also kept in a template
+// store task shared by the whole process: server::start creates the store and fills it in when
+// it precompiles the templates so new connections only need to compile the text.
+
+/// A template along with the information needed to decide whether it is still current.
+///
+/// * text: the raw template with partials inlined (used as-is when there is no context to expand it with).
+/// * template: the compiled template.
+/// * files: the template file followed by the files for any partials it uses.
+/// * modified: the newest modification time of files (None if the time is not known).
+pub struct CachedTemplate
+{
+	pub text: @~str,
+	pub template: @template_engine::Template,
+	pub files: ~[Path],
+	pub modified: Option<i64>,
+}
+
+/// Maps template paths (rooted at resources_root) to compiled templates.
+pub type TemplateCache = HashMap<@~str, @CachedTemplate>;
+
+/// Sendable version of a CachedTemplate (without the compiled template).
+pub struct SharedTemplate
+{
+	pub text: ~str,
+	pub files: ~[Path],
+	pub modified: Option<i64>,
+}
+
+/// Messages handled by the template store task.
+pub enum TemplateMesg
+{
+	FindTemplateMesg(~str, comm::Chan<Option<SharedTemplate>>),	// path, reply channel
+	StoreTemplateMesg(~str, SharedTemplate),						// path, template
+	ExitTemplatesMesg,
+}
+
+/// Used to communicate with the template store task (see Config.template_store).
+pub type TemplateStore = comm::Chan<TemplateMesg>;
+
+/// Starts up a new template store task.
+pub fn start_template_store() -> TemplateStore
+{
+	do task::spawn_listener
+	|port: comm::Port<TemplateMesg>|
+	{
+		let table: HashMap<@~str, SharedTemplate> = std::map::HashMap();
+		loop
+		{
+			match comm::recv(port)
+			{
+				FindTemplateMesg(path, reply) =>
+				{
+					comm::send(reply, table.find(@path));
+				}
+				StoreTemplateMesg(path, template) =>
+				{
+					table.insert(@path, template);
+				}
+				ExitTemplatesMesg =>
+				{
+					break;
+				}
+			}
+		}
+	}
+}
+
+/// Stops the template store task.
+pub fn stop_template_store(store: TemplateStore)
+{
+	comm::send(store, ExitTemplatesMesg);
+}
+
+/// Returns the compiled template at path, loading it and compiling it with the engine registered
+/// for engine (an extension in config.template_engines) if it is not already cached.
+///
+/// In debug mode the modification time of the file is checked on every call so that edits
+/// are picked up without restarting the server. Otherwise templates are kept for the lifetime
+/// of the process: each task compiles a template once and (if config.template_store is set)
+/// the text is loaded once for the whole process.
+pub fn find_template(config: &connection::ConnConfig, path: &Path, engine: &str) -> result::Result<@CachedTemplate, ~str>
+{
+	let key = @path.to_str();
+	let debug = config.settings.contains_key(@~"debug") && config.settings.get(@~"debug") == @~"true";
+	match config.template_cache.find(key)
+	{
+		option::Some(cached) if !debug || newest_modified(config, cached.files) == cached.modified =>
+		{
+			return result::Ok(cached);
+		}
+		_ =>
+		{
+		}
+	}
+
+	let engine = match config.template_engines.find(@engine.to_unique())
+		{
+			option::Some(engine) => engine,
+			option::None => return result::Err(fmt!("no template engine for '%s'", engine)),
+		};
+	
+	match find_shared(config, *key)
+	{
+		option::Some(ref shared) if !debug || newest_modified(config, shared.files) == shared.modified =>
+		{
+			return do result::chain(engine.compile(shared.text))
+			|template|
+			{
+				let cached = @CachedTemplate {text: @copy shared.text, template: template, files: copy shared.files, modified: shared.modified};
+				config.template_cache.insert(key, cached);
+				result::Ok(cached)
+			};
+		}
+		_ =>
+		{
+		}
+	}
+	
+	let mut files = ~[copy *path];
+	match load_template(config, path)
+	{
+		result::Ok(text) =>
+		{
+			match expand_partials(config, *text, 0u, &mut files)
+			{
+				result::Ok(move text) =>
+				{
+					do result::chain(engine.compile(text))
+					|template|
+					{
+						let modified = newest_modified(config, files);
+						let cached = @CachedTemplate {text: @copy text, template: template, files: copy files, modified: modified};
+						config.template_cache.insert(key, cached);
+						store_shared(config, *key, SharedTemplate {text: copy text, files: copy files, modified: modified});
+						result::Ok(cached)
+					}
+				}
+				result::Err(move mesg) =>
+				{
+					result::Err(mesg)
+				}
+			}
+		}
+		result::Err(move mesg) =>
+		{
+			result::Err(mesg)
+		}
+	}
+}
+
+/// Compiles all of the templates under resources_root and returns a list of errors.
+///
+/// This is called at startup so that broken templates are reported immediately instead of
+/// when a client happens to request them. If config.template_store is set the templates which
+/// compiled are added to it.
+pub fn precompile_templates(config: &Config) -> ~[~str]
+{
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(config, ech);
+	let root = config.resources_root.to_str();
+
+	let mut errors = ~[];
+	for os::walk_dir(&config.resources_root)
+	|path|
+	{
+		let path_str = path.to_str();
+		if !os::path_is_dir(path) && str::starts_with(path_str, root)
+		{
+			let url = path_str.slice(root.len(), path_str.len());
+			let url = if str::starts_with(url, "/") {url} else {~"/" + url};
+			match iconfig.is_template(&iconfig, url)
+			{
+				option::Some(ref engine) =>
+				{
+					match compile_file(config, &iconfig, path, *engine)
+					{
+						result::Ok(()) =>
+						{
+						}
+						result::Err(ref mesg) =>
+						{
+							vec::push(&mut errors, fmt!("%s: %s", url, *mesg));
+						}
+					}
+				}
+				option::None =>
+				{
+				}
+			}
+		}
+		true
+	};
+	errors
+}
+
+// Engines may fail if the template is bad so we compile with a new engine in a new task.
+priv fn compile_file(config: &Config, iconfig: &connection::ConnConfig, path: &Path, engine: &str) -> result::Result<(), ~str>
+{
+	let factory = match vec::find(config.template_engines, |e| {str::eq_slice(e.first(), engine)})
+		{
+			option::Some((_, move factory)) => factory,
+			option::None => return result::Err(fmt!("no template engine for '%s'", engine)),
+		};
+	
+	do result::chain(load_template(iconfig, path))
+	|text|
+	{
+		let mut files = ~[copy *path];
+		do result::chain(expand_partials(iconfig, *text, 0u, &mut files))
+		|text|
+		{
+			let port = comm::Port();
+			let chan = comm::Chan(&port);
+			let outcome = do task::try
+			|copy text, copy factory, copy chan|
+			{
+				match factory().compile(text)
+				{
+					result::Ok(_) => comm::send(chan, ~""),
+					result::Err(move mesg) => comm::send(chan, mesg),
+				}
+			};
+			if outcome.is_err()
+			{
+				result::Err(~"failed to compile")
+			}
+			else
+			{
+				let mesg = comm::recv(port);
+				if str::is_empty(mesg)
+				{
+					let modified = newest_modified(iconfig, files);
+					store_shared(iconfig, path.to_str(), SharedTemplate {text: copy text, files: copy files, modified: modified});
+					result::Ok(())
+				}
+				else
+				{
+					result::Err(mesg)
+				}
+			}
+		}
+	}
+}
+
+priv fn find_shared(config: &connection::ConnConfig, path: &str) -> Option<SharedTemplate>
+{
+	match config.template_store
+	{
+		option::Some(store) =>
+		{
+			let port = comm::Port();
+			comm::send(store, FindTemplateMesg(path.to_unique(), comm::Chan(&port)));
+			comm::recv(port)
+		}
+		option::None =>
+		{
+			option::None
+		}
+	}
+}
+
+priv fn store_shared(config: &connection::ConnConfig, path: &str, template: SharedTemplate)
+{
+	match config.template_store
+	{
+		option::Some(store) =>
+		{
+			comm::send(store, StoreTemplateMesg(path.to_unique(), template));
+		}
+		option::None =>
+		{
+		}
+	}
+}
+
+priv fn load_template(config: &connection::ConnConfig, path: &Path) -> result::Result<@~str, ~str>
+{
+	do result::chain(config.load_rsrc(path))
+	|template|
+	{
+		result::Ok(@str::from_bytes(template))
+	}
+}
+
+// Replaces {{> name}} with the contents of the file at resources_root/name. This is done before
+// compiling so partials are expanded using the context of the template which includes them.
+priv fn expand_partials(config: &connection::ConnConfig, text: &str, depth: uint, files: &mut ~[Path]) -> result::Result<~str, ~str>
+{
+	if depth > 8u
+	{
+		return result::Err(~"partials are nested too deeply");
+	}
+
+	let mut result = ~"";
+	let mut index = 0u;
+	loop
+	{
+		match str::find_str_from(text, "{{>", index)
+		{
+			option::Some(i) =>
+			{
+				match str::find_str_from(text, "}}", i + 3u)
+				{
+					option::Some(j) =>
+					{
+						let name = str::trim(text.slice(i + 3u, j));
+						let path = utils::url_to_path(&config.resources_root, name);
+						vec::push(files, copy path);
+
+						match load_template(config, &path)
+						{
+							result::Ok(partial) =>
+							{
+								match expand_partials(config, *partial, depth + 1u, files)
+								{
+									result::Ok(ref partial) =>
+									{
+										result += text.slice(index, i) + *partial;
+									}
+									result::Err(ref mesg) =>
+									{
+										return result::Err(fmt!("%s (in partial %s)", *mesg, name));
+									}
+								}
+							}
+							result::Err(ref mesg) =>
+							{
+								return result::Err(fmt!("%s (in partial %s)", *mesg, name));
+							}
+						}
+						index = j + 2u;
+					}
+					option::None =>
+					{
+						break;
+					}
+				}
+			}
+			option::None =>
+			{
+				break;
+			}
+		}
+	}
+	result += text.slice(index, text.len());
+	result::Ok(result)
+}
+
+// Returns the most recent modification time of the files or None if any of the times are unknown.
+priv fn newest_modified(config: &connection::ConnConfig, files: &[Path]) -> Option<i64>
+{
+	let mut newest = option::None;
+	for vec::each(files)
+	|path|
+	{
+		match config.rsrc_modified(path)
+		{
+			option::Some(t) =>
+			{
+				if newest.is_none() || t > newest.get()
+				{
+					newest = option::Some(t);
+				}
+			}
+			option::None =>
+			{
+				return option::None;
+			}
+		}
+	}
+	newest
+}
+
+// The loader sends the path of each file it loads to loads so that tests can count them.
+#[cfg(test)]
+fn make_config(settings: ~[(~str, ~str)], loads: comm::Chan<~str>) -> connection::ConnConfig
+{
+	let counting_loader: RsrcLoader = |path: &Path, copy loads|
+	{
+		comm::send(loads, path.to_str());
+		result::Ok(str::to_bytes(fmt!("{{name}} from %s", path.to_str())))
+	};
+
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		load_rsrc: counting_loader,
+		rsrc_modified: |_path| {option::Some(1000i64)},
+		settings: settings,
+		..initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	connection::config_to_conn(&config, ech)
+}
+
+#[test]
+fn bad_template()
+{
+	fn bad_loader(_path: &Path) ->  result::Result<~[u8], ~str>
+	{
+		result::Ok(str::to_bytes(~"unbalanced {{curly}} {{braces}"))
+	}
+
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		load_rsrc: bad_loader,
+		settings: ~[(~"debug", ~"true")],
+		..initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	match find_template(&iconfig, &path::from_str(~"blah.html"), ~".mustache")
+	{
+		result::Ok(_) =>
+		{
+			io::stderr().write_line(~"Expected error but found a template");
+			assert false;
+		}
+		result::Err(ref s) =>
+		{
+			assert str::contains(*s, "mismatched curly braces");
+		}
+	}
+}
+
+#[test]
+fn cached_templates()
+{
+	let loads_port = comm::Port();
+	let config = make_config(~[], comm::Chan(&loads_port));
+	let path = path::from_str(~"server/html/home.html");
+
+	let first = result::unwrap(find_template(&config, &path, ~".mustache"));
+	let second = result::unwrap(find_template(&config, &path, ~".mustache"));
+	let third = result::unwrap(find_template(&config, &path, ~".mustache"));
+	assert ptr::ref_eq(first, second);
+	assert ptr::ref_eq(first, third);
+	assert config.template_cache.size() == 1u;
+	assert first.modified == option::Some(1000i64);
+	
+	assert comm::recv(loads_port) == ~"server/html/home.html";
+	assert !loads_port.peek();		// the file was loaded once
+}
+
+#[test]
+fn stale_templates()
+{
+	let loads_port = comm::Port();
+	let config = make_config(~[(~"debug", ~"true")], comm::Chan(&loads_port));
+	let path = path::from_str(~"server/html/home.html");
+
+	let first = result::unwrap(find_template(&config, &path, ~".mustache"));
+	config.template_cache.insert(@path.to_str(), @CachedTemplate {text: first.text, template: first.template, files: copy first.files, modified: option::Some(10i64)});
+
+	// The file's modification time no longer matches so the template is reloaded.
+	let second = result::unwrap(find_template(&config, &path, ~".mustache"));
+	assert second.modified == option::Some(1000i64);
+	assert !ptr::ref_eq(first, second);
+	
+	assert comm::recv(loads_port) == ~"server/html/home.html";
+	assert comm::recv(loads_port) == ~"server/html/home.html";
+	assert !loads_port.peek();
+}
+
+#[test]
+fn partials()
+{
+	fn partial_loader(path: &Path) -> result::Result<~[u8], ~str>
+	{
+		if str::ends_with(path.to_str(), "page.html")
+		{
+			result::Ok(str::to_bytes(~"<h1>{{> partials/header.html }}</h1>"))
+		}
+		else
+		{
+			result::Ok(str::to_bytes(~"header {{title}}"))
+		}
+	}
+
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		load_rsrc: partial_loader,
+		..initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	let cached = result::unwrap(find_template(&iconfig, &path::from_str(~"server/html/page.html"), ~".mustache"));
+	assert utils::check_strs(*cached.text, ~"<h1>header {{title}}</h1>");
+	assert utils::check_strs(cached.files[1].to_str(), ~"server/html/partials/header.html");
+}
+
+#[test]
+fn precompile()
+{
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		..initialize_config()};
+	assert utils::check_vectors(precompile_templates(&config), ~[]);
+}
+
+#[test]
+fn shared_templates()
+{
+	fn failing_loader(_path: &Path) -> result::Result<~[u8], ~str>
+	{
+		result::Err(~"loaded twice")
+	}
+
+	let store = start_template_store();
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		template_store: option::Some(store),
+		..initialize_config()};
+	assert utils::check_vectors(precompile_templates(&config), ~[]);
+
+	// Connections (and workers) have their own compiled templates but the text comes from the store.
+	let config = Config {load_rsrc: failing_loader, ..config};
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	let cached = result::unwrap(find_template(&iconfig, &path::from_str(~"server/html/home.html"), ~".mustache"));
+	assert str::is_not_empty(*cached.text);
+	assert cached.files[0].to_str() == ~"server/html/home.html";
+	assert iconfig.template_cache.size() == 1u;
+
+	assert find_template(&iconfig, &path::from_str(~"server/html/missing.html"), ~".mustache").is_err();
+	stop_template_store(store);
+}
@@ -0,0 +1,215 @@
+//! Pluggable template engines.
+//
+// Engines are registered in Config.template_engines using file extensions. Contexts use
+// mustache::Data because it is a simple tree of strings, bools, vectors, and maps that any
+// engine can walk.
+
+/// A compiled template.
+pub trait Template
+{
+	fn render(context: HashMap<@~str, mustache::Data>) -> ~str;
+}
+
+/// Compiles template files. Engines (and their templates) are created for each connection
+/// so they do not need to be sendable.
+pub trait TemplateEngine
+{
+	fn compile(text: &str) -> result::Result<@Template, ~str>;
+}
+
+/// Creates a template engine. Unlike the engine this is sendable so it can be stored in Config.
+pub type EngineFactory = fn~ () -> @TemplateEngine;
+
+/// Returns a table mapping extensions to new engines.
+pub fn engines_table(engines: &[(~str, EngineFactory)]) -> HashMap<@~str, @TemplateEngine>
+{
+	let table = HashMap();
+	for vec::each(engines)
+	|entry|
+	{
+		match *entry
+		{
+			(ref ext, ref factory) =>
+			{
+				table.insert(@copy *ext, (*factory)());
+			}
+		}
+	}
+	table
+}
+
+// ---- mustache ----------------------------------------------------------
+pub struct MustacheEngine
+{
+	priv unused: (),
+}
+
+/// Returns an engine using rust-mustache. This is the default engine.
+pub fn mustache_engine() -> @TemplateEngine
+{
+	@MustacheEngine {unused: ()} as @TemplateEngine
+}
+
+pub impl MustacheEngine : TemplateEngine
+{
+	fn compile(text: &str) -> result::Result<@Template, ~str>
+	{
+		// {{ should be followed by }} (rust-mustache hangs if this is not the case).
+		if match_curly_braces(text)
+		{
+			result::Ok(@mustache::compile_str(text) as @Template)
+		}
+		else
+		{
+			result::Err(~"mismatched curly braces")
+		}
+	}
+}
+
+pub impl mustache::Template : Template
+{
+	fn render(context: HashMap<@~str, mustache::Data>) -> ~str
+	{
+		self.render_data(mustache::Map(context))
+	}
+}
+
+// ---- substitution ------------------------------------------------------
+pub struct SubstitutionEngine
+{
+	priv unused: (),
+}
+
+/// Returns a minimal engine which replaces ${name} with the string value of name
+/// from the context (or with nothing if name isn't a string or bool).
+pub fn substitution_engine() -> @TemplateEngine
+{
+	@SubstitutionEngine {unused: ()} as @TemplateEngine
+}
+
+priv struct SubstitutionTemplate
+{
+	parts: ~[either::Either<~str, ~str>],		// Left is literal text, Right is a context name
+}
+
+pub impl SubstitutionEngine : TemplateEngine
+{
+	fn compile(text: &str) -> result::Result<@Template, ~str>
+	{
+		let mut parts = ~[];
+		let mut index = 0u;
+		loop
+		{
+			match str::find_str_from(text, "${", index)
+			{
+				option::Some(i) =>
+				{
+					match str::find_char_from(text, '}', i + 2u)
+					{
+						option::Some(j) =>
+						{
+							vec::push(&mut parts, either::Left(text.slice(index, i)));
+							vec::push(&mut parts, either::Right(str::trim(text.slice(i + 2u, j))));
+							index = j + 1u;
+						}
+						option::None =>
+						{
+							return result::Err(fmt!("unterminated ${ at offset %?", i));
+						}
+					}
+				}
+				option::None =>
+				{
+					break;
+				}
+			}
+		}
+		vec::push(&mut parts, either::Left(text.slice(index, text.len())));
+		result::Ok(@SubstitutionTemplate {parts: parts} as @Template)
+	}
+}
+
+pub impl SubstitutionTemplate : Template
+{
+	fn render(context: HashMap<@~str, mustache::Data>) -> ~str
+	{
+		let mut result = ~"";
+		for vec::each(self.parts)
+		|part|
+		{
+			match *part
+			{
+				either::Left(ref text) =>
+				{
+					result += *text;
+				}
+				either::Right(ref name) =>
+				{
+					match context.find(@copy *name)
+					{
+						option::Some(mustache::Str(value)) => result += *value,
+						option::Some(mustache::Bool(value)) => result += bool::to_str(value),
+						_ => {}
+					}
+				}
+			}
+		}
+		result
+	}
+}
+
+priv fn match_curly_braces(text: &str) -> bool
+{
+	let mut index = 0u;
+
+	while index < str::len(text)
+	{
+		match str::find_str_from(text, "{{", index)
+		{
+			option::Some(i) =>
+			{
+				match str::find_str_from(text, "}}", i + 2u)
+				{
+					option::Some(j) =>
+					{
+						index = j + 2u;
+					}
+					option::None() =>
+					{
+						return false;
+					}
+				}
+			}
+			option::None =>
+			{
+				break;
+			}
+		}
+	}
+	return true;
+}
+
+#[test]
+fn mustache_templates()
+{
+	let context = HashMap();
+	context.insert(@~"name", mustache::Str(@~"Bob"));
+
+	let engine = mustache_engine();
+	let template = result::unwrap(engine.compile(~"hello {{name}}"));
+	assert utils::check_strs(template.render(context), ~"hello Bob");
+	assert engine.compile(~"unbalanced {{curly}} {{braces}").is_err();
+}
+
+#[test]
+fn substitution_templates()
+{
+	let context = HashMap();
+	context.insert(@~"name", mustache::Str(@~"Bob"));
+	context.insert(@~"admin", mustache::Bool(true));
+
+	let engine = substitution_engine();
+	let template = result::unwrap(engine.compile(~"hello ${name} (${admin}${missing})"));
+	assert utils::check_strs(template.render(context), ~"hello Bob (true)");
+	assert engine.compile(~"hello ${name").is_err();
+}
@@ -0,0 +1,127 @@
+//! Request ids and timing spans used to correlate the log lines and metrics for a request.
+//
+// Each request is given an id (the X-Request-ID header if a client or proxy sent one) which is
+// included in the log lines written while servicing it and echoed in the response. The time
+// spent in each stage is recorded in the request's trace: rwebserve records parse, routing,
+// handler, render (for templates), and write spans and views may add their own. Once the
+// response has been written the spans are logged and sent to the metrics task.
+
+/// The time spent in part of the processing of a request.
+pub struct Span
+{
+	pub name: ~str,
+	pub duration: u64,			// microseconds
+}
+
+/// The spans recorded for a request.
+pub struct Trace
+{
+	pub id: ~str,
+	priv mut spans: ~[Span],
+}
+
+/// Returns an empty trace for the request with the specified id.
+pub fn Trace(id: &str) -> @Trace
+{
+	@Trace {id: id.to_unique(), spans: ~[]}
+}
+
+pub impl Trace
+{
+	/// Records a span which took duration microseconds.
+	fn add(name: &str, duration: u64)
+	{
+		vec::push(&mut self.spans, Span {name: name.to_unique(), duration: duration});
+	}
+
+	/// Calls f and records the time it took as a span, e.g.
+	/// let rows = do request.trace.time(~"db") {query(db, sql)};
+	fn time<T>(name: &str, f: fn () -> T) -> T
+	{
+		let start_ns = std::time::precise_time_ns();
+		let result = f();
+		self.add(name, (std::time::precise_time_ns() - start_ns)/1000u64);
+		result
+	}
+
+	fn spans() -> ~[Span]
+	{
+		copy self.spans
+	}
+
+	/// Returns the spans formatted like "parse=20us routing=5us handler=310us".
+	fn summary() -> ~str
+	{
+		str::connect(vec::map(self.spans, |span| {fmt!("%s=%uus", span.name, span.duration as uint)}), ~" ")
+	}
+}
+
+/// Returns the id to use for a request: the X-Request-ID header if it was present and valid
+/// (up to 128 visible ASCII characters) or a new id. New ids are a random 64-bit prefix chosen
+/// for each connection followed by a count of the requests it has serviced so they
+/// are unique in practice but not guaranteed to be.
+pub fn request_id(config: &connection::ConnConfig, headers: headers::Headers) -> ~str
+{
+	match headers.find(~"x-request-id")
+	{
+		option::Some(ref id) if is_valid_id(*id) =>
+		{
+			copy *id
+		}
+		_ =>
+		{
+			config.request_count += 1u;
+			fmt!("%s-%u", config.id_prefix, config.request_count)
+		}
+	}
+}
+
+// ---- Functions used by the connection code -----------------------------------
+/// Returns a random prefix for the ids of requests on a new connection. This is 64 bits so that
+/// collisions are unlikely even across billions of connections.
+pub fn id_prefix() -> ~str
+{
+	let rng = rand::Rng();
+	fmt!("%08x%08x", rng.gen_u32() as uint, rng.gen_u32() as uint)
+}
+
+/// Logs trace's spans and sends them to config.metrics (if it is set).
+pub fn finish_trace(config: &connection::ConnConfig, trace: @Trace)
+{
+	info!("[%s] %s", trace.id, trace.summary());
+	metrics::record_event(config, metrics::SpansMesg(trace.spans()));
+}
+
+// ---- Internal Items ----------------------------------------------------------
+priv fn is_valid_id(id: &str) -> bool
+{
+	let len = str::len(id);
+	len > 0u && len <= 128u && str::all(id, |ch| {ch > ' ' && ch <= '~'})
+}
+
+#[test]
+fn request_ids()
+{
+	let config = Config {
+		hosts: ~[~"localhost"],
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		.. initialize_config()};
+
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+
+	assert request_id(&iconfig, headers::headers_from(~[(~"x-request-id", ~"abc-123")])) == ~"abc-123";
+	assert request_id(&iconfig, headers::headers_from(~[])) == iconfig.id_prefix + ~"-1";
+	assert request_id(&iconfig, headers::headers_from(~[(~"x-request-id", ~"has space")])) == iconfig.id_prefix + ~"-2";
+	assert request_id(&iconfig, headers::headers_from(~[(~"x-request-id", str::from_chars(vec::from_elem(129u, 'x')))])) == iconfig.id_prefix + ~"-3";
+
+	let trace = Trace(~"abc-123");
+	trace.add(~"parse", 20u64);
+	let x = do trace.time(~"handler") {1 + 2};
+	assert x == 3;
+	assert vec::map(trace.spans(), |s| {copy s.name}) == ~[~"parse", ~"handler"];
+	assert str::starts_with(trace.summary(), ~"parse=20us handler=");
+	assert str::len(id_prefix()) == 16u;
+}
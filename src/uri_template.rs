@@ -3,7 +3,47 @@ pub enum Component
 {
 	Literal(~str),		// match iff the component is str
 	Variable(~str),		// matches an arbitrary component, str will be the key name
-	Trailer(~str)		// matches zero or more components, str will be the key name
+	Trailer(~str),		// matches zero or more components, str will be the key name
+	Pattern(~[Part]),		// matches a component using literals and expressions, e.g. "{name}.{ext}" or "{id:int}"
+	Query(Expression),		// a {?x,y} or {&z} expression, these are matched against the query parameters
+	Fragment(Expression),	// a {#x} expression, this is only used when expanding templates
+}
+
+// Pieces of a Pattern component.
+pub enum Part
+{
+	Text(~str),
+	Expr(Expression),
+}
+
+// An RFC 6570 expression, e.g. {x}, {+path}, {.ext}, {;id}, {?page,size}, or {#frag}.
+// op is one of + # . / ; ? & or '\0' for simple string expansion.
+pub struct Expression
+{
+	pub op: char,
+	pub vars: ~[VarSpec],
+}
+
+// A variable within an expression. Prefix is the maximum length of the value (0 for no limit).
+pub struct VarSpec
+{
+	pub name: ~str,
+	pub explode: bool,
+	pub prefix: uint,
+	pub constraint: Constraint,
+}
+
+// Restricts the values a variable will match, e.g. {id:int} or {slug:[a-z0-9-]+}.
+pub enum Constraint
+{
+	AnyValue,
+	IntValue,		// optional minus sign followed by digits
+	UIntValue,		// digits
+	FloatValue,
+	AlphaValue,
+	AlnumValue,
+	HexValue,
+	RegexValue(regex::Regex),
 }
 
 // TODO: This is hopefully temporary: at some point rust should again be able to compare enums without assistence.
@@ -20,39 +60,61 @@ pub impl Component : cmp::Eq
 	}
 }
 
-// Template should correspond to the path component of an URI.
-// Note that the template need not have variable components.
+// Template should correspond to the path component of an URI optionally followed by
+// query and fragment expressions. Note that the template need not have variable components.
 // Templates look like:
 //    /blueprint/{site}/{building}		site and building match any (single) component
-//    /csv/*path						path matches zero or more components
+//    /csv/*path						path matches zero or more components (so does {/path*} and {+path})
+//    /files/{name}.{ext}				variables may be mixed with literals within a component
+//    /users/{id:int}					the value must be an integer (also uint, float, alpha, alnum, and hex)
+//    /archive/{year:[0-9]{4}}			the value must match the regular expression
+//    /search{?q,page}					query expressions are matched against the query parameters
+// See RFC 6570 for the full syntax. Bad templates are logged and treated as literals (validate_config
+// also reports them so that the server won't start).
 pub fn compile(template: &str) -> ~[Component]
 {
-	let parts = str::split_char_nonempty(template, '/');
-	
-	let mut result = do vec::map(parts)
-	|part|
+	match parse_template(template)
 	{
-		if str::starts_with(*part, "{") && str::ends_with(*part, "}")
+		result::Ok(move components) =>
 		{
-			Variable(str::slice(*part, 1u, str::len(*part)-1u))
+			components
 		}
-		else
+		result::Err(ref mesg) =>
 		{
-			Literal(part.to_unique())
+			error!("bad URI template '%s': %s", template, *mesg);
+			do vec::map(str::split_char_nonempty(template, '/')) |part| {Literal(copy *part)}
 		}
-	};
-	
-	if vec::is_not_empty(parts)
+	}
+}
+
+// Like compile except that errors are returned instead of logged.
+pub fn parse_template(template: &str) -> result::Result<~[Component], ~str>
+{
+	do result::chain(tokenize(template))
+	|parts|
 	{
-		let last = 	vec::last(parts);
-		if str::starts_with(last, "*")
+		// Split off the query and fragment expressions.
+		let tail_start = vec::position(parts, |p| {match *p {Expr(ref e) => e.op == '?' || e.op == '&' || e.op == '#', _ => false}});
+		let tail_start = tail_start.get_default(vec::len(parts));
+		let (path, tail) = (vec::slice(parts, 0u, tail_start), vec::slice(parts, tail_start, vec::len(parts)));
+		
+		do result::chain(path_components(path))
+		|components|
 		{
-			vec::pop(&mut result);
-			vec::push(&mut result, Trailer(str::slice(last, 1u, str::len(last))));
+			let mut components = components;
+			for vec::each(tail)
+			|part|
+			{
+				match *part
+				{
+					Expr(ref e) if e.op == '#' => vec::push(&mut components, Fragment(copy *e)),
+					Expr(ref e) if e.op == '?' || e.op == '&' => vec::push(&mut components, Query(copy *e)),
+					_ => return result::Err(~"query and fragment expressions must be at the end"),
+				}
+			}
+			result::Ok(components)
 		}
 	}
-	
-	return result;
 }
 
 // Path should be the path component of an URI.
@@ -62,10 +124,11 @@ pub fn compile(template: &str) -> ~[Component]
 pub fn match_template(path: &str, components: &[Component]) -> HashMap<@~str, @~str>
 {
 	let parts = str::split_char_nonempty(path, '/');
+	let count = path_count(components);
 	
 	let mut i = 0u;
 	let result = std::map::HashMap();
-	while i < vec::len(components)
+	while i < count
 	{
 		if i == vec::len(parts)
 		{
@@ -91,6 +154,19 @@ pub fn match_template(path: &str, components: &[Component]) -> HashMap<@~str, @~
 				result.insert(@s, @str::connect(path, ~"/"));
 				i = vec::len(parts) - 1u;
 			}
+			Pattern(ref pattern) =>
+			{
+				let mut values = ~[];
+				if !match_parts(*pattern, parts[i], 0u, &mut values)
+				{
+					return std::map::HashMap();	// match failed
+				}
+				for vec::each(values) |v| {result.insert(@v.first(), @v.second())};
+			}
+			Query(_) | Fragment(_) =>
+			{
+				fail ~"path_count should have stopped at query and fragment components";
+			}
 		}
 		i += 1u;
 	}
@@ -104,7 +180,679 @@ pub fn match_template(path: &str, components: &[Component]) -> HashMap<@~str, @~
 	return result;
 }
 
+// Returns the number of components which match path segments (i.e. the components before any
+// query or fragment expressions).
+pub fn path_count(components: &[Component]) -> uint
+{
+	match vec::position(components, |c| {match *c {Query(_) | Fragment(_) => true, _ => false}})
+	{
+		option::Some(i) => i,
+		option::None => vec::len(components),
+	}
+}
+
+// Returns true if a Literal, Variable, or Pattern component matches a single path segment.
+pub fn match_segment(component: &Component, segment: &str) -> bool
+{
+	match *component
+	{
+		Literal(ref s) => str::eq_slice(*s, segment),
+		Variable(_) => true,
+		Pattern(ref parts) => {let mut values = ~[]; match_parts(*parts, segment, 0u, &mut values)}
+		Trailer(_) | Query(_) | Fragment(_) => false,
+	}
+}
+
+// Checks the query expressions in components against params and adds the values to matches.
+// Query variables are optional but if they are present they must satisfy their constraints.
+pub fn match_query(components: &[Component], params: &IMap<@~str, @~str>, matches: HashMap<@~str, @~str>) -> bool
+{
+	for vec::each(components)
+	|component|
+	{
+		match *component
+		{
+			Query(ref e) =>
+			{
+				for vec::each(e.vars)
+				|var|
+				{
+					let values = params.get_all(@copy var.name);
+					if !vec::all(values, |v| {satisfies(var, **v)})
+					{
+						return false;
+					}
+					if vec::is_not_empty(values)
+					{
+						matches.insert(@copy var.name, @str::connect(vec::map(values, |v| {copy **v}), ~","));
+					}
+				}
+			}
+			_ =>
+			{
+			}
+		}
+	}
+	true
+}
+
+// The inverse of match_template: returns the URL for components using vars for the variables along
+// with the names of any required variables missing from vars (these are left in the URL as "{name}").
+// Variables in query, fragment, label, and path parameter expressions are optional. Names may appear
+// more than once in vars in which case they are treated as a list (e.g. {?id*} may expand to "?id=1&id=2").
+// Values are percent encoded except that slashes in Trailer values are retained.
+pub fn expand(components: &[Component], vars: &[(~str, ~str)]) -> (~str, ~[~str])
+{
+	let mut path = ~"";
+	let mut missing = ~[];
+	for vec::each(components)
+	|component|
+	{
+		match *component
+		{
+			Literal(ref s) =>
+			{
+				path += ~"/" + *s;
+			}
+			Variable(ref name) | Trailer(ref name) =>
+			{
+				path += ~"/";
+				match vec::find(vars, |v| {v.first() == *name})
+				{
+					option::Some((_, ref value)) =>
+					{
+						let is_trailer = match *component {Trailer(_) => true, _ => false};
+						path += percent_encode(*value, is_trailer);
+					}
+					option::None =>
+					{
+						path += ~"{" + *name + ~"}";
+						vec::push(&mut missing, copy *name);
+					}
+				}
+			}
+			Pattern(ref parts) =>
+			{
+				path += ~"/";
+				for vec::each(*parts)
+				|part|
+				{
+					match *part
+					{
+						Text(ref s) => path += *s,
+						Expr(ref e) => path += expand_expression(e, vars, &mut missing),
+					}
+				}
+			}
+			Query(ref e) | Fragment(ref e) =>
+			{
+				if str::is_empty(path)
+				{
+					path = ~"/";
+				}
+				path += expand_expression(e, vars, &mut missing);
+			}
+		}
+	}
+	
+	(if str::is_empty(path) {~"/"} else {path}, missing)
+}
+
+// Returns the names of all the variables in components.
+pub fn variable_names(components: &[Component]) -> ~[~str]
+{
+	let mut names = ~[];
+	for vec::each(components)
+	|component|
+	{
+		match *component
+		{
+			Literal(_) => {}
+			Variable(ref name) | Trailer(ref name) => vec::push(&mut names, copy *name),
+			Pattern(ref parts) =>
+			{
+				for vec::each(*parts)
+				|part|
+				{
+					match *part
+					{
+						Expr(ref e) => names += vec::map(e.vars, |v| {copy v.name}),
+						Text(_) => {}
+					}
+				}
+			}
+			Query(ref e) | Fragment(ref e) => names += vec::map(e.vars, |v| {copy v.name}),
+		}
+	}
+	names
+}
+
+// Encodes everything except the unreserved characters from section 2.3 of RFC 3986
+// (and slashes if keep_slashes is set).
+pub fn percent_encode(text: &str, keep_slashes: bool) -> ~str
+{
+	let mut result = ~"";
+	for str::each(text)
+	|b|
+	{
+		let ch = b as char;
+		if is_unreserved(ch) || (keep_slashes && ch == '/')
+		{
+			str::push_char(&mut result, ch);
+		}
+		else
+		{
+			result += fmt!("%%%02X", b as uint);
+		}
+	}
+	result
+}
+
+// ---- Parsing -------------------------------------------------------------
+priv fn tokenize(template: &str) -> result::Result<~[Part], ~str>
+{
+	let mut parts = ~[];
+	let mut text = ~"";
+	let mut i = 0u;
+	while i < str::len(template)
+	{
+		let ch = template[i] as char;
+		if ch == '{'
+		{
+			// Constraints may include braces (e.g. "{year:[0-9]{4}}") so we need to find the matching brace.
+			let mut depth = 1u;
+			let mut j = i + 1u;
+			while j < str::len(template) && depth > 0u
+			{
+				if template[j] as char == '{' {depth += 1u;}
+				else if template[j] as char == '}' {depth -= 1u;}
+				j += 1u;
+			}
+			if depth > 0u
+			{
+				return result::Err(~"missing }");
+			}
+			
+			if str::is_not_empty(text)
+			{
+				vec::push(&mut parts, Text(copy text));
+				text = ~"";
+			}
+			match parse_expression(template.slice(i + 1u, j - 1u))
+			{
+				result::Ok(move e) => vec::push(&mut parts, Expr(e)),
+				result::Err(move mesg) => return result::Err(mesg),
+			}
+			i = j;
+		}
+		else if ch == '}'
+		{
+			return result::Err(~"unbalanced }");
+		}
+		else
+		{
+			str::push_char(&mut text, ch);
+			i += 1u;
+		}
+	}
+	if str::is_not_empty(text)
+	{
+		vec::push(&mut parts, Text(text));
+	}
+	result::Ok(parts)
+}
+
+priv fn parse_expression(body: &str) -> result::Result<Expression, ~str>
+{
+	if str::is_empty(body)
+	{
+		return result::Err(~"empty expression");
+	}
+	
+	let first = body[0] as char;
+	let (op, body) = if str::contains_char("+#./;?&", first) {(first, body.slice(1u, body.len()))} else {('\0', body.to_unique())};
+	
+	let mut vars = ~[];
+	for vec::each(split_top_level(body))
+	|spec|
+	{
+		match parse_varspec(*spec)
+		{
+			result::Ok(move var) => vec::push(&mut vars, var),
+			result::Err(move mesg) => return result::Err(mesg),
+		}
+	}
+	result::Ok(Expression {op: op, vars: vars})
+}
+
+// Splits on commas which are not nested within brackets, braces, or parentheses (so that
+// commas can be used within regex constraints).
+priv fn split_top_level(body: &str) -> ~[~str]
+{
+	let mut result = ~[];
+	let mut current = ~"";
+	let mut depth = 0;
+	for str::each_char(body)
+	|ch|
+	{
+		match ch
+		{
+			'{' | '[' | '(' => {depth += 1; str::push_char(&mut current, ch);}
+			'}' | ']' | ')' => {depth -= 1; str::push_char(&mut current, ch);}
+			',' if depth == 0 => {vec::push(&mut result, copy current); current = ~"";}
+			_ => str::push_char(&mut current, ch),
+		}
+	}
+	vec::push(&mut result, current);
+	result
+}
+
+priv fn parse_varspec(spec: &str) -> result::Result<VarSpec, ~str>
+{
+	let (name, modifier) =
+		match str::find_char(spec, ':')
+		{
+			option::Some(i) => (spec.slice(0u, i), option::Some(spec.slice(i + 1u, spec.len()))),
+			option::None => (spec.to_unique(), option::None),
+		};
+	let (name, explode) = if modifier.is_none() && str::ends_with(name, "*") {(name.slice(0u, name.len() - 1u), true)} else {(name, false)};
+	
+	if str::is_empty(name) || !str::all(name, |c| {char::is_alphanumeric(c) || c == '_' || c == '.' || c == '-'})
+	{
+		return result::Err(fmt!("bad variable name '%s'", name));
+	}
+	
+	let (prefix, constraint) =
+		match modifier
+		{
+			option::None => (0u, AnyValue),
+			option::Some(~"int") => (0u, IntValue),
+			option::Some(~"uint") => (0u, UIntValue),
+			option::Some(~"float") => (0u, FloatValue),
+			option::Some(~"alpha") => (0u, AlphaValue),
+			option::Some(~"alnum") => (0u, AlnumValue),
+			option::Some(~"hex") => (0u, HexValue),
+			option::Some(ref m) if str::is_not_empty(*m) && str::all(*m, char::is_digit) =>
+			{
+				match uint::from_str(*m)
+				{
+					option::Some(n) if n > 0u && n < 10000u => (n, AnyValue),
+					_ => return result::Err(fmt!("bad prefix length for '%s'", name)),
+				}
+			}
+			option::Some(ref m) =>
+			{
+				match regex::compile_regex(*m)
+				{
+					result::Ok(move r) => (0u, RegexValue(r)),
+					result::Err(ref mesg) => return result::Err(fmt!("bad constraint for '%s': %s", name, *mesg)),
+				}
+			}
+		};
+	result::Ok(VarSpec {name: name, explode: explode, prefix: prefix, constraint: constraint})
+}
+
+priv fn path_components(parts: &[Part]) -> result::Result<~[Component], ~str>
+{
+	// Break the parts up into path segments.
+	let mut segments = ~[];
+	let mut current = ~[];
+	for vec::eachi(parts)
+	|i, part|
+	{
+		match *part
+		{
+			Text(ref text) =>
+			{
+				let pieces = str::split_char(*text, '/');
+				for vec::eachi(pieces)
+				|j, piece|
+				{
+					if j > 0u && vec::is_not_empty(current)
+					{
+						vec::push(&mut segments, move current);
+						current = ~[];
+					}
+					if str::is_not_empty(*piece)
+					{
+						vec::push(&mut current, Text(copy *piece));
+					}
+				}
+			}
+			Expr(ref e) if e.op == '/' =>
+			{
+				// Each variable in {/a,b} starts a new segment and {/path*} at the end is a trailer.
+				for vec::eachi(e.vars)
+				|j, var|
+				{
+					if vec::is_not_empty(current)
+					{
+						vec::push(&mut segments, move current);
+					}
+					if var.explode && (i + 1u < vec::len(parts) || j + 1u < vec::len(e.vars))
+					{
+						return result::Err(~"{/var*} must be at the end of the path");
+					}
+					current = ~[Expr(Expression {op: if var.explode {'*'} else {'\0'}, vars: ~[copy *var]})];
+				}
+			}
+			Expr(ref e) =>
+			{
+				vec::push(&mut current, Expr(copy *e));
+			}
+		}
+	}
+	if vec::is_not_empty(current)
+	{
+		vec::push(&mut segments, move current);
+	}
+	
+	let count = vec::len(segments);
+	result::Ok(do vec::mapi(segments)
+	|i, segment|
+	{
+		segment_component(*segment, i + 1u == count)
+	})
+}
+
+priv fn segment_component(segment: &[Part], is_last: bool) -> Component
+{
+	if vec::len(segment) == 1u
+	{
+		match segment[0]
+		{
+			Text(ref s) if is_last && str::starts_with(*s, "*") =>
+			{
+				return Trailer(s.slice(1u, s.len()));
+			}
+			Text(ref s) =>
+			{
+				return Literal(copy *s);
+			}
+			Expr(ref e) if vec::len(e.vars) == 1u && is_simple(&e.vars[0]) =>
+			{
+				if e.op == '\0'
+				{
+					return Variable(copy e.vars[0].name);
+				}
+				else if is_last && (e.op == '*' || e.op == '+')
+				{
+					return Trailer(copy e.vars[0].name);
+				}
+			}
+			_ =>
+			{
+			}
+		}
+	}
+	
+	// Any {/path*} or {+path} expressions which didn't become trailers are treated as simple expressions.
+	Pattern(do vec::map(segment)
+	|part|
+	{
+		match *part
+		{
+			Expr(ref e) if e.op == '*' => Expr(Expression {op: '\0', .. copy *e}),
+			_ => copy *part,
+		}
+	})
+}
+
+priv pure fn is_simple(var: &VarSpec) -> bool
+{
+	var.prefix == 0u && match var.constraint {AnyValue => true, _ => false}
+}
+
+// ---- Matching ------------------------------------------------------------
+
+// Returns true if parts matches segment starting at index i. Expressions are greedy so
+// "{name}.{ext}" will match "archive.tar.gz" with name "archive.tar" and ext "gz".
+priv fn match_parts(parts: &[Part], segment: &str, i: uint, values: &mut ~[(~str, ~str)]) -> bool
+{
+	if vec::is_empty(parts)
+	{
+		return i == str::len(segment);
+	}
+	
+	let rest = vec::view(parts, 1u, vec::len(parts));
+	match parts[0]
+	{
+		Text(ref text) =>
+		{
+			matches_at(segment, *text, i) && match_parts(rest, segment, i + str::len(*text), values)
+		}
+		Expr(ref e) =>
+		{
+			let mut end = str::len(segment);
+			loop
+			{
+				match decode_expression(e, segment.slice(i, end))
+				{
+					option::Some(move decoded) =>
+					{
+						let old_len = vec::len(*values);
+						*values += decoded;
+						if match_parts(rest, segment, end, values)
+						{
+							return true;
+						}
+						vec::truncate(values, old_len);
+					}
+					option::None =>
+					{
+					}
+				}
+				if end == i
+				{
+					return false;
+				}
+				end = str::char_range_at_reverse(segment, end).next;		// segments may contain multibyte characters
+			}
+		}
+	}
+}
+
+// Returns true if segment contains text at byte offset i.
+priv pure fn matches_at(segment: &str, text: &str, i: uint) -> bool
+{
+	let len = str::len(text);
+	if i + len > str::len(segment)
+	{
+		return false;
+	}
+	
+	let mut k = 0u;
+	while k < len
+	{
+		if segment[i + k] != text[k]
+		{
+			return false;
+		}
+		k += 1u;
+	}
+	true
+}
+
+// Returns the variable values for text matched by an expression or None if text doesn't match.
+priv fn decode_expression(e: &Expression, text: &str) -> Option<~[(~str, ~str)]>
+{
+	let (first, sep, named, _, _) = op_info(e.op);
+	if str::is_empty(text)
+	{
+		// Only the optional label and path parameter expressions can match nothing.
+		return if str::is_not_empty(first) {option::Some(~[])} else {option::None};
+	}
+	if !str::starts_with(text, first)
+	{
+		return option::None;
+	}
+	
+	let body = text.slice(str::len(first), text.len());
+	let items = if vec::len(e.vars) == 1u && !e.vars[0].explode && !named {~[copy body]} else {str::split_str(body, sep)};
+	
+	let mut values = ~[];
+	if named
+	{
+		for vec::each(items)
+		|item|
+		{
+			let (name, value) = match str::find_char(*item, '=') {option::Some(k) => (item.slice(0u, k), item.slice(k + 1u, item.len())), option::None => (copy *item, ~"")};
+			match vec::find(e.vars, |v| {v.name == name})
+			{
+				option::Some(ref var) if satisfies(var, value) => vec::push(&mut values, (name, value)),
+				_ => return option::None,
+			}
+		}
+	}
+	else
+	{
+		let n = vec::len(e.vars);
+		let exploded = e.vars[n - 1u].explode;
+		if vec::len(items) < n || (vec::len(items) > n && !exploded)
+		{
+			return option::None;
+		}
+		for vec::eachi(e.vars)
+		|k, var|
+		{
+			let value = if k + 1u == n {str::connect(vec::slice(items, k, vec::len(items)), sep)} else {copy items[k]};
+			if str::is_empty(value) || !satisfies(var, value)
+			{
+				return option::None;
+			}
+			vec::push(&mut values, (copy var.name, value));
+		}
+	}
+	option::Some(values)
+}
+
+priv fn satisfies(var: &VarSpec, value: &str) -> bool
+{
+	if var.prefix > 0u && str::char_len(value) > var.prefix
+	{
+		return false;
+	}
+	
+	match var.constraint
+	{
+		AnyValue => true,
+		IntValue => str::is_not_empty(value) && int::from_str(value).is_some(),
+		UIntValue => str::is_not_empty(value) && str::all(value, char::is_digit),
+		FloatValue => str::is_not_empty(value) && float::from_str(value).is_some(),
+		AlphaValue => str::is_not_empty(value) && str::all(value, char::is_alphabetic),
+		AlnumValue => str::is_not_empty(value) && str::all(value, char::is_alphanumeric),
+		HexValue => str::is_not_empty(value) && str::all(value, |c| {char::is_digit(c) || (c >= 'a' && c <= 'f') || (c >= 'A' && c <= 'F')}),
+		RegexValue(ref r) => r.matches(value),
+	}
+}
+
+// ---- Expansion -----------------------------------------------------------
+// Returns (first, separator, named, if-empty, allow-reserved) from the table in appendix A of RFC 6570.
+priv pure fn op_info(op: char) -> (~str, ~str, bool, ~str, bool)
+{
+	match op
+	{
+		'+' => (~"", ~",", false, ~"", true),
+		'#' => (~"#", ~",", false, ~"", true),
+		'.' => (~".", ~".", false, ~"", false),
+		'/' => (~"/", ~"/", false, ~"", false),
+		';' => (~";", ~";", true, ~"", false),
+		'?' => (~"?", ~"&", true, ~"=", false),
+		'&' => (~"&", ~"&", true, ~"=", false),
+		_ => (~"", ~",", false, ~"", false),
+	}
+}
+
+priv fn expand_expression(e: &Expression, vars: &[(~str, ~str)], missing: &mut ~[~str]) -> ~str
+{
+	let (first, sep, named, ifemp, allow_reserved) = op_info(e.op);
+	let required = e.op == '\0' || e.op == '+';
+	let encode = |value: &str| {if allow_reserved {encode_reserved(value)} else {percent_encode(value, false)}};
+	
+	let mut items = ~[];
+	for vec::each(e.vars)
+	|var|
+	{
+		let values = do vec::filter_map(vars) |v| {if v.first() == var.name {option::Some(v.second())} else {option::None}};
+		if vec::is_empty(values)
+		{
+			if required
+			{
+				vec::push(missing, copy var.name);
+				vec::push(&mut items, ~"{" + var.name + ~"}");
+			}
+		}
+		else if vec::len(values) == 1u && !var.explode
+		{
+			let value = if var.prefix > 0u {str::from_chars(vec::slice(str::chars(values[0]), 0u, uint::min(var.prefix, str::char_len(values[0]))))} else {copy values[0]};
+			let value = encode(value);
+			if named
+			{
+				vec::push(&mut items, if str::is_empty(value) {var.name + ifemp} else {var.name + ~"=" + value});
+			}
+			else
+			{
+				vec::push(&mut items, value);
+			}
+		}
+		else if var.explode
+		{
+			let values = do vec::map(values) |v| {if named {var.name + (if str::is_empty(*v) {copy ifemp} else {~"=" + encode(*v)})} else {encode(*v)}};
+			vec::push(&mut items, str::connect(values, sep));
+		}
+		else
+		{
+			let value = str::connect(vec::map(values, |v| {encode(*v)}), ~",");
+			vec::push(&mut items, if named {var.name + ~"=" + value} else {value});
+		}
+	}
+	
+	if vec::is_empty(items) {~""} else {first + str::connect(items, sep)}
+}
+
+// Encodes everything except the unreserved and reserved characters (and existing percent escapes).
+priv fn encode_reserved(text: &str) -> ~str
+{
+	let mut result = ~"";
+	let mut i = 0u;
+	while i < str::len(text)
+	{
+		let b = text[i];
+		let ch = b as char;
+		if is_unreserved(ch) || str::contains_char(":/?#[]@!$&'()*+,;=", ch)
+		{
+			str::push_char(&mut result, ch);
+		}
+		else if ch == '%' && i + 2u < str::len(text) && is_hex(text[i + 1u] as char) && is_hex(text[i + 2u] as char)
+		{
+			str::push_char(&mut result, ch);
+		}
+		else
+		{
+			result += fmt!("%%%02X", b as uint);
+		}
+		i += 1u;
+	}
+	result
+}
+
+priv pure fn is_unreserved(ch: char) -> bool
+{
+	(ch >= 'a' && ch <= 'z') || (ch >= 'A' && ch <= 'Z') || (ch >= '0' && ch <= '9') || ch == '-' || ch == '.' || ch == '_' || ch == '~'
+}
+
+priv pure fn is_hex(ch: char) -> bool
+{
+	(ch >= '0' && ch <= '9') || (ch >= 'a' && ch <= 'f') || (ch >= 'A' && ch <= 'F')
+}
+
 // ---- Unit Tests ------------------------------------------------------------
+#[cfg(test)]
+fn simple_var(name: ~str) -> VarSpec
+{
+	VarSpec {name: name, explode: false, prefix: 0u, constraint: AnyValue}
+}
+
 #[test]
 fn compile_literal()
 {
@@ -126,8 +874,8 @@ fn compile_variable()
 	//io::println(fmt!("%?", components));
 	
 	assert components[0] == Literal(~"foo");
-	assert components[1] == Literal(~"{ba}r");
-	assert components[2] == Literal(~"ba{z}");
+	assert components[1] == Pattern(~[Expr(Expression {op: '\0', vars: ~[simple_var(~"ba")]}), Text(~"r")]);
+	assert components[2] == Pattern(~[Text(~"ba"), Expr(Expression {op: '\0', vars: ~[simple_var(~"z")]})]);
 	assert vec::len(components) == 3u;
 }
 
@@ -250,3 +998,109 @@ fn match_empty_path()
 	
 	assert m.size() == 0u;
 }
+
+#[test]
+fn expand_templates()
+{
+	let components = compile(~"/blueprint/{site}/*path");
+	let (path, missing) = expand(components, ~[(~"site", ~"new york"), (~"path", ~"a/b&c")]);
+	assert utils::check_strs(path, ~"/blueprint/new%20york/a/b%26c");
+	assert vec::is_empty(missing);
+	
+	let (path, missing) = expand(components, ~[(~"path", ~"x")]);
+	assert utils::check_strs(path, ~"/blueprint/{site}/x");
+	assert utils::check_vectors(missing, ~[~"site"]);
+	
+	let (path, _) = expand(compile(~"/"), ~[]);
+	assert utils::check_strs(path, ~"/");
+}
+
+#[test]
+fn match_patterns()
+{
+	let components = compile(~"/files/{name}.{ext}");
+	let m = match_template(~"/files/archive.tar.gz", components);
+	assert m.get(@~"name") == @~"archive.tar";
+	assert m.get(@~"ext") == @~"gz";
+	assert match_template(~"/files/readme", components).size() == 0u;
+	
+	let m = match_template(~"/docs/intro/1", compile(~"/docs{/section,page}"));
+	assert m.get(@~"section") == @~"intro";
+	assert m.get(@~"page") == @~"1";
+	
+	let m = match_template(~"/static/css/site.css", compile(~"/static{/path*}"));
+	assert m.get(@~"path") == @~"css/site.css";
+	
+	let m = match_template(~"/map;x=10;y=20", compile(~"/map{;x,y}"));
+	assert m.get(@~"x") == @~"10";
+	assert m.get(@~"y") == @~"20";
+}
+
+#[test]
+fn match_non_ascii()
+{
+	let components = compile(~"/files/{name}.{ext}");
+	let m = match_template(~"/files/\u00e9t\u00e9.txt", components);
+	assert m.get(@~"name") == @~"\u00e9t\u00e9";
+	assert m.get(@~"ext") == @~"txt";
+	
+	let m = match_template(~"/files/r\u00e9sum\u00e9.\u00e9", components);
+	assert m.get(@~"ext") == @~"\u00e9";
+	assert match_template(~"/files/\u00e9\u00e9", components).size() == 0u;
+	
+	assert match_segment(&components[1], ~"\u00e9.txt");
+	assert !match_segment(&compile(~"/{id:int}")[0], ~"4\u00e9");
+}
+
+#[test]
+fn match_constraints()
+{
+	let components = compile(~"/users/{id:int}");
+	assert components[1] == Pattern(~[Expr(Expression {op: '\0', vars: ~[VarSpec {name: ~"id", explode: false, prefix: 0u, constraint: IntValue}]})]);
+	assert match_template(~"/users/-42", components).get(@~"id") == @~"-42";
+	assert match_template(~"/users/bob", components).size() == 0u;
+	
+	let components = compile(~"/archive/{year:[0-9]{4}}/{slug:[a-z0-9-]+}");
+	assert match_template(~"/archive/2012/hello-world", components).get(@~"year") == @~"2012";
+	assert match_template(~"/archive/12/hello-world", components).size() == 0u;
+	assert match_template(~"/archive/2012/Hello", components).size() == 0u;
+	
+	assert match_template(~"/color/ff00cc", compile(~"/color/{rgb:hex}")).size() > 0u;
+	assert match_template(~"/color/red", compile(~"/color/{rgb:hex}")).size() == 0u;
+	assert match_template(~"/code/abc", compile(~"/code/{c:2}")).size() == 0u;
+	
+	let components = compile(~"/search{?q,page:uint}");
+	let m = match_template(~"/search", components);
+	assert m.size() > 0u;
+	assert match_query(components, &~[(@~"q", @~"rust"), (@~"page", @~"2")], m);
+	assert m.get(@~"page") == @~"2";
+	assert !match_query(components, &~[(@~"page", @~"two")], match_template(~"/search", components));
+	
+	assert parse_template(~"/users/{id").is_err();
+	assert parse_template(~"/users/{id:[a-z}").is_err();
+	assert parse_template(~"/users{?q}/{id}").is_err();
+}
+
+#[test]
+fn expand_expressions()
+{
+	let vars = ~[(~"var", ~"value"), (~"hello", ~"Hello World!"), (~"path", ~"/foo/bar"), (~"x", ~"1024"), (~"y", ~"768"), (~"list", ~"red"), (~"list", ~"green")];
+	let check = |template: &str, expected: &str| {let (path, _) = expand(compile(template), vars); utils::check_strs(path, expected)};
+	
+	assert check(~"/files/{var}.{x}", ~"/files/value.1024");
+	assert check(~"/say/{hello}", ~"/say/Hello%20World%21");
+	assert check(~"/say/{+hello}/x", ~"/say/Hello%20World!/x");
+	assert check(~"/say/{var:3}", ~"/say/val");
+	assert check(~"/img{/var,x}{.y}", ~"/img/value/1024.768");
+	assert check(~"/map{;x,y}", ~"/map;x=1024;y=768");
+	assert check(~"/search{?x,y,missing}", ~"/search?x=1024&y=768");
+	assert check(~"/search{?x}{&list*}", ~"/search?x=1024&list=red&list=green");
+	assert check(~"/colors{?list}", ~"/colors?list=red,green");
+	assert check(~"/page{#path}", ~"/page#/foo/bar");
+	assert check(~"{?x}", ~"/?x=1024");
+	
+	let (path, missing) = expand(compile(~"/files/{name}.{ext}{?page}"), ~[(~"ext", ~"txt")]);
+	assert utils::check_strs(path, ~"/files/{name}.txt");
+	assert utils::check_vectors(missing, ~[~"name"]);
+	assert utils::check_vectors(variable_names(compile(~"/files/{name}.{ext}{?page}")), ~[~"name", ~"ext", ~"page"]);
+}
@@ -0,0 +1,98 @@
+//! Generation of URLs from route names.
+
+/// Returns the path for the named route with its template variables replaced by values from vars,
+/// e.g. url_for(config, "greeting", ~[(~"name", ~"Bob Smith")]) might return "/hello/Bob%20Smith".
+///
+/// Values are percent encoded (but slashes in values for trailer components like "*path" are retained).
+/// Entries in vars which are not template variables are appended as query parameters. An error is
+/// returned if there is no such route or if vars does not have a value for every template variable.
+pub fn url_for(config: &connection::ConnConfig, route_name: &str, vars: &[(~str, ~str)]) -> result::Result<~str, ~str>
+{
+	match vec::find(config.route_list, |r| {str::eq_slice(r.route, route_name)})
+	{
+		option::Some(ref route) =>
+		{
+			let (path, missing) = uri_template::expand(route.template, vars);
+			if vec::is_not_empty(missing)
+			{
+				return result::Err(fmt!("route '%s' needs a value for %s", route_name, str::connect(missing, ~", ")));
+			}
+			
+			let names = uri_template::variable_names(route.template);
+			let query = do vec::filter_map(vars)
+			|var|
+			{
+				match *var
+				{
+					(ref name, ref value) if !vec::contains(names, name) =>
+					{
+						option::Some(uri_template::percent_encode(*name, false) + ~"=" + uri_template::percent_encode(*value, false))
+					}
+					_ =>
+					{
+						option::None
+					}
+				}
+			};
+			
+			if vec::is_empty(query)
+			{
+				result::Ok(path)
+			}
+			else
+			{
+				// The template may already have a query (e.g. "/search{?q}") or a fragment.
+				let (path, fragment) = match str::find_char(path, '#') {option::Some(i) => (path.slice(0u, i), path.slice(i, path.len())), option::None => (copy path, ~"")};
+				let sep = if str::contains_char(path, '?') {~"&"} else {~"?"};
+				result::Ok(path + sep + str::connect(query, ~"&") + fragment)
+			}
+		}
+		option::None =>
+		{
+			result::Err(fmt!("there is no route named '%s'", route_name))
+		}
+	}
+}
+
+/// Returns a map from route names to URLs for use within templates, e.g. {{#urls}}{{greeting}}{{/urls}}.
+///
+/// Template variables are filled in using the request's matches so this includes every route without
+/// variables and routes whose variables were all matched by the current request.
+pub fn route_urls(config: &connection::ConnConfig, request: &Request) -> mustache::Data
+{
+	let mut vars = ~[];
+	for request.matches.each |key, value| {vec::push(&mut vars, (copy *key, copy *value))};
+	
+	let urls = std::map::HashMap();
+	for vec::each(config.route_list)
+	|route|
+	{
+		let (path, missing) = uri_template::expand(route.template, vars);
+		if vec::is_empty(missing) && !urls.contains_key(@copy route.route)
+		{
+			urls.insert(@copy route.route, mustache::Str(@path));
+		}
+	}
+	mustache::Map(urls)
+}
+
+#[test]
+fn generated_urls()
+{
+	let config = Config {
+		server_info: ~"unit test",
+		resources_root: path::from_str(~"server/html"),
+		routes: ~[(~"GET", ~"/", ~"home"), (~"GET", ~"/hello/{name}", ~"greeting"), (~"GET", ~"/files/*path", ~"files"), (~"GET", ~"/search{?q}", ~"search")],
+		..initialize_config()};
+	
+	let eport = comm::Port();
+	let ech = comm::Chan(&eport);
+	let iconfig = connection::config_to_conn(&config, ech);
+	
+	assert url_for(&iconfig, ~"home", ~[]) == result::Ok(~"/");
+	assert url_for(&iconfig, ~"greeting", ~[(~"name", ~"Bob Smith")]) == result::Ok(~"/hello/Bob%20Smith");
+	assert url_for(&iconfig, ~"files", ~[(~"path", ~"docs/a b.txt"), (~"page", ~"2"), (~"q", ~"x&y")]) == result::Ok(~"/files/docs/a%20b.txt?page=2&q=x%26y");
+	assert url_for(&iconfig, ~"search", ~[(~"q", ~"rust"), (~"page", ~"2")]) == result::Ok(~"/search?q=rust&page=2");
+	assert url_for(&iconfig, ~"greeting", ~[]).is_err();
+	assert url_for(&iconfig, ~"missing", ~[]).is_err();
+}